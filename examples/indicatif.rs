@@ -1,5 +1,5 @@
 
-use std::{path::PathBuf, env};
+use std::env;
 use aws_sdk_s3::Client;
 use indicatif::ProgressBar;
 use trackable_s3_stream::TrackableBodyStream;
@@ -11,14 +11,14 @@ async fn main() {
     if args.len() < 2 {
         panic!("The first argment must be the target S3 bucket name");
     }
-    let mut body = TrackableBodyStream::try_from(PathBuf::from("./examples/sample.jpeg")).map_err(|e| {
+    let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await.map_err(|e| {
         panic!("Could not open sample file: {}", e);
     }).unwrap();
 
-    let bar = ProgressBar::new(body.content_length() as u64);
+    let bar = ProgressBar::new(body.content_length().unwrap_or(0) as u64);
     
     body.set_callback(move |tot_size: u64, sent: u64, cur_buf: u64| {
-        bar.inc(cur_buf as u64);
+        bar.inc(cur_buf);
         if sent == tot_size {
             bar.finish();
         }
@@ -31,7 +31,7 @@ async fn main() {
     match s3_client.put_object()
                     .bucket(bucket)
                     .key("tracked_sample.jpeg")
-                    .content_length(body.content_length())
+                    .set_content_length(body.content_length())
                     .body(body.to_s3_stream())
                     .send().await {
                         Ok(_) => {
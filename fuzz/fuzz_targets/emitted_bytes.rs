@@ -0,0 +1,107 @@
+//! Checks that, whatever the combination of settings, the bytes emitted by a
+//! `TrackableBodyStream` are exactly the bytes of its source and that the progress callback
+//! reports the whole source as read.
+//!
+//! The first bytes of the input select the settings, the rest is the source:
+//! * bytes 0-1: buffer size, 1 to 8192
+//! * byte 2: minimum final frame, 0 disables it
+//! * byte 3: read ahead, 0 to 7 chunks
+//! * byte 4: flags, bit 0 base64, bit 1 `from_chunks` source, bit 2 `prime`, bit 3 end of
+//!   stream marker
+//! * byte 5: number of chunks the source is split into for `from_chunks`
+#![no_main]
+
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::StreamExt;
+use libfuzzer_sys::fuzz_target;
+use trackable_s3_stream::{BodySource, Bytes, TrackableBodyStream};
+
+const CONFIG_LEN: usize = 6;
+
+struct Config {
+    buffer_size: usize,
+    min_final_frame: usize,
+    read_ahead: usize,
+    base64: bool,
+    chunks: Option<usize>,
+    prime: bool,
+    eof_marker: bool,
+}
+
+impl Config {
+    fn parse(config: &[u8]) -> Self {
+        let flags = config[4];
+        Config {
+            buffer_size: 1 + u16::from_le_bytes([config[0], config[1]]) as usize % 8192,
+            min_final_frame: config[2] as usize,
+            read_ahead: (config[3] % 8) as usize,
+            base64: flags & 1 != 0,
+            chunks: (flags & 2 != 0).then_some(1 + config[5] as usize),
+            prime: flags & 4 != 0,
+            eof_marker: flags & 8 != 0,
+        }
+    }
+}
+
+async fn check<I: BodySource>(mut body: TrackableBodyStream<I>, config: &Config, source: &[u8]) {
+    let sent = Arc::new(AtomicU64::new(0));
+    let counter = sent.clone();
+    body.set_callback(move |total, sent, _| {
+        assert!(sent <= total);
+        counter.store(sent, Ordering::SeqCst);
+    });
+    body.set_buffer_size(config.buffer_size);
+    body.set_min_final_frame(config.min_final_frame);
+    body.set_read_ahead(config.read_ahead);
+    body.set_emit_eof_marker(config.eof_marker);
+    if config.base64 {
+        body = body.with_base64();
+    }
+    let expected_length = body.content_length().expect("in-memory sources have a known length") as usize;
+    if config.prime {
+        body.prime().await.expect("priming never fails for in-memory sources");
+    }
+
+    let mut emitted = Vec::new();
+    let mut marker_seen = false;
+    while let Some(frame) = body.next().await {
+        let frame = frame.expect("in-memory sources never fail");
+        assert!(!marker_seen, "the end of stream marker must be the last frame");
+        if frame.is_empty() {
+            assert!(config.eof_marker, "empty frames are only emitted as end of stream markers");
+            marker_seen = true;
+        }
+        emitted.extend_from_slice(&frame);
+    }
+    assert_eq!(marker_seen, config.eof_marker);
+    assert!(body.next().await.is_none(), "a completed stream stays completed");
+
+    assert_eq!(emitted.len(), expected_length);
+    if config.base64 {
+        assert_eq!(STANDARD.decode(&emitted).expect("valid base64"), source);
+    } else {
+        assert_eq!(emitted, source);
+    }
+    assert_eq!(sent.load(Ordering::SeqCst), source.len() as u64);
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < CONFIG_LEN {
+        return;
+    }
+    let (config, source) = data.split_at(CONFIG_LEN);
+    let config = Config::parse(config);
+
+    futures::executor::block_on(async {
+        match config.chunks {
+            Some(count) => {
+                let step = source.len().div_ceil(count).max(1);
+                let chunks: Vec<_> = source.chunks(step).map(Bytes::copy_from_slice).collect();
+                check(TrackableBodyStream::from_chunks(chunks), &config, source).await
+            }
+            None => check(TrackableBodyStream::from(source), &config, source).await,
+        }
+    });
+});
@@ -0,0 +1,106 @@
+use std::{pin::Pin, sync::Mutex, task::{Context, Poll}};
+
+use aws_smithy_http::body::SdkBody;
+use futures::Stream;
+use http_body::{combinators::BoxBody, Body, SizeHint};
+
+use crate::{BodySource, Bytes, TrackableBodyStream, TrackableStreamError};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// An `http_body::Body` over a stream of chunks, so that the SDK body can be built from a stream
+/// without going through `hyper::Body`
+struct StreamBody<S> {
+    /// Boxed bodies must be `Sync`, the stream is only ever polled through `get_mut`
+    stream: Mutex<S>,
+    /// The exact length of the body, if it is known and should be reported in the size hint
+    len: Option<u64>,
+}
+
+impl<S, E> Body for StreamBody<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Data = Bytes;
+    type Error = E;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let stream = self.get_mut().stream.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Pin::new(stream).poll_next(cx)
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.len.map(SizeHint::with_exact).unwrap_or_default()
+    }
+}
+
+/// Builds the body of an SDK request from a stream of chunks. Errors of the stream are handed to
+/// the SDK as they are, not wrapped in a `hyper::Error`.
+pub(crate) fn sdk_body<S, E>(stream: S) -> SdkBody
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin + Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    let body = StreamBody { stream: Mutex::new(stream), len: None }.map_err(Into::into);
+    SdkBody::from_dyn(aws_smithy_http::body::BoxBody::new(body))
+}
+
+impl<I: BodySource + Send + 'static> TrackableBodyStream<I> {
+    /// Consumes this body stream and returns it as a boxed `http_body::Body`, for HTTP clients
+    /// and SDKs other than the AWS SDK that accept a streaming body, for example to upload to
+    /// other object stores through their REST APIs. The size hint of the body is the
+    /// `encoded_content_length` when it is known. The end of stream marker of
+    /// `set_emit_eof_marker` is turned off.
+    ///
+    /// # Examples
+    /// ```
+    /// # use http_body::Body;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data: &'static [u8] = &[1u8; 5000];
+    /// let mut body = TrackableBodyStream::from(data);
+    /// body.set_callback(|total, sent, _| println!("{}/{} bytes", sent, total));
+    /// let mut body = body.to_http_body();
+    /// assert_eq!(body.size_hint().exact(), Some(5000));
+    ///
+    /// let mut sent = 0;
+    /// while let Some(chunk) = body.data().await {
+    ///     sent += chunk.unwrap().len();
+    /// }
+    /// assert_eq!(sent, 5000);
+    /// # });
+    /// ```
+    pub fn to_http_body(mut self) -> BoxBody<Bytes, TrackableStreamError> {
+        self.emit_eof_marker = false;
+        let len = self.encoded_content_length();
+        BoxBody::new(StreamBody { stream: Mutex::new(self), len })
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl<I: crate::BodySource + Send + 'static> crate::TrackableBodyStream<I> {
+    /// Consumes this body stream and returns a `hyper::Body`, for requests sent with a `hyper`
+    /// client rather than the SDK. The end of stream marker of `set_emit_eof_marker` is turned
+    /// off. Available with the `hyper` feature.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), hyper::Error> {
+    /// let body = TrackableBodyStream::from_chunks(vec!["hello ".into(), "world".into()])
+    ///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+    ///     .to_hyper_body();
+    /// assert_eq!(hyper::body::to_bytes(body).await?, "hello world");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_hyper_body(mut self) -> hyper::Body {
+        self.emit_eof_marker = false;
+        hyper::Body::wrap_stream(self)
+    }
+}
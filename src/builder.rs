@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use tokio::fs::File;
+
+use crate::{CallbackMutFn, ChecksumAlgorithm, Throttle, TrackableBodyStream};
+
+/// Configures a `TrackableBodyStream` over a file in one expression, created with
+/// `TrackableBodyStream::builder`. Every option is applied with the setter of the same name
+/// once the file has been opened, the setters remain available on the stream.
+///
+/// # Examples
+/// ```
+/// # use futures::TryStreamExt;
+/// # use trackable_s3_stream::{ChecksumAlgorithm, Throttle, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let body = TrackableBodyStream::builder()
+///     .source("./examples/sample.jpeg")
+///     .buffer_size(16 * 1024)
+///     .callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+///     .throttle(Throttle::new(100 * 1024 * 1024))
+///     .checksum_algorithm(ChecksumAlgorithm::Sha256)
+///     .build()
+///     .await?;
+/// assert_eq!(body.buffer_size(), 16 * 1024);
+///
+/// let frames: Vec<_> = body.try_collect().await?;
+/// assert_eq!(frames.concat(), std::fs::read("./examples/sample.jpeg")?);
+///
+/// assert!(TrackableBodyStream::builder().build().await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct TrackableBodyStreamBuilder {
+    source: Option<PathBuf>,
+    buffer_size: Option<usize>,
+    callback: Option<Box<CallbackMutFn>>,
+    throttle: Option<Throttle>,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+}
+
+impl TrackableBodyStreamBuilder {
+    /// Sets the path of the file to stream, required by `build`
+    pub fn source(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source = Some(path.into());
+        self
+    }
+
+    /// Sets the size of the chunks read from the file, see `set_buffer_size`. Panics if
+    /// `buffer_size` is zero.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0, "the buffer size must be greater than zero");
+        self.buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// Sets the progress callback, see `set_callback`
+    pub fn callback(mut self, callback: impl FnMut(u64, u64, u64) + Send + 'static) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Limits the rate at which the stream emits bytes, see `set_throttle`
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Computes a checksum of the file as it is streamed, see `set_checksum_algorithm`
+    pub fn checksum_algorithm(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.checksum_algorithm = Some(algorithm);
+        self
+    }
+
+    /// Opens the file, without blocking the runtime, and returns the configured stream.
+    /// Returns an `InvalidInput` error if no source has been set.
+    pub async fn build(self) -> std::io::Result<TrackableBodyStream<File>> {
+        let path = self.source.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "the builder has no source")
+        })?;
+        let mut stream = TrackableBodyStream::from_path(path).await?;
+        if let Some(buffer_size) = self.buffer_size {
+            stream.set_buffer_size(buffer_size);
+        }
+        if let Some(callback) = self.callback {
+            stream.set_callback(callback);
+        }
+        if let Some(throttle) = self.throttle {
+            stream.set_throttle(throttle);
+        }
+        if let Some(algorithm) = self.checksum_algorithm {
+            stream.set_checksum_algorithm(algorithm);
+        }
+        Ok(stream)
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt, io, path::{Path, PathBuf}, str::FromStr};
+
+use aws_sdk_s3::model::CompletedPart;
+
+/// The first line of every checkpoint file, followed by the version of the format
+const HEADER: &str = "trackable-s3-stream checkpoint 1";
+
+/// The state of a multipart upload started by a `MultipartUploader` with a checkpoint file,
+/// enough to resume the upload after the process was interrupted.
+///
+/// The checkpoint is saved as a small text file, one field per line, and rewritten each time
+/// S3 confirms a part. It is written to a temporary file first and then renamed, so that a
+/// crash while saving leaves the previous checkpoint in place.
+///
+/// # Examples
+/// ```
+/// # use trackable_s3_stream::{CheckpointPart, MultipartCheckpoint};
+/// let mut checkpoint = MultipartCheckpoint::new("upload-id", "bucket", "backup.tar", 12, 5);
+/// checkpoint.parts.push(CheckpointPart { number: 1, offset: 0, len: 5, e_tag: "\"etag\"".to_string() });
+///
+/// let saved = checkpoint.to_string();
+/// let loaded: MultipartCheckpoint = saved.parse().unwrap();
+/// assert_eq!(loaded, checkpoint);
+/// assert_eq!(loaded.completed_bytes(), 5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartCheckpoint {
+    /// The id returned by `create_multipart_upload`
+    pub upload_id: String,
+    /// The bucket the object is uploaded to
+    pub bucket: String,
+    /// The key of the object
+    pub key: String,
+    /// The size of the file being uploaded
+    pub file_size: u64,
+    /// The size of the parts, the last part may be smaller
+    pub part_size: u64,
+    /// The parts S3 has confirmed so far, in the order they completed
+    pub parts: Vec<CheckpointPart>,
+}
+
+/// A part of a multipart upload that S3 has confirmed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointPart {
+    /// The number of the part, starting at 1
+    pub number: i32,
+    /// The offset of the part in the file
+    pub offset: u64,
+    /// The size of the part
+    pub len: u64,
+    /// The ETag S3 returned for the part, needed to complete the upload
+    pub e_tag: String,
+}
+
+impl MultipartCheckpoint {
+    /// Creates the checkpoint of an upload with no confirmed parts yet
+    pub fn new(
+        upload_id: impl Into<String>,
+        bucket: impl Into<String>,
+        key: impl Into<String>,
+        file_size: u64,
+        part_size: u64,
+    ) -> Self {
+        Self {
+            upload_id: upload_id.into(),
+            bucket: bucket.into(),
+            key: key.into(),
+            file_size,
+            part_size,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Reads a checkpoint from `path`
+    pub async fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        tokio::fs::read_to_string(path).await?.parse()
+    }
+
+    /// Writes the checkpoint to `path`, replacing the previous checkpoint in one step
+    pub async fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let temp = temp_path(path);
+        tokio::fs::write(&temp, self.to_string()).await?;
+        tokio::fs::rename(&temp, path).await
+    }
+
+    /// Returns the number of bytes of the file covered by the confirmed parts
+    pub fn completed_bytes(&self) -> u64 {
+        self.parts.iter().map(|part| part.len).sum()
+    }
+
+    /// Returns whether part `number` has already been confirmed
+    pub fn is_completed(&self, number: i32) -> bool {
+        self.parts.iter().any(|part| part.number == number)
+    }
+
+    /// Returns the confirmed parts in the form `complete_multipart_upload` expects
+    pub(crate) fn completed_parts(&self) -> Vec<CompletedPart> {
+        self.parts
+            .iter()
+            .map(|part| CompletedPart::builder().e_tag(&part.e_tag).part_number(part.number).build())
+            .collect()
+    }
+}
+
+impl fmt::Display for MultipartCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "upload_id {}", self.upload_id)?;
+        writeln!(f, "bucket {}", self.bucket)?;
+        writeln!(f, "key {}", self.key)?;
+        writeln!(f, "file_size {}", self.file_size)?;
+        writeln!(f, "part_size {}", self.part_size)?;
+        for part in &self.parts {
+            writeln!(f, "part {} {} {} {}", part.number, part.offset, part.len, part.e_tag)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MultipartCheckpoint {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let mut checkpoint = MultipartCheckpoint::new("", "", "", 0, 0);
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (field, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            match field {
+                "upload_id" => checkpoint.upload_id = value.to_string(),
+                "bucket" => checkpoint.bucket = value.to_string(),
+                "key" => checkpoint.key = value.to_string(),
+                "file_size" => checkpoint.file_size = value.parse().map_err(|_| invalid(line))?,
+                "part_size" => checkpoint.part_size = value.parse().map_err(|_| invalid(line))?,
+                "part" => checkpoint.parts.push(parse_part(value).ok_or_else(|| invalid(line))?),
+                _ => return Err(invalid(line)),
+            }
+        }
+        if checkpoint.upload_id.is_empty() || checkpoint.part_size == 0 {
+            return Err(invalid("incomplete checkpoint"));
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn parse_part(value: &str) -> Option<CheckpointPart> {
+    let mut fields = value.splitn(4, ' ');
+    Some(CheckpointPart {
+        number: fields.next()?.parse().ok()?,
+        offset: fields.next()?.parse().ok()?,
+        len: fields.next()?.parse().ok()?,
+        e_tag: fields.next()?.to_string(),
+    })
+}
+
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid checkpoint: {}", line))
+}
+
+/// The file a checkpoint is written to before it replaces the one at `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
@@ -0,0 +1,161 @@
+use aws_sdk_s3::{model, output::PutObjectOutput};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// The checksum algorithms a `TrackableBodyStream` can compute over the bytes of its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// SHA-256, the same digest S3 uses for the `x-amz-checksum-sha256` header
+    Sha256,
+    /// CRC32C, the same checksum S3 uses for the `x-amz-checksum-crc32c` header. Unlike a
+    /// cryptographic digest its intermediate values are meaningful: the value after each chunk
+    /// is the CRC32C of all the bytes read so far.
+    Crc32c,
+    /// MD5, the digest S3 expects in the `Content-MD5` header to check the integrity of an upload
+    Md5,
+    /// CRC32, the same checksum S3 uses for the `x-amz-checksum-crc32` header. Like CRC32C its
+    /// intermediate values are meaningful.
+    Crc32,
+    /// SHA-1, the same digest S3 uses for the `x-amz-checksum-sha1` header
+    Sha1,
+}
+
+impl ChecksumAlgorithm {
+    /// Returns the matching S3 additional checksum algorithm, to pass to the `checksum_algorithm`
+    /// of a request, or `None` for `Md5` which S3 only supports through `Content-MD5`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::ChecksumAlgorithm;
+    /// use aws_sdk_s3::model;
+    ///
+    /// assert_eq!(ChecksumAlgorithm::Crc32c.s3_algorithm(), Some(model::ChecksumAlgorithm::Crc32C));
+    /// assert_eq!(ChecksumAlgorithm::Md5.s3_algorithm(), None);
+    /// ```
+    pub fn s3_algorithm(self) -> Option<model::ChecksumAlgorithm> {
+        match self {
+            ChecksumAlgorithm::Sha256 => Some(model::ChecksumAlgorithm::Sha256),
+            ChecksumAlgorithm::Crc32c => Some(model::ChecksumAlgorithm::Crc32C),
+            ChecksumAlgorithm::Crc32 => Some(model::ChecksumAlgorithm::Crc32),
+            ChecksumAlgorithm::Sha1 => Some(model::ChecksumAlgorithm::Sha1),
+            ChecksumAlgorithm::Md5 => None,
+        }
+    }
+
+    /// Compares `expected`, a checksum computed with this algorithm while streaming the source,
+    /// with the one S3 reports for the new object, without a second pass over the source.
+    /// Returns an `InvalidData` error if they differ or if S3 did not report a checksum: it only
+    /// reports one computed with the `checksum_algorithm` of the request.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::{ChecksumAlgorithm, TrackableBodyStream};
+    /// # async fn upload(client: aws_sdk_s3::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// let algorithm = ChecksumAlgorithm::Crc32c;
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// let checksum = body.share_checksum(algorithm);
+    /// let output = client
+    ///     .put_object()
+    ///     .bucket("bucket")
+    ///     .key("sample.jpeg")
+    ///     .set_checksum_algorithm(algorithm.s3_algorithm())
+    ///     .set_content_length(body.content_length())
+    ///     .body(body.to_s3_stream())
+    ///     .send()
+    ///     .await?;
+    /// let checksum = checksum.lock().unwrap().clone().unwrap_or_default();
+    /// algorithm.verify(&checksum, &output)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn verify(self, expected: &str, output: &PutObjectOutput) -> std::io::Result<()> {
+        match self.reported_by(output) {
+            Some(reported) if reported == expected => Ok(()),
+            Some(reported) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("S3 reported a {:?} checksum of {}, expected {}", self, reported, expected),
+            )),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("S3 did not report a {:?} checksum", self),
+            )),
+        }
+    }
+
+    /// Returns the checksum computed with this algorithm that S3 reported for an upload
+    fn reported_by(self, output: &PutObjectOutput) -> Option<&str> {
+        match self {
+            ChecksumAlgorithm::Sha256 => output.checksum_sha256(),
+            ChecksumAlgorithm::Crc32c => output.checksum_crc32_c(),
+            ChecksumAlgorithm::Crc32 => output.checksum_crc32(),
+            ChecksumAlgorithm::Sha1 => output.checksum_sha1(),
+            ChecksumAlgorithm::Md5 => None,
+        }
+    }
+}
+
+/// Incremental state of a checksum computation, fed one chunk at a time.
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Crc32c(u32),
+    Md5(Md5),
+    Crc32(crc32fast::Hasher),
+    Sha1(Sha1),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Crc32c => Hasher::Crc32c(0),
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            ChecksumAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+        }
+    }
+
+    pub(crate) fn algorithm(&self) -> ChecksumAlgorithm {
+        match self {
+            Hasher::Sha256(_) => ChecksumAlgorithm::Sha256,
+            Hasher::Crc32c(_) => ChecksumAlgorithm::Crc32c,
+            Hasher::Md5(_) => ChecksumAlgorithm::Md5,
+            Hasher::Crc32(_) => ChecksumAlgorithm::Crc32,
+            Hasher::Sha1(_) => ChecksumAlgorithm::Sha1,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Consumes the hasher and returns the base64 encoded digest, the format S3 expects in
+    /// its checksum headers.
+    pub(crate) fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => STANDARD.encode(hasher.finalize()),
+            Hasher::Crc32c(crc) => STANDARD.encode(crc.to_be_bytes()),
+            Hasher::Md5(hasher) => STANDARD.encode(hasher.finalize()),
+            Hasher::Crc32(hasher) => STANDARD.encode(hasher.finalize().to_be_bytes()),
+            Hasher::Sha1(hasher) => STANDARD.encode(hasher.finalize()),
+        }
+    }
+
+    /// Returns the base64 encoded checksum of the bytes fed so far, without consuming the hasher
+    pub(crate) fn current(&self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => STANDARD.encode(hasher.clone().finalize()),
+            Hasher::Crc32c(crc) => STANDARD.encode(crc.to_be_bytes()),
+            Hasher::Md5(hasher) => STANDARD.encode(hasher.clone().finalize()),
+            Hasher::Crc32(hasher) => STANDARD.encode(hasher.clone().finalize().to_be_bytes()),
+            Hasher::Sha1(hasher) => STANDARD.encode(hasher.clone().finalize()),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use aws_sdk_s3::{model::{CompletedMultipartUpload, CompletedPart}, output::CompleteMultipartUploadOutput, Client};
+use futures::{StreamExt, TryStreamExt};
+
+use crate::{multipart::AggregateProgress, TransferError, MAX_PARTS};
+
+/// The size of the ranges copied by `copy_object_tracked`, raised for objects that would need
+/// more than `MAX_PARTS` ranges
+const COPY_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How many ranges `copy_object_tracked` copies at the same time
+const COPY_CONCURRENCY: usize = 4;
+
+/// How many times `copy_object_tracked` retries a range that failed to copy
+const COPY_RETRIES: usize = 3;
+
+/// Copies `source_bucket`/`source_key` to `bucket`/`key` within S3, with progress. A plain
+/// `copy_object` request gives no progress signal and is limited to objects of 5 GB, this helper
+/// runs a multipart upload instead, whose parts are copied from ranges of the source with
+/// `upload_part_copy`, four at a time. No data goes through the client.
+///
+/// The callback receives the same arguments as the `TrackableBodyStream` callback, with the size
+/// of the source as the total; it is called every time a range has been copied. Each range is
+/// conditional on the ETag of the source, so a source overwritten during the copy fails the
+/// copy instead of mixing versions. A range that fails is retried up to three times, if it still
+/// fails the multipart upload is aborted.
+///
+/// Like `copy_object`, the copy keeps the content type, encoding, language and disposition,
+/// the cache control, the expiry and the user metadata of the source. Tags, the storage class
+/// and the encryption settings are not copied.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::copy_object_tracked;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+///
+/// copy_object_tracked(&client, "source-bucket", "backup.tar", "bucket", "backup.tar", |total, copied, _| {
+///     println!("{}/{} bytes", copied, total)
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn copy_object_tracked(
+    client: &Client,
+    source_bucket: &str,
+    source_key: &str,
+    bucket: &str,
+    key: &str,
+    callback: impl Fn(u64, u64, u64) + Sync + Send + 'static,
+) -> Result<CompleteMultipartUploadOutput, TransferError> {
+    let head = client
+        .head_object()
+        .bucket(source_bucket)
+        .key(source_key)
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("HeadObject", err))?;
+    let total = head.content_length().max(0) as u64;
+    let e_tag = head.e_tag().map(str::to_string);
+    let copy_source = format!("{}/{}", source_bucket, encode_key(source_key));
+
+    let created = client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        // the attributes a plain `copy_object` keeps
+        .set_content_type(head.content_type().map(str::to_string))
+        .set_content_encoding(head.content_encoding().map(str::to_string))
+        .set_content_language(head.content_language().map(str::to_string))
+        .set_content_disposition(head.content_disposition().map(str::to_string))
+        .set_cache_control(head.cache_control().map(str::to_string))
+        .set_expires(head.expires().copied())
+        .set_metadata(head.metadata().cloned())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("CreateMultipartUpload", err))?;
+    let upload_id = created.upload_id().unwrap_or_default();
+
+    let part_size = COPY_PART_SIZE.max(total.div_ceil(MAX_PARTS));
+    let count = total.div_ceil(part_size).max(1);
+    let progress = Arc::new(AggregateProgress::new(total, 0));
+    let copies = futures::stream::iter(0..count)
+        .map(|index| {
+            let offset = index * part_size;
+            // a single part copies the whole source, which also covers empty sources
+            let range = (count > 1).then(|| (offset, part_size.min(total - offset)));
+            let request = client
+                .upload_part_copy()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(index as i32 + 1)
+                .copy_source(&copy_source)
+                .set_copy_source_if_match(e_tag.clone())
+                .set_copy_source_range(range.map(|(offset, len)| format!("bytes={}-{}", offset, offset + len - 1)));
+            let (progress, callback) = (progress.clone(), &callback);
+            async move {
+                let mut attempt = 0;
+                let output = loop {
+                    match request.clone().send().await {
+                        Ok(output) => break output,
+                        Err(err) if attempt < COPY_RETRIES => {
+                            attempt += 1;
+                            log::warn!("Retrying copy of part {} after error: {}", index + 1, err);
+                        }
+                        Err(err) => return Err(TransferError::s3("UploadPartCopy", err)),
+                    }
+                };
+                let len = range.map_or(total, |(_, len)| len);
+                progress.add(len, |copied, increase| callback(progress.total(), copied, increase));
+                let e_tag = output.copy_part_result().and_then(|result| result.e_tag()).unwrap_or_default();
+                Ok(CompletedPart::builder().part_number(index as i32 + 1).e_tag(e_tag).build())
+            }
+        })
+        .buffer_unordered(COPY_CONCURRENCY)
+        .try_collect::<Vec<_>>()
+        .await;
+    let mut completed = match copies {
+        Ok(completed) => completed,
+        Err(err) => {
+            // the error of the part matters more than a failure to clean up after it
+            let _ = client.abort_multipart_upload().bucket(bucket).key(key).upload_id(upload_id).send().await;
+            return Err(err);
+        }
+    };
+    completed.sort_by_key(|part| part.part_number());
+
+    client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(completed)).build())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("CompleteMultipartUpload", err))
+}
+
+/// URL-encodes a key for the `x-amz-copy-source` header, keeping the `/` separators
+fn encode_key(key: &str) -> String {
+    key.bytes().fold(String::with_capacity(key.len()), |mut encoded, byte| {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+        encoded
+    })
+}
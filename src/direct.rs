@@ -0,0 +1,186 @@
+use std::{
+    alloc::{self, Layout},
+    fs::OpenOptions,
+    io::Read,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::Future;
+use tokio::{io::{AsyncRead, ReadBuf}, task::JoinHandle};
+
+use crate::TrackableBodyStream;
+
+/// A heap buffer whose start address is aligned to a given boundary, as required by `O_DIRECT`.
+struct AlignedBuffer {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+// the buffer owns its allocation exclusively, like a `Box<[u8]>`
+unsafe impl Send for AlignedBuffer {}
+
+impl AlignedBuffer {
+    fn new(len: usize, alignment: usize) -> std::io::Result<Self> {
+        let layout = Layout::from_size_align(len.max(alignment), alignment)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        // SAFETY: the layout has a nonzero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Ok(Self { ptr, layout })
+    }
+
+    fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the pointer is valid for `len` initialized bytes for the lifetime of self
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: as above, and `&mut self` guarantees exclusive access
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the pointer was allocated with this layout in `new`
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+type BlockingRead = JoinHandle<(std::fs::File, AlignedBuffer, std::io::Result<usize>)>;
+
+enum State {
+    Idle(std::fs::File),
+    Busy(BlockingRead),
+    Failed,
+}
+
+/// An `AsyncRead` over a file opened with `O_DIRECT`, bypassing the page cache. Direct IO
+/// requires the buffer address, the read size, and the file offset to be multiples of the
+/// logical block size of the device (usually 512 or 4096 bytes), which `tokio::fs::File` cannot
+/// guarantee because it reads through its own internal buffer. `DirectFile` reads whole aligned
+/// blocks on tokio's blocking thread pool into a buffer allocated with the required alignment.
+///
+/// Only available on Linux. Some filesystems, such as tmpfs, do not support `O_DIRECT` and
+/// opening the file fails with an `InvalidInput` error.
+pub struct DirectFile {
+    state: State,
+    alignment: usize,
+    // bytes already read from the file but not yet copied to the caller
+    pending: Option<(AlignedBuffer, usize, usize)>,
+}
+
+impl DirectFile {
+    /// Opens `path` for direct IO with reads aligned to `alignment` bytes, which must be a
+    /// power of two.
+    pub async fn open(path: impl AsRef<Path>, alignment: usize) -> std::io::Result<Self> {
+        crate::validate_alignment(alignment)?;
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::task::spawn_blocking(move || {
+            OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(path)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        Ok(Self {
+            state: State::Idle(file),
+            alignment,
+            pending: None,
+        })
+    }
+
+    fn copy_pending(&mut self, buf: &mut ReadBuf<'_>) {
+        if let Some((block, pos, len)) = &mut self.pending {
+            let count = (*len - *pos).min(buf.remaining());
+            buf.put_slice(&block.as_slice()[*pos..*pos + count]);
+            *pos += count;
+        }
+    }
+}
+
+impl AsyncRead for DirectFile {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if matches!(&this.pending, Some((_, pos, len)) if pos < len) {
+            this.copy_pending(buf);
+            return Poll::Ready(Ok(()));
+        }
+
+        loop {
+            match std::mem::replace(&mut this.state, State::Failed) {
+                State::Idle(mut file) => {
+                    // read whole blocks, any excess is kept for the next call
+                    let wanted = buf.remaining().max(1).next_multiple_of(this.alignment);
+                    let mut block = match this.pending.take().map(|(block, _, _)| block) {
+                        Some(block) if block.len() == wanted => block,
+                        _ => AlignedBuffer::new(wanted, this.alignment)?,
+                    };
+                    this.state = State::Busy(tokio::task::spawn_blocking(move || {
+                        let res = file.read(block.as_mut_slice());
+                        (file, block, res)
+                    }));
+                }
+                State::Busy(mut handle) => match Pin::new(&mut handle).poll(cx) {
+                    Poll::Pending => {
+                        this.state = State::Busy(handle);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(std::io::Error::other(err))),
+                    Poll::Ready(Ok((file, block, res))) => {
+                        this.state = State::Idle(file);
+                        let read = res?;
+                        this.pending = Some((block, 0, read));
+                        this.copy_pending(buf);
+                        return Poll::Ready(Ok(()));
+                    }
+                },
+                State::Failed => {
+                    return Poll::Ready(Err(std::io::Error::other("a previous direct read failed")))
+                }
+            }
+        }
+    }
+}
+
+impl TrackableBodyStream<DirectFile> {
+    /// Opens `path` with `O_DIRECT` and returns a stream whose buffer size is a multiple of
+    /// `alignment`, see `DirectFile` and `set_alignment`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let path = std::env::temp_dir().join("trackable_s3_stream_direct_io");
+    /// let data: Vec<u8> = (0..4096 * 3 + 100).map(|i| i as u8).collect();
+    /// std::fs::write(&path, &data)?;
+    ///
+    /// let body = match TrackableBodyStream::from_direct_io(&path, 4096).await {
+    ///     Ok(body) => body,
+    ///     // the filesystem of the temp dir does not support direct IO
+    ///     Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => return Ok(()),
+    ///     Err(err) => return Err(err.into()),
+    /// };
+    /// let frames: Vec<_> = body.try_collect().await?;
+    /// assert_eq!(frames.concat(), data);
+    /// # std::fs::remove_file(&path)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_direct_io(path: impl AsRef<Path>, alignment: usize) -> std::io::Result<Self> {
+        let file_size = tokio::fs::metadata(path.as_ref()).await?.len();
+        let file = DirectFile::open(path, alignment).await?;
+        let mut stream = Self::with_input(file, file_size);
+        stream.set_alignment(alignment)?;
+        Ok(stream)
+    }
+}
@@ -0,0 +1,359 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::SystemTime};
+
+use aws_sdk_s3::{types::DateTime, Client};
+use futures::StreamExt;
+use tokio::io::AsyncReadExt;
+
+use crate::{multipart::AggregateProgress, CallbackFn, ExpectedETag, MultipartUploader, TrackableBodyStream, TransferError, MAX_PARTS, MIN_PART_SIZE};
+
+/// The callback set with `DirectoryUploader::with_file_callback`: the path of the file followed
+/// by the arguments of `CallbackFn` for that file
+type FileCallbackFn = dyn Fn(&Path, u64, u64, u64) + Sync + Send + 'static;
+
+/// Files at least this large are uploaded with a multipart upload unless
+/// `with_multipart_threshold` says otherwise
+pub(crate) const DEFAULT_MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// A file found while walking the directory
+struct Entry {
+    path: PathBuf,
+    key: String,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// An object found under the key prefix by `DirectoryUploader::sync`
+struct RemoteObject {
+    len: u64,
+    last_modified: Option<DateTime>,
+    e_tag: Option<String>,
+}
+
+/// Uploads every file of a local directory tree to S3, with the progress of each file and of
+/// the whole job.
+///
+/// The directory is walked recursively, the key of a file is its path relative to the directory,
+/// with `/` separators, after the prefix set with `with_key_prefix`. Symbolic links are not
+/// followed. Files smaller than `with_multipart_threshold` are uploaded with a single
+/// `put_object` request, larger files with a `MultipartUploader`, which uploads up to four parts
+/// of the file at the same time. At most `with_concurrency` files are uploaded at once.
+///
+/// The callback receives the total size of all the files, the bytes sent so far over all files
+/// and the increase since the previous call, the file callback the same values for the file
+/// being uploaded. A request retried by the SDK does not count its bytes twice.
+///
+/// A file that fails to upload does not stop the others: `upload` returns the key and the
+/// outcome of every file, in the order of their paths. The failed file is taken out of the
+/// progress of the job, its size and the bytes it had sent are subtracted and the callback is
+/// called with an increase of `0`, so the progress reaches its total when the other files are
+/// uploaded. Only a failure to walk the directory fails the whole job.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::DirectoryUploader;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+///
+/// let outcomes = DirectoryUploader::new(client, "bucket", "./site")
+///     .with_key_prefix("www/")
+///     .with_concurrency(8)
+///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+///     .with_file_callback(|path, total, sent, _| println!("{}: {}/{} bytes", path.display(), sent, total))
+///     .upload()
+///     .await?;
+/// for (key, outcome) in &outcomes {
+///     if let Err(err) = outcome {
+///         println!("{} failed: {}", key, err);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct DirectoryUploader {
+    client: Client,
+    bucket: String,
+    directory: PathBuf,
+    key_prefix: String,
+    concurrency: usize,
+    multipart_threshold: u64,
+    callback: Option<Arc<CallbackFn>>,
+    file_callback: Option<Arc<FileCallbackFn>>,
+}
+
+impl DirectoryUploader {
+    /// Prepares the upload of the files under `directory` to `bucket`, four files at a time
+    pub fn new(client: Client, bucket: impl Into<String>, directory: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+            directory: directory.into(),
+            key_prefix: String::new(),
+            concurrency: 4,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            callback: None,
+            file_callback: None,
+        }
+    }
+
+    /// Sets the prefix prepended to the relative path of every file to form its key, include
+    /// the trailing `/` to upload the tree under a folder
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Sets the maximum number of files uploaded at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets the size from which files are uploaded with a multipart upload, 16 MB by default
+    pub fn with_multipart_threshold(mut self, threshold: u64) -> Self {
+        self.multipart_threshold = threshold;
+        self
+    }
+
+    /// Sets the progress callback of the whole job: the total size of all the files, the bytes
+    /// sent so far over all files, and the increase since the previous call
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the progress callback of each file: the path of the file, its size, the bytes of
+    /// the file sent so far, and the increase since the previous call
+    pub fn with_file_callback(mut self, callback: impl Fn(&Path, u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.file_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Walks the directory, uploads every file and returns their keys with the outcome of their
+    /// upload, in the order of their paths
+    pub async fn upload(self) -> Result<Vec<(String, Result<(), TransferError>)>, TransferError> {
+        let entries = self.walk().await?;
+        self.upload_entries(entries).await
+    }
+
+    /// Uploads only the files that differ from the objects already under the key prefix, like
+    /// `rsync`, and returns the keys of the uploaded files with the outcome of their upload.
+    /// The total passed to the callback is the size of the files that need to be uploaded, not
+    /// of the whole tree.
+    ///
+    /// A file is uploaded when there is no object with its key or the object has a different
+    /// size. When the sizes match, a file modified before the object was last written is
+    /// skipped, otherwise the file is read to compute the ETag it would have, see
+    /// `ExpectedETag`, and it is skipped if the object has the same ETag. A file that cannot be
+    /// read to compute its ETag is uploaded, so that its error is returned with its outcome
+    /// rather than failing the whole sync. Objects without a file are left in place.
+    ///
+    /// The ETag of a multipart upload depends on its part size, which S3 does not return. The
+    /// expected ETag is computed with the part size the uploaders of this crate use by default,
+    /// `MIN_PART_SIZE` or the size that fits the file in `MAX_PARTS` parts. An object uploaded
+    /// with another part size, by `MultipartUploader::with_part_size` or another tool, never
+    /// matches: it is uploaded again whenever its file is newer than the object, and that
+    /// upload gives it an ETag the next `sync` matches. ETags of objects encrypted with SSE-KMS
+    /// or SSE-C never match either.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::DirectoryUploader;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    ///
+    /// let changed = DirectoryUploader::new(client, "bucket", "./site")
+    ///     .with_key_prefix("www/")
+    ///     .with_callback(|total, sent, _| println!("{}/{} changed bytes", sent, total))
+    ///     .sync()
+    ///     .await?;
+    /// println!("{} files changed", changed.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn sync(self) -> Result<Vec<(String, Result<(), TransferError>)>, TransferError> {
+        let remote = self.list().await?;
+        let mut changed = Vec::new();
+        for entry in self.walk().await? {
+            // a file that cannot be read is uploaded, its upload reports the error
+            if self.has_changed(&entry, remote.get(&entry.key)).await.unwrap_or(true) {
+                changed.push(entry);
+            }
+        }
+        self.upload_entries(changed).await
+    }
+
+    async fn upload_entries(&self, entries: Vec<Entry>) -> Result<Vec<(String, Result<(), TransferError>)>, TransferError> {
+        let total = entries.iter().map(|entry| entry.len).sum();
+        let progress = Arc::new(AggregateProgress::new(total, 0));
+        let outcomes = futures::stream::iter(entries)
+            .map(|entry| self.upload_file(entry, progress.clone()))
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        Ok(outcomes)
+    }
+
+    /// Lists the files under the directory, sorted by path
+    async fn walk(&self) -> std::io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut directories = vec![self.directory.clone()];
+        while let Some(directory) = directories.pop() {
+            let mut read_dir = tokio::fs::read_dir(&directory).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    directories.push(entry.path());
+                } else if file_type.is_file() {
+                    let (path, metadata) = (entry.path(), entry.metadata().await?);
+                    let key = self.key(&path);
+                    entries.push(Entry { len: metadata.len(), modified: metadata.modified().ok(), path, key });
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// Lists the objects under the key prefix, by key
+    async fn list(&self) -> Result<HashMap<String, RemoteObject>, TransferError> {
+        let mut objects = HashMap::new();
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&self.key_prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|err| TransferError::s3("ListObjectsV2", err))?;
+            for object in page.contents().unwrap_or_default() {
+                let Some(key) = object.key() else {
+                    continue;
+                };
+                objects.insert(
+                    key.to_string(),
+                    RemoteObject {
+                        len: object.size().max(0) as u64,
+                        last_modified: object.last_modified().copied(),
+                        e_tag: object.e_tag().map(str::to_string),
+                    },
+                );
+            }
+            continuation_token = page.next_continuation_token().map(str::to_string);
+            if continuation_token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    async fn has_changed(&self, entry: &Entry, remote: Option<&RemoteObject>) -> std::io::Result<bool> {
+        let Some(remote) = remote.filter(|remote| remote.len == entry.len) else {
+            return Ok(true);
+        };
+        if let (Some(modified), Some(last_modified)) = (entry.modified, remote.last_modified) {
+            if DateTime::from(modified).as_nanos() <= last_modified.as_nanos() {
+                return Ok(false);
+            }
+        }
+        let Some(e_tag) = &remote.e_tag else {
+            return Ok(true);
+        };
+        let mut expected = match e_tag.contains('-') {
+            // the part size the uploaders of this crate use by default
+            true => ExpectedETag::multipart(MIN_PART_SIZE.max(entry.len.div_ceil(MAX_PARTS))),
+            false => ExpectedETag::new(),
+        };
+        let mut file = tokio::fs::File::open(&entry.path).await?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            expected.update(&buffer[..read]);
+        }
+        Ok(expected.verify_e_tag(e_tag).is_err())
+    }
+
+    fn key(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.directory).unwrap_or(path);
+        let components: Vec<_> = relative.components().map(|component| component.as_os_str().to_string_lossy()).collect();
+        format!("{}{}", self.key_prefix, components.join("/"))
+    }
+
+    async fn upload_file(&self, entry: Entry, progress: Arc<AggregateProgress>) -> (String, Result<(), TransferError>) {
+        let (callback, file_callback, path) = (self.callback.clone(), self.file_callback.clone(), entry.path.clone());
+        // the bytes of the file counted in the progress of the job
+        let counted = Arc::new(AtomicU64::new(0));
+        let report = {
+            let (progress, callback, counted) = (progress.clone(), callback.clone(), counted.clone());
+            move |file_total: u64, file_sent: u64, chunk: u64| {
+                counted.fetch_add(chunk, Ordering::SeqCst);
+                progress.add(chunk, |sent, increase| {
+                    if let Some(callback) = &callback {
+                        callback(progress.total(), sent, increase);
+                    }
+                });
+                if let Some(file_callback) = &file_callback {
+                    file_callback(&path, file_total, file_sent, chunk);
+                }
+            }
+        };
+        let retract = {
+            let (progress, counted) = (progress.clone(), counted.clone());
+            move |discarded| {
+                counted.fetch_sub(discarded, Ordering::SeqCst);
+                progress.retract(discarded);
+            }
+        };
+
+        let outcome = upload_path(&self.client, &self.bucket, &entry.key, &entry.path, entry.len, self.multipart_threshold, report, retract).await;
+        if outcome.is_err() {
+            progress.remove(entry.len, counted.load(Ordering::SeqCst), |total, sent| {
+                if let Some(callback) = &callback {
+                    callback(total, sent, 0);
+                }
+            });
+        }
+        (entry.key, outcome)
+    }
+}
+
+/// Uploads the file at `path` of `len` bytes with a single `put_object` request, or with a
+/// `MultipartUploader` once it reaches `multipart_threshold`. `report` is the progress
+/// callback of the file, `retract` receives the bytes of an attempt retried by the SDK.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn upload_path(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    len: u64,
+    multipart_threshold: u64,
+    report: impl Fn(u64, u64, u64) + Sync + Send + 'static,
+    retract: impl Fn(u64) + Sync + Send + 'static,
+) -> Result<(), TransferError> {
+    if len >= multipart_threshold {
+        MultipartUploader::new(client.clone(), bucket, key, path).with_callback(report).upload().await?;
+        return Ok(());
+    }
+    let mut body = TrackableBodyStream::from_path(path).await?.with_callback(report);
+    // bytes of an attempt the SDK retries are sent again
+    body.set_retry_callback(move |_, discarded| retract(discarded));
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_content_length(body.content_length())
+        .body(body.to_retryable_s3_stream())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("PutObject", err))?;
+    Ok(())
+}
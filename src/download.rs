@@ -0,0 +1,375 @@
+use std::{path::Path, pin::Pin, task::{Context, Poll}, time::Duration};
+
+use aws_sdk_s3::{error::GetObjectError, output::GetObjectOutput, types::{ByteStream, SdkError}, Client};
+use futures::{Stream, StreamExt};
+use http_body::Body;
+use bytes::Buf;
+use tokio::{fs::File, io::AsyncWriteExt};
+
+use crate::{checksum::Hasher, Bytes, CallbackFn, ChecksumAlgorithm, TrackableStreamError, TrackableWriter, TransferError};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// How many times `download_object` resumes a download after a transient failure
+const DOWNLOAD_RETRIES: usize = 3;
+
+/// How long `download_object` waits before its first resume, doubled for every further one
+const DOWNLOAD_BACKOFF: Duration = Duration::from_millis(200);
+
+/// A `futures::Stream` wrapper that tracks a stream of bytes, for example the body of a
+/// `get_object` response while downloading from S3 or the body of an incoming HTTP request
+/// being proxied to S3. As the wrapped stream is consumed it triggers the same callback as
+/// `TrackableBodyStream`, and it can verify the bytes against an expected checksum.
+///
+/// # Examples
+/// ```
+/// # use futures::TryStreamExt;
+/// # use trackable_s3_stream::{Bytes, ChecksumAlgorithm, TrackableByteStream, TrackableStreamError};
+/// # futures::executor::block_on(async {
+/// let chunks = || futures::stream::iter(vec![
+///     Ok::<_, std::io::Error>(Bytes::from("hello ")),
+///     Ok(Bytes::from("world")),
+/// ]);
+///
+/// let download = TrackableByteStream::new(chunks(), 11)
+///     .with_expected_checksum(ChecksumAlgorithm::Sha256, "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=");
+/// let body: Vec<_> = download.try_collect().await.unwrap();
+/// assert_eq!(body.concat(), b"hello world");
+///
+/// let download = TrackableByteStream::new(chunks(), 11)
+///     .with_expected_checksum(ChecksumAlgorithm::Sha256, "bm90IHRoZSByaWdodCBjaGVja3N1bQ==");
+/// let res: Result<Vec<_>, _> = download.try_collect().await;
+/// assert!(matches!(res, Err(TrackableStreamError::ChecksumMismatch { .. })));
+/// # });
+/// ```
+pub struct TrackableByteStream<S> {
+    inner: S,
+    total: Option<u64>,
+    cur_read: u64,
+    callback: Option<Box<CallbackFn>>,
+    hasher: Option<Hasher>,
+    expected_checksum: Option<String>,
+    complete: bool,
+}
+
+/// Adapts an `http_body::Body`, such as a `hyper::Body`, into a `futures::Stream` of its data
+/// frames. Trailers are not part of the stream and are discarded.
+pub struct HttpBodyStream<B>(B);
+
+impl<B> Stream for HttpBodyStream<B>
+where
+    B: Body + Unpin,
+{
+    type Item = Result<Bytes, B::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().0)
+            .poll_data(cx)
+            .map(|data| data.map(|data| data.map(|mut data| data.copy_to_bytes(data.remaining()))))
+    }
+}
+
+impl<B> TrackableByteStream<HttpBodyStream<B>>
+where
+    B: Body + Unpin,
+    B::Error: Into<BoxError>,
+{
+    /// Tracks the data of an HTTP body, for example the incoming request of a proxy that
+    /// forwards it to S3 with `to_s3_stream`. The bytes are passed through as they arrive,
+    /// without buffering. The total length is taken from the exact size hint of the body, which
+    /// hyper derives from the `Content-Length` header; when the length is unknown the callback
+    /// receives `0` as the total. Trailers of the incoming body are not forwarded.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableByteStream;
+    /// # futures::executor::block_on(async {
+    /// let sent = Arc::new(AtomicU64::new(0));
+    /// let progress = sent.clone();
+    ///
+    /// let incoming = hyper::Body::from("proxied request body");
+    /// let proxied = TrackableByteStream::from_http_body(incoming)
+    ///     .with_callback(move |_, sent, _| progress.store(sent, Ordering::SeqCst));
+    /// assert_eq!(proxied.content_length(), Some(20));
+    ///
+    /// let body: Vec<_> = proxied.try_collect().await.unwrap();
+    /// assert_eq!(body.concat(), b"proxied request body");
+    /// assert_eq!(sent.load(Ordering::SeqCst), 20);
+    /// # });
+    /// ```
+    pub fn from_http_body(body: B) -> Self {
+        let mut stream = Self::with_total(HttpBodyStream(body), None);
+        stream.total = stream.inner.0.size_hint().exact();
+        stream
+    }
+}
+
+impl TrackableByteStream<ByteStream> {
+    /// Tracks the body of a `get_object` response, to report download progress with the same
+    /// callback as uploads. The total length is the `content_length` of the response, which S3
+    /// sets on every response, `0` for an empty object, and the downloaded length is checked
+    /// against it, as `TrackableBodyStream::from_get_object` does. Only a negative length is
+    /// treated as unknown.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use futures::TryStreamExt;
+    /// # use indicatif::ProgressBar;
+    /// # use trackable_s3_stream::TrackableByteStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    /// let output = client.get_object().bucket("bucket").key("sample.jpeg").send().await?;
+    ///
+    /// let bar = ProgressBar::new(output.content_length() as u64);
+    /// let download = TrackableByteStream::from_get_object(output)
+    ///     .with_callback(move |_, _, chunk| bar.inc(chunk));
+    /// let body: Vec<_> = download.try_collect().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_get_object(output: GetObjectOutput) -> Self {
+        let total = Some(output.content_length()).filter(|length| *length >= 0).map(|length| length as u64);
+        Self::with_total(output.body, total)
+    }
+}
+
+impl<S, E> TrackableByteStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<BoxError>,
+{
+    /// Wraps a stream of bytes whose total length is `total`, usually the `content_length` of
+    /// the `get_object` response.
+    pub fn new(inner: S, total: u64) -> Self {
+        Self::with_total(inner, Some(total))
+    }
+
+    fn with_total(inner: S, total: Option<u64>) -> Self {
+        Self {
+            inner,
+            total,
+            cur_read: 0,
+            callback: None,
+            hasher: None,
+            expected_checksum: None,
+            complete: false,
+        }
+    }
+
+    /// Sets the callback method and returns the populated stream. The callback receives the
+    /// same arguments as the `TrackableBodyStream` callback.
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the callback method
+    pub fn set_callback(&mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Computes the checksum of the downloaded bytes and compares it with `expected`, the base64
+    /// encoded value S3 returns in the checksum fields of `GetObjectOutput`. When the download
+    /// completes with a different checksum the stream yields a
+    /// `TrackableStreamError::ChecksumMismatch` error instead of ending.
+    pub fn with_expected_checksum(mut self, algorithm: ChecksumAlgorithm, expected: impl Into<String>) -> Self {
+        self.hasher = Some(Hasher::new(algorithm));
+        self.expected_checksum = Some(expected.into());
+        self
+    }
+
+    /// Returns the number of bytes downloaded so far
+    pub fn bytes_read(&self) -> u64 {
+        self.cur_read
+    }
+
+    /// Returns the total length of the stream, if known. S3 rejects a `put_object` request
+    /// without a content length, streams of unknown length need to be uploaded with a
+    /// multipart upload instead.
+    pub fn content_length(&self) -> Option<i64> {
+        self.total.map(|total| total as i64)
+    }
+
+    fn verify(&mut self) -> Result<(), TrackableStreamError> {
+        if let Some(total) = self.total {
+            if total != self.cur_read {
+                return Err(TrackableStreamError::LengthMismatch { expected: total, actual: self.cur_read });
+            }
+        }
+        if let (Some(hasher), Some(expected)) = (self.hasher.take(), self.expected_checksum.take()) {
+            let algorithm = hasher.algorithm();
+            let actual = hasher.finalize();
+            if actual != expected {
+                return Err(TrackableStreamError::ChecksumMismatch { algorithm, expected, actual });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S, E> TrackableByteStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin + Send + 'static,
+    E: Into<BoxError>,
+{
+    /// Consumes this stream and returns a `ByteStream` object that can be passed to the `body`
+    /// method of the `put_object` call in the AWS SDK for Rust.
+    pub fn to_s3_stream(self) -> ByteStream {
+        ByteStream::new(crate::body::sdk_body(self))
+    }
+}
+
+impl<S, E> Stream for TrackableByteStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<BoxError>,
+{
+    type Item = Result<Bytes, TrackableStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut_self = self.get_mut();
+        if mut_self.complete {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut mut_self.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                mut_self.cur_read += chunk.len() as u64;
+                if let Some(hasher) = &mut mut_self.hasher {
+                    hasher.update(&chunk);
+                }
+                if let Some(callback) = &mut_self.callback {
+                    callback(mut_self.total.unwrap_or(0), mut_self.cur_read, chunk.len() as u64);
+                }
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                // the body is not read past its first error, which would hide it behind the
+                // length or checksum mismatch of the bytes that are missing
+                mut_self.complete = true;
+                Poll::Ready(Some(Err(TrackableStreamError::Io {
+                    source: std::io::Error::other(err),
+                    bytes_read: mut_self.cur_read,
+                })))
+            }
+            Poll::Ready(None) => {
+                mut_self.complete = true;
+                match mut_self.verify() {
+                    Ok(()) => Poll::Ready(None),
+                    Err(err) => Poll::Ready(Some(Err(err))),
+                }
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Downloads `bucket`/`key` to the file at `path`, replacing it, and returns the number of bytes
+/// written. The callback receives the same arguments as the `TrackableBodyStream` callback,
+/// with the `content_length` of the object as the total.
+///
+/// A download interrupted by a transient failure, a timeout, a dropped connection or a server
+/// error, is resumed up to three times with a ranged request for the rest of the object, on the
+/// condition that its ETag has not changed, so the bytes already written are not downloaded
+/// again and the progress never goes backwards. The resumes wait 200 ms, 400 ms then 800 ms,
+/// so that a throttled request or a brief outage has time to clear. The file is synced to disk
+/// before the helper returns, a download that ends before `content_length` bytes fails with
+/// `TrackableStreamError::LengthMismatch`.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::download_object;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+///
+/// download_object(&client, "bucket", "backup.tar", "./backup.tar", |total, sent, _| {
+///     println!("{}/{} bytes", sent, total)
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn download_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: impl AsRef<Path>,
+    callback: impl Fn(u64, u64, u64) + Sync + Send + 'static,
+) -> Result<u64, TransferError> {
+    let file = File::create(path).await?;
+    let mut writer = TrackableWriter::with_unknown_length(file).with_callback(callback);
+    let mut e_tag = None;
+    let mut attempt = 0;
+
+    'download: loop {
+        let written = writer.bytes_written();
+        if written > 0 && writer.total() == Some(written) {
+            // the connection failed after the last byte, there is nothing left to resume
+            break;
+        }
+        let mut request = client.get_object().bucket(bucket).key(key);
+        if written > 0 {
+            request = request.range(format!("bytes={}-", written)).set_if_match(e_tag.clone());
+        }
+        let output = match request.send().await {
+            Ok(output) => output,
+            Err(err) if attempt < DOWNLOAD_RETRIES && is_transient(&err) => {
+                attempt += 1;
+                log::warn!("Retrying download of {} after error: {}", key, err);
+                tokio::time::sleep(backoff(attempt)).await;
+                continue;
+            }
+            Err(err) => return Err(TransferError::s3("GetObject", err)),
+        };
+        if written == 0 {
+            writer.set_total(output.content_length().max(0) as u64);
+            e_tag = output.e_tag().map(str::to_string);
+        }
+
+        let mut body = output.body;
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => writer.write_all(&chunk).await?,
+                Err(err) if attempt < DOWNLOAD_RETRIES => {
+                    attempt += 1;
+                    log::warn!("Resuming download of {} after error: {}", key, err);
+                    tokio::time::sleep(backoff(attempt)).await;
+                    continue 'download;
+                }
+                Err(err) => {
+                    let source = std::io::Error::other(err);
+                    return Err(TrackableStreamError::Io { source, bytes_read: writer.bytes_written() }.into());
+                }
+            }
+        }
+        break;
+    }
+
+    writer.flush().await?;
+    let actual = writer.bytes_written();
+    if let Some(expected) = writer.total().filter(|expected| *expected != actual) {
+        return Err(TrackableStreamError::LengthMismatch { expected, actual }.into());
+    }
+    writer.get_ref().sync_all().await?;
+    Ok(actual)
+}
+
+/// Returns how long to wait before the `attempt`th resume of a download, so that a throttled
+/// request or a brief outage does not use up every resume at once
+fn backoff(attempt: usize) -> Duration {
+    DOWNLOAD_BACKOFF * 2u32.pow(attempt as u32 - 1)
+}
+
+fn is_transient(err: &SdkError<GetObjectError>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError { .. } => true,
+        SdkError::ServiceError { raw, .. } => raw.http().status().is_server_error(),
+        _ => false,
+    }
+}
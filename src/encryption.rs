@@ -0,0 +1,189 @@
+use std::{fmt, io, sync::Arc};
+
+use ring::{aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM}, rand::{SecureRandom, SystemRandom}};
+
+/// The number of plaintext bytes sealed in each segment unless `with_segment_size` says otherwise
+pub const DEFAULT_SEGMENT_SIZE: usize = 64 * 1024;
+
+/// The version of the format, the first byte of an encrypted body
+const VERSION: u8 = 1;
+
+const PREFIX_LEN: usize = 7;
+const HEADER_LEN: usize = 1 + PREFIX_LEN;
+const TAG_LEN: usize = 16;
+
+/// Client-side encryption of the body of a `TrackableBodyStream` with AES-256-GCM, set with
+/// `with_encryption`, so that S3 only ever stores ciphertext.
+///
+/// GCM cannot stream a message of unlimited length, the source is split in segments of
+/// `with_segment_size` bytes instead, each sealed on its own and followed by its 16-byte
+/// authentication tag. The nonce of a segment is made of a random prefix, chosen for every
+/// body and stored in its 8-byte header, the number of the segment and a flag marking the last
+/// segment, so that segments cannot be reordered, dropped or truncated without `decrypt`
+/// noticing. The length of the encrypted body is known in advance.
+///
+/// # Examples
+/// ```
+/// # use futures::TryStreamExt;
+/// # use trackable_s3_stream::{Encryption, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let key = [7u8; 32];
+/// let encryption = Encryption::aes_256_gcm(&key).with_segment_size(1000);
+/// let data = vec![1u8; 2500];
+/// let body = TrackableBodyStream::from(data.as_slice()).with_encryption(encryption.clone());
+/// let length = body.content_length();
+///
+/// let mut encrypted = body.try_collect::<Vec<_>>().await?.concat();
+/// assert_eq!(length, Some(encrypted.len() as i64));
+/// assert_eq!(encryption.decrypt(&encrypted)?, data);
+///
+/// encrypted[100] ^= 1;
+/// assert!(encryption.decrypt(&encrypted).is_err());
+/// assert!(encryption.decrypt(&encrypted[..1000]).is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Encryption {
+    key: Arc<LessSafeKey>,
+    segment_size: usize,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption").field("segment_size", &self.segment_size).finish_non_exhaustive()
+    }
+}
+
+impl Encryption {
+    /// Encrypts with AES-256-GCM under `key`, in segments of `DEFAULT_SEGMENT_SIZE` bytes
+    pub fn aes_256_gcm(key: &[u8; 32]) -> Self {
+        // the key has the length the algorithm expects, `new` cannot fail
+        let key = UnboundKey::new(&AES_256_GCM, key).expect("AES-256 key of 32 bytes");
+        Self { key: Arc::new(LessSafeKey::new(key)), segment_size: DEFAULT_SEGMENT_SIZE }
+    }
+
+    /// Sets the number of plaintext bytes sealed in each segment. Larger segments add less
+    /// overhead, 16 bytes per segment, but are buffered whole before they are emitted.
+    pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+
+    /// Returns the length of the encrypted body of a source of `len` bytes
+    pub fn encrypted_length(&self, len: u64) -> u64 {
+        let segments = len.div_ceil(self.segment_size as u64).max(1);
+        HEADER_LEN as u64 + len + segments * TAG_LEN as u64
+    }
+
+    /// Decrypts a whole body encrypted with the same key and segment size. Returns an
+    /// `InvalidData` error if the body is not in the expected format or has been tampered with.
+    pub fn decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.len() < HEADER_LEN || data[0] != VERSION {
+            return Err(invalid("not an encrypted body"));
+        }
+        let prefix: [u8; PREFIX_LEN] = data[1..HEADER_LEN].try_into().unwrap_or_default();
+        let mut segments = data[HEADER_LEN..].chunks(self.segment_size + TAG_LEN).peekable();
+        let mut plaintext = Vec::with_capacity(data.len());
+        let mut counter = 0u32;
+        while let Some(segment) = segments.next() {
+            let last = segments.peek().is_none();
+            let mut in_out = segment.to_vec();
+            let opened = self
+                .key
+                .open_in_place(nonce(&prefix, counter, last), Aad::empty(), &mut in_out)
+                .map_err(|_| invalid("segment failed authentication"))?;
+            plaintext.extend_from_slice(opened);
+            counter = counter.checked_add(1).ok_or_else(|| invalid("too many segments"))?;
+        }
+        if counter == 0 {
+            return Err(invalid("truncated encrypted body"));
+        }
+        Ok(plaintext)
+    }
+
+    pub(crate) fn encryptor(&self) -> Encryptor {
+        let mut prefix = [0u8; PREFIX_LEN];
+        // the system random number generator only fails on platforms ring does not support
+        SystemRandom::new().fill(&mut prefix).expect("random nonce prefix");
+        Encryptor { encryption: self.clone(), prefix, counter: 0, carry: Vec::new(), started: false }
+    }
+}
+
+/// The state of the encryption of one body
+pub(crate) struct Encryptor {
+    encryption: Encryption,
+    prefix: [u8; PREFIX_LEN],
+    counter: u32,
+    /// Plaintext not sealed yet: the last segment is only sealed once the end of the source is
+    /// reached, so a full segment is held until more bytes follow
+    carry: Vec<u8>,
+    started: bool,
+}
+
+impl Encryptor {
+    /// Returns an encryptor for the same key with a new nonce prefix, to encrypt the source again
+    pub(crate) fn restart(&self) -> Self {
+        self.encryption.encryptor()
+    }
+
+    pub(crate) fn encrypted_length(&self, len: u64) -> u64 {
+        self.encryption.encrypted_length(len)
+    }
+
+    pub(crate) fn encode(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = self.header();
+        self.carry.extend_from_slice(chunk);
+        let segment_size = self.encryption.segment_size;
+        while self.carry.len() > segment_size {
+            let rest = self.carry.split_off(segment_size);
+            let segment = std::mem::replace(&mut self.carry, rest);
+            self.seal(segment, false, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    pub(crate) fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = self.header();
+        let segment = std::mem::take(&mut self.carry);
+        self.seal(segment, true, &mut out)?;
+        Ok(out)
+    }
+
+    fn header(&mut self) -> Vec<u8> {
+        if self.started {
+            return Vec::new();
+        }
+        self.started = true;
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.push(VERSION);
+        header.extend_from_slice(&self.prefix);
+        header
+    }
+
+    fn seal(&mut self, mut segment: Vec<u8>, last: bool, out: &mut Vec<u8>) -> io::Result<()> {
+        let nonce = nonce(&self.prefix, self.counter, last);
+        let tag = self
+            .encryption
+            .key
+            .seal_in_place_separate_tag(nonce, Aad::empty(), &mut segment)
+            .map_err(|_| invalid("segment could not be sealed"))?;
+        self.counter = self.counter.checked_add(1).ok_or_else(|| invalid("too many segments"))?;
+        out.extend_from_slice(&segment);
+        out.extend_from_slice(tag.as_ref());
+        Ok(())
+    }
+}
+
+fn nonce(prefix: &[u8; PREFIX_LEN], counter: u32, last: bool) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,193 @@
+use std::{fmt, time::Duration};
+
+use crate::ChecksumAlgorithm;
+
+/// The error type returned by a `TrackableBodyStream` when it cannot produce the next chunk.
+///
+/// New variants may be added in future releases, matches on this enum should include a
+/// wildcard arm.
+///
+/// # Examples
+/// ```
+/// # use futures::TryStreamExt;
+/// # use trackable_s3_stream::{Bytes, TrackableByteStream, TrackableStreamError};
+/// # futures::executor::block_on(async {
+/// let chunks = futures::stream::iter(vec![
+///     Ok(Bytes::from("hello")),
+///     Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")),
+/// ]);
+/// let res: Result<Vec<_>, _> = TrackableByteStream::new(chunks, 11).try_collect().await;
+///
+/// match res {
+///     Err(TrackableStreamError::Io { bytes_read, .. }) => assert_eq!(bytes_read, 5),
+///     _ => panic!("expected an IO error"),
+/// }
+/// # });
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TrackableStreamError {
+    /// Reading from the source failed after `bytes_read` bytes had been read successfully
+    Io {
+        source: std::io::Error,
+        bytes_read: u64,
+    },
+    /// The source ended after a different number of bytes than its declared length
+    LengthMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The progress callback panicked while the `CallbackPanicPolicy::Abort` policy was set.
+    /// Contains the panic message, if it was a string.
+    CallbackPanicked(String),
+    /// The checksum computed over the streamed bytes does not match the expected value
+    ChecksumMismatch {
+        algorithm: ChecksumAlgorithm,
+        expected: String,
+        actual: String,
+    },
+    /// The deadline set with `set_deadline` passed after `bytes_read` bytes had been read
+    DeadlineExceeded {
+        bytes_read: u64,
+    },
+    /// The cancellation token set with `set_cancellation_token` was cancelled after
+    /// `bytes_read` bytes had been read
+    Cancelled {
+        bytes_read: u64,
+    },
+    /// No bytes were read from the source or taken by the consumer for `idle`, longer than the
+    /// timeout set with `set_stall_timeout`, after `bytes_read` bytes had been read
+    Stalled {
+        bytes_read: u64,
+        idle: Duration,
+    },
+}
+
+impl fmt::Display for TrackableStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackableStreamError::Io { source, bytes_read } => {
+                write!(f, "could not read from source after {} bytes: {}", bytes_read, source)
+            }
+            TrackableStreamError::LengthMismatch { expected, actual } => {
+                write!(f, "source ended after {} bytes, expected {}", actual, expected)
+            }
+            TrackableStreamError::CallbackPanicked(message) => write!(f, "progress callback panicked: {}", message),
+            TrackableStreamError::ChecksumMismatch { algorithm, expected, actual } => write!(
+                f,
+                "{:?} checksum mismatch: expected {}, computed {}",
+                algorithm, expected, actual
+            ),
+            TrackableStreamError::DeadlineExceeded { bytes_read } => {
+                write!(f, "deadline exceeded after {} bytes", bytes_read)
+            }
+            TrackableStreamError::Cancelled { bytes_read } => write!(f, "cancelled after {} bytes", bytes_read),
+            TrackableStreamError::Stalled { bytes_read, idle } => {
+                write!(f, "stalled for {:?} after {} bytes", idle, bytes_read)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TrackableStreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TrackableStreamError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Converts the error for readers and other IO interfaces, keeping the kind of the error of the
+/// source for `TrackableStreamError::Io`
+impl From<TrackableStreamError> for std::io::Error {
+    fn from(err: TrackableStreamError) -> Self {
+        match &err {
+            TrackableStreamError::Io { source, .. } => std::io::Error::new(source.kind(), err),
+            _ => std::io::Error::other(err),
+        }
+    }
+}
+
+/// The error returned by operations bounded by a timeout: either the timeout elapsed, or the
+/// operation failed before it did.
+#[derive(Debug)]
+pub enum TimeoutOr<E> {
+    /// The operation did not complete within the given duration
+    Timeout(Duration),
+    /// The operation failed
+    Error(E),
+}
+
+impl<E: fmt::Display> fmt::Display for TimeoutOr<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutOr::Timeout(duration) => write!(f, "timed out after {:?}", duration),
+            TimeoutOr::Error(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for TimeoutOr<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TimeoutOr::Timeout(_) => None,
+            TimeoutOr::Error(err) => Some(err),
+        }
+    }
+}
+
+/// The error type returned by the helpers that drive requests to S3, such as
+/// `put_object_if_changed`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TransferError {
+    /// Reading or writing a local file failed
+    Io(std::io::Error),
+    /// A trackable stream failed to produce or consume the body of a request
+    Stream(TrackableStreamError),
+    /// A request to S3 failed
+    S3 {
+        /// The name of the S3 operation, for example `PutObject`
+        operation: &'static str,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+}
+
+impl TransferError {
+    pub(crate) fn s3(operation: &'static str, err: impl std::error::Error + Send + Sync + 'static) -> Self {
+        TransferError::S3 { operation, source: Box::new(err) }
+    }
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Io(err) => write!(f, "local IO failed: {}", err),
+            TransferError::Stream(err) => err.fmt(f),
+            TransferError::S3 { operation, source } => write!(f, "{} request failed: {}", operation, source),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransferError::Io(err) => Some(err),
+            TransferError::Stream(err) => Some(err),
+            TransferError::S3 { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<std::io::Error> for TransferError {
+    fn from(err: std::io::Error) -> Self {
+        TransferError::Io(err)
+    }
+}
+
+impl From<TrackableStreamError> for TransferError {
+    fn from(err: TrackableStreamError) -> Self {
+        TransferError::Stream(err)
+    }
+}
@@ -0,0 +1,188 @@
+use std::{fmt::Write, io};
+
+use aws_sdk_s3::output::{CompleteMultipartUploadOutput, PutObjectOutput, UploadPartOutput};
+use md5::{Digest, Md5};
+
+/// The ETag S3 should assign to an object, computed from the bytes of the upload as they are
+/// streamed, to detect an upload that was silently corrupted.
+///
+/// An object uploaded with a single `put_object` request has the MD5 digest of its content as
+/// its ETag. An object uploaded with a multipart upload has the MD5 digest of the binary MD5
+/// digests of its parts, followed by `-` and the number of parts, so the size of the parts must
+/// be known to compute it. Objects encrypted with SSE-KMS or SSE-C have ETags that are not
+/// derived from their content and cannot be verified this way.
+///
+/// # Examples
+/// ```
+/// # use trackable_s3_stream::ExpectedETag;
+/// let mut etag = ExpectedETag::new();
+/// etag.update(b"hello ");
+/// etag.update(b"world");
+/// assert_eq!(etag.e_tag(), "\"5eb63bbbe01eeed093cb22bb8f5acdc3\"");
+/// assert!(etag.verify_e_tag("\"5eb63bbbe01eeed093cb22bb8f5acdc3\"").is_ok());
+///
+/// let mut etag = ExpectedETag::multipart(6);
+/// etag.update(b"hello world");
+/// assert_eq!(etag.parts(), 2);
+/// assert!(etag.e_tag().ends_with("-2\""));
+/// ```
+#[derive(Clone, Default)]
+pub struct ExpectedETag {
+    part_size: Option<u64>,
+    part: Md5,
+    part_len: u64,
+    /// The binary digests of the parts completed so far
+    digests: Vec<u8>,
+}
+
+impl ExpectedETag {
+    /// Creates the ETag of an object uploaded with a single `put_object` request
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the ETag of an object uploaded with a multipart upload in parts of `part_size`
+    /// bytes, the last part may be smaller
+    pub fn multipart(part_size: u64) -> Self {
+        Self { part_size: Some(part_size.max(1)), ..Self::default() }
+    }
+
+    /// Builds the ETag of a completed multipart upload from the ETags S3 returned for each of its
+    /// parts, in the order of their part numbers. Returns an `InvalidData` error if an ETag is
+    /// not an MD5 digest.
+    pub fn from_part_e_tags<'a>(e_tags: impl IntoIterator<Item = &'a str>) -> io::Result<Self> {
+        let mut etag = Self::multipart(u64::MAX);
+        for e_tag in e_tags {
+            let digest = decode_hex(e_tag.trim_matches('"'))
+                .ok_or_else(|| invalid(format!("{} is not the ETag of a part", e_tag)))?;
+            etag.digests.extend_from_slice(&digest);
+        }
+        Ok(etag)
+    }
+
+    /// Feeds the next bytes of the upload
+    pub fn update(&mut self, mut data: &[u8]) {
+        let Some(part_size) = self.part_size else {
+            self.part.update(data);
+            return;
+        };
+        while !data.is_empty() {
+            let len = (part_size - self.part_len).min(data.len() as u64) as usize;
+            self.part.update(&data[..len]);
+            self.part_len += len as u64;
+            data = &data[len..];
+            if self.part_len == part_size {
+                let digest = std::mem::take(&mut self.part).finalize();
+                self.digests.extend_from_slice(&digest);
+                self.part_len = 0;
+            }
+        }
+    }
+
+    /// Returns the number of parts of a multipart upload fed so far, including a partial last part
+    pub fn parts(&self) -> usize {
+        self.digests.len() / 16 + usize::from(self.part_len > 0)
+    }
+
+    /// Returns the expected ETag, in double quotes as S3 returns it
+    pub fn e_tag(&self) -> String {
+        if self.part_size.is_none() {
+            return format!("\"{}\"", encode_hex(&self.part.clone().finalize()));
+        }
+        let mut digests = self.digests.clone();
+        if self.part_len > 0 {
+            digests.extend_from_slice(&self.part.clone().finalize());
+        }
+        format!("\"{}-{}\"", encode_hex(&Md5::digest(&digests)), digests.len() / 16)
+    }
+
+    /// Compares the expected ETag with `e_tag`, with or without its double quotes. Returns an
+    /// `InvalidData` error if they differ.
+    pub fn verify_e_tag(&self, e_tag: &str) -> io::Result<()> {
+        let expected = self.e_tag();
+        if expected.trim_matches('"') == e_tag.trim_matches('"') {
+            return Ok(());
+        }
+        Err(invalid(format!("S3 reported an ETag of {}, expected {}", e_tag, expected)))
+    }
+
+    /// Compares the expected ETag with the one S3 returned for the upload, either a
+    /// `PutObjectOutput` or a `CompleteMultipartUploadOutput`. Returns an `InvalidData` error if
+    /// they differ or if S3 did not return an ETag.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::{ExpectedETag, TrackableBodyStream};
+    /// # async fn upload(client: aws_sdk_s3::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// let etag = body.track_e_tag(ExpectedETag::new());
+    /// let output = client
+    ///     .put_object()
+    ///     .bucket("bucket")
+    ///     .key("sample.jpeg")
+    ///     .set_content_length(body.content_length())
+    ///     .body(body.to_s3_stream())
+    ///     .send()
+    ///     .await?;
+    /// etag.lock().unwrap().verify(&output)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn verify(&self, output: &impl ETagOutput) -> io::Result<()> {
+        match output.reported_e_tag() {
+            Some(e_tag) => self.verify_e_tag(e_tag),
+            None => Err(invalid("S3 did not return an ETag".to_string())),
+        }
+    }
+
+    /// Starts again from the first byte, keeping the part size
+    pub(crate) fn restart(&mut self) {
+        *self = Self { part_size: self.part_size, ..Self::default() };
+    }
+}
+
+/// The output of an S3 request that returns the ETag of the new object
+pub trait ETagOutput {
+    /// Returns the ETag S3 assigned to the object
+    fn reported_e_tag(&self) -> Option<&str>;
+}
+
+impl ETagOutput for PutObjectOutput {
+    fn reported_e_tag(&self) -> Option<&str> {
+        self.e_tag()
+    }
+}
+
+impl ETagOutput for UploadPartOutput {
+    fn reported_e_tag(&self) -> Option<&str> {
+        self.e_tag()
+    }
+}
+
+impl ETagOutput for CompleteMultipartUploadOutput {
+    fn reported_e_tag(&self) -> Option<&str> {
+        self.e_tag()
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; 16];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,376 @@
+use std::{io::{self, Cursor}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+use aws_sdk_s3::{model::{CompletedMultipartUpload, CompletedPart}, Client};
+use bytes::BytesMut;
+use futures::{channel::mpsc, SinkExt, StreamExt};
+
+use crate::{directory::DEFAULT_MULTIPART_THRESHOLD, multipart::AggregateProgress, BodySource, Bytes, CallbackFn, TrackableBodyStream, TransferError, MAX_PARTS, MIN_PART_SIZE};
+
+/// The callback set with `FanOutUploader::with_destination_callback`: the index of the
+/// destination followed by the arguments of `CallbackFn` for that destination
+type DestinationCallbackFn = dyn Fn(usize, u64, u64, u64) + Sync + Send + 'static;
+
+/// How many frames of the source a destination can fall behind before it holds back the others
+const CHANNEL_CAPACITY: usize = 8;
+
+/// An object the source is uploaded to
+struct Destination {
+    client: Client,
+    bucket: String,
+    key: String,
+    /// The content encoding of the source, set on every object
+    content_encoding: Option<&'static str>,
+}
+
+/// The combined progress of the destinations still uploading
+struct Combined {
+    /// The total and the bytes sent, the lock is held while the callback runs so that it
+    /// observes them in order
+    totals: Mutex<(u64, u64)>,
+    callback: Option<Arc<CallbackFn>>,
+}
+
+impl Combined {
+    fn add(&self, increase: u64) {
+        let mut totals = self.totals.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        totals.1 += increase;
+        if let Some(callback) = &self.callback {
+            callback(totals.0, totals.1, increase);
+        }
+    }
+
+    /// Takes a destination that failed out of the progress, with the bytes it had sent
+    fn remove(&self, total: u64, sent: u64) {
+        let mut totals = self.totals.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        totals.0 -= total;
+        totals.1 -= sent;
+        if let Some(callback) = &self.callback {
+            callback(totals.0, totals.1, 0);
+        }
+    }
+}
+
+/// The progress callbacks of a destination
+#[derive(Clone)]
+struct Reporter {
+    index: usize,
+    progress: Arc<AggregateProgress>,
+    /// The bytes of the destination reported to the combined progress
+    reported: Arc<AtomicU64>,
+    combined: Arc<Combined>,
+    destination_callback: Option<Arc<DestinationCallbackFn>>,
+}
+
+impl Reporter {
+    fn add(&self, chunk: u64) {
+        self.progress.add(chunk, |sent, increase| {
+            if let Some(callback) = &self.destination_callback {
+                callback(self.index, self.progress.total(), sent, increase);
+            }
+            self.reported.fetch_add(increase, Ordering::SeqCst);
+            self.combined.add(increase);
+        });
+    }
+
+    /// Takes back the bytes of a request the SDK is retrying, they are only reported again once
+    /// the retry sends more than the failed attempt
+    fn retract(&self, bytes: u64) {
+        self.progress.retract(bytes);
+    }
+
+    /// Takes the destination out of the combined progress once it has failed
+    fn fail(&self) {
+        self.combined.remove(self.progress.total(), self.reported.swap(0, Ordering::SeqCst));
+    }
+}
+
+/// Uploads a single source to several objects at the same time, for example to a primary
+/// bucket and to a bucket in a disaster recovery region, reading the source only once.
+///
+/// Every frame of the source is handed to all the destinations, each destination uploads it
+/// with its own client, so destinations can be in different regions or accounts. Sources of
+/// known length smaller than `with_multipart_threshold` are uploaded with a `put_object` request
+/// per destination, the others with a multipart upload per destination, which buffers one part
+/// of `with_part_size` bytes at a time in memory and retries a part the SDK fails to send. A
+/// `put_object` request is not retried, since its body cannot be read again.
+///
+/// The source is read once and as fast as the slowest destination accepts it: every frame is
+/// handed to each destination in turn, through a channel that holds up to 8 frames per
+/// destination, so a destination that falls 8 frames behind holds back the others until it
+/// catches up. A body compressed with `with_compression`, whose length is unknown, is uploaded
+/// with a multipart upload, and the objects get its `content_encoding`.
+///
+/// A destination that fails does not stop the others: `upload` returns the outcome of every
+/// destination, in the order they were added. An error of the source fails all of them.
+///
+/// The callback receives the size of the source times the number of destinations, the bytes sent
+/// so far over all destinations and the increase since the previous call, the destination
+/// callback the same values for a single destination. A destination that fails is taken out of
+/// the combined progress: its size and the bytes it had sent are subtracted, and the callback
+/// is called with an increase of `0`, so the combined progress reaches its total when the other
+/// destinations succeed. The callbacks of the source itself, set on the
+/// `TrackableBodyStream`, report the bytes read from the source.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::{FanOutUploader, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let primary = aws_sdk_s3::Client::new(&aws_config::from_env().load().await);
+/// let recovery_config = aws_config::from_env().region(aws_sdk_s3::Region::new("eu-west-1")).load().await;
+/// let recovery = aws_sdk_s3::Client::new(&recovery_config);
+///
+/// let body = TrackableBodyStream::from_path("./backup.tar").await?;
+/// let outcomes = FanOutUploader::new(body)
+///     .with_destination(primary, "bucket", "backup.tar")
+///     .with_destination(recovery, "recovery-bucket", "backup.tar")
+///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+///     .with_destination_callback(|index, total, sent, _| println!("#{}: {}/{} bytes", index, sent, total))
+///     .upload()
+///     .await;
+/// for (index, outcome) in outcomes.iter().enumerate() {
+///     if let Err(err) = outcome {
+///         println!("destination #{} failed: {}", index, err);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct FanOutUploader<I: BodySource> {
+    body: TrackableBodyStream<I>,
+    destinations: Vec<Destination>,
+    multipart_threshold: u64,
+    part_size: u64,
+    callback: Option<Arc<CallbackFn>>,
+    destination_callback: Option<Arc<DestinationCallbackFn>>,
+}
+
+impl<I: BodySource + Send> FanOutUploader<I> {
+    /// Prepares the upload of `body`, to no destination yet
+    pub fn new(body: TrackableBodyStream<I>) -> Self {
+        Self {
+            body,
+            destinations: Vec::new(),
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            part_size: MIN_PART_SIZE,
+            callback: None,
+            destination_callback: None,
+        }
+    }
+
+    /// Adds a destination, `bucket`/`key` with `client`. Its index, for the destination callback
+    /// and the outcomes of `upload`, is the number of destinations added before it.
+    pub fn with_destination(mut self, client: Client, bucket: impl Into<String>, key: impl Into<String>) -> Self {
+        let content_encoding = self.body.content_encoding();
+        self.destinations.push(Destination { client, bucket: bucket.into(), key: key.into(), content_encoding });
+        self
+    }
+
+    /// Sets the size from which the source is uploaded with multipart uploads, 16 MB by
+    /// default. Sources of unknown length always are.
+    pub fn with_multipart_threshold(mut self, threshold: u64) -> Self {
+        self.multipart_threshold = threshold;
+        self
+    }
+
+    /// Sets the size of the parts of multipart uploads, `MIN_PART_SIZE` by default. The size is
+    /// increased if a source of known length would need more than `MAX_PARTS` parts, a source
+    /// of unknown length must fit in `MAX_PARTS` parts of this size. Every destination holds a
+    /// part in memory while it uploads it.
+    pub fn with_part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Sets the callback of the combined progress of all the destinations
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback that receives the index of a destination followed by its total, the
+    /// bytes it has sent so far and the increase since the previous call
+    pub fn with_destination_callback(mut self, callback: impl Fn(usize, u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.destination_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Runs the uploads and returns the outcome of every destination
+    pub async fn upload(self) -> Vec<Result<(), TransferError>> {
+        let Self { body, destinations, multipart_threshold, part_size, callback, destination_callback } = self;
+        let len = body.content_length().map(|len| len as u64);
+        let multipart = len.is_none_or(|len| len >= multipart_threshold);
+        let part_size = part_size.max(len.unwrap_or(0).div_ceil(MAX_PARTS));
+        let combined = Arc::new(Combined { totals: Mutex::new((len.unwrap_or(0) * destinations.len() as u64, 0)), callback });
+
+        let mut senders = Vec::new();
+        let uploads = destinations.iter().enumerate().map(|(index, destination)| {
+            let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+            senders.push(Some(sender));
+            let reporter = Reporter {
+                index,
+                progress: Arc::new(AggregateProgress::new(len.unwrap_or(0), 0)),
+                reported: Default::default(),
+                combined: combined.clone(),
+                destination_callback: destination_callback.clone(),
+            };
+            async move {
+                let outcome = match multipart {
+                    true => upload_parts(destination, receiver, part_size, reporter.clone()).await,
+                    false => put_object(destination, receiver, len, reporter.clone()).await,
+                };
+                if outcome.is_err() {
+                    reporter.fail();
+                }
+                outcome
+            }
+        });
+        let uploads = futures::future::join_all(uploads.collect::<Vec<_>>());
+        let (_, outcomes) = futures::future::join(feed(body, senders), uploads).await;
+        outcomes
+    }
+}
+
+async fn upload_parts(
+    destination: &Destination,
+    mut receiver: mpsc::Receiver<io::Result<Bytes>>,
+    part_size: u64,
+    reporter: Reporter,
+) -> Result<(), TransferError> {
+    let Destination { client, bucket, key, content_encoding } = destination;
+    let created = client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .set_content_encoding(content_encoding.map(String::from))
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("CreateMultipartUpload", err))?;
+    let upload_id = created.upload_id().unwrap_or_default();
+
+    let mut completed = Vec::new();
+    let mut part = BytesMut::new();
+    let result = loop {
+        let ended = match receiver.next().await {
+            Some(Ok(frame)) => {
+                part.extend_from_slice(&frame);
+                false
+            }
+            Some(Err(err)) => break Err(err.into()),
+            None => true,
+        };
+        let full = part.len() as u64 >= part_size;
+        // a multipart upload has at least one part, the only one may be empty
+        let last = ended && (!part.is_empty() || completed.is_empty());
+        if full || last {
+            let number = completed.len() as i32 + 1;
+            match upload_part(client, bucket, key, upload_id, number, part.split().freeze(), &reporter).await {
+                Ok(e_tag) => completed.push(CompletedPart::builder().part_number(number).e_tag(e_tag).build()),
+                Err(err) => break Err(err),
+            }
+        }
+        if ended {
+            break Ok(());
+        }
+    };
+    if let Err(err) = result {
+        // the error of the part matters more than a failure to clean up after it
+        let _ = client.abort_multipart_upload().bucket(bucket).key(key).upload_id(upload_id).send().await;
+        return Err(err);
+    }
+
+    client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(completed)).build())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("CompleteMultipartUpload", err))?;
+    Ok(())
+}
+
+/// Hands every frame of the source to the destinations still uploading, or the error of the
+/// source to all of them. Each destination gets the frame in turn, waiting for room in its
+/// channel, which paces the source to the slowest destination.
+async fn feed<I: BodySource>(mut body: TrackableBodyStream<I>, mut senders: Vec<Option<mpsc::Sender<io::Result<Bytes>>>>) {
+    while let Some(frame) = body.next().await {
+        let frame = frame.map_err(io::Error::from);
+        for slot in &mut senders {
+            let Some(sender) = slot else {
+                continue;
+            };
+            let item = match &frame {
+                Ok(frame) => Ok(frame.clone()),
+                Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            };
+            // a destination that failed has dropped its receiver
+            if sender.send(item).await.is_err() {
+                *slot = None;
+            }
+        }
+        if frame.is_err() {
+            return;
+        }
+    }
+}
+
+async fn put_object(
+    destination: &Destination,
+    receiver: mpsc::Receiver<io::Result<Bytes>>,
+    len: Option<u64>,
+    reporter: Reporter,
+) -> Result<(), TransferError> {
+    let body = TrackableBodyStream::from_stream(receiver, len).with_callback(move |_, _, chunk| reporter.add(chunk));
+    destination
+        .client
+        .put_object()
+        .bucket(&destination.bucket)
+        .key(&destination.key)
+        .set_content_encoding(destination.content_encoding.map(String::from))
+        .set_content_length(body.content_length())
+        .body(body.to_s3_stream())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("PutObject", err))?;
+    Ok(())
+}
+
+async fn upload_part(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    number: i32,
+    bytes: Bytes,
+    reporter: &Reporter,
+) -> Result<String, TransferError> {
+    let len = bytes.len() as u64;
+    let reported = Arc::new(AtomicU64::new(0));
+    let (reporting, counter) = (reporter.clone(), reported.clone());
+    let mut body = TrackableBodyStream::new(Cursor::new(bytes), len).with_callback(move |_, _, chunk| {
+        counter.fetch_add(chunk, Ordering::SeqCst);
+        reporting.add(chunk);
+    });
+    let (retracting, counter) = (reporter.clone(), reported.clone());
+    body.set_retry_callback(move |_, _| retracting.retract(counter.swap(0, Ordering::SeqCst)));
+    let result = client
+        .upload_part()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .part_number(number)
+        .content_length(len as i64)
+        .body(body.to_retryable_s3_stream())
+        .send()
+        .await;
+    match result {
+        Ok(output) => Ok(output.e_tag().unwrap_or_default().to_string()),
+        Err(err) => {
+            // the bytes of the part will not be sent
+            reporter.retract(reported.load(Ordering::SeqCst));
+            Err(TransferError::s3("UploadPart", err))
+        }
+    }
+}
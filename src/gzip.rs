@@ -0,0 +1,256 @@
+/// The size of the window in which the encoder looks for repeated sequences, the largest
+/// distance deflate can refer back to
+const WINDOW: usize = 32 * 1024;
+
+/// The number of slots of the hash table of 3-byte sequences
+const HASH_SIZE: usize = 1 << 15;
+
+/// How many earlier occurrences of a sequence are compared before settling for the best match
+const MAX_CHAIN: usize = 64;
+
+/// The number of bytes of the source the encoder waits for before it encodes a block, so that
+/// small reads do not pay for the overhead of a block each
+const MIN_BLOCK: usize = 16 * 1024;
+
+/// The largest length of a stored block
+const MAX_STORED: usize = u16::MAX as usize;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// The header of a gzip member: no file name, no modification time, unknown operating system
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// A streaming gzip encoder. The source is buffered until there are at least 16 KB, which are
+/// compressed into a deflate block with the fixed Huffman codes, matching repeated sequences
+/// against the last 32 KB of the source, and emitted as soon as they have been encoded. A block
+/// that would not get smaller, such as already compressed data, is stored as it is instead.
+pub(crate) struct GzipEncoder {
+    /// The bytes of the source waiting to be encoded in the next block
+    pending: Vec<u8>,
+    /// The end of the source encoded so far, followed by the chunk being encoded
+    history: Vec<u8>,
+    /// The position in the source of the first byte of `history`
+    base: usize,
+    /// The last position of every hashed 3-byte sequence, `usize::MAX` for none
+    head: Vec<usize>,
+    /// The previous position with the same hash, indexed by position modulo the window size
+    prev: Vec<usize>,
+    bits: u64,
+    bit_count: u32,
+    crc: crc32fast::Hasher,
+    size: u32,
+    started: bool,
+}
+
+impl GzipEncoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            history: Vec::new(),
+            base: 0,
+            head: vec![usize::MAX; HASH_SIZE],
+            prev: vec![usize::MAX; WINDOW],
+            bits: 0,
+            bit_count: 0,
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            started: false,
+        }
+    }
+
+    /// Compresses a chunk of the source and returns the complete bytes of output it produced,
+    /// only the header until enough of the source has been buffered
+    pub(crate) fn encode(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER.len());
+        self.start(&mut out);
+        self.crc.update(chunk);
+        self.size = self.size.wrapping_add(chunk.len() as u32);
+        self.pending.extend_from_slice(chunk);
+        if self.pending.len() >= MIN_BLOCK {
+            let block = std::mem::take(&mut self.pending);
+            self.encode_block(&block, false, &mut out);
+        }
+        out
+    }
+
+    /// Ends the deflate stream and returns the rest of the output, followed by the gzip trailer
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER.len() + self.pending.len() + 16);
+        self.start(&mut out);
+        let block = std::mem::take(&mut self.pending);
+        self.encode_block(&block, true, &mut out);
+        if self.bit_count > 0 {
+            self.write_bits(0, 8 - self.bit_count % 8, &mut out);
+        }
+        out.extend_from_slice(&self.crc.clone().finalize().to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out
+    }
+
+    /// Writes `block` with the fixed codes, or stored as it is if that is shorter
+    fn encode_block(&mut self, block: &[u8], last: bool, out: &mut Vec<u8>) {
+        let (bits, bit_count) = (self.bits, self.bit_count);
+        let mut fixed = Vec::with_capacity(block.len() / 2);
+        self.write_fixed(block, last, &mut fixed);
+        let fixed_state = (self.bits, self.bit_count);
+
+        (self.bits, self.bit_count) = (bits, bit_count);
+        let mut stored = Vec::with_capacity(block.len() + 5 * (block.len() / MAX_STORED + 1));
+        self.write_stored(block, last, &mut stored);
+        if stored.len() * 8 + self.bit_count as usize <= fixed.len() * 8 + fixed_state.1 as usize {
+            out.extend_from_slice(&stored);
+        } else {
+            (self.bits, self.bit_count) = fixed_state;
+            out.extend_from_slice(&fixed);
+        }
+    }
+
+    /// Writes `block` as a block compressed with the fixed codes, and adds it to the history
+    fn write_fixed(&mut self, block: &[u8], last: bool, out: &mut Vec<u8>) {
+        self.write_bits(if last { 0b011 } else { 0b010 }, 3, out);
+        let start = self.history.len();
+        self.history.extend_from_slice(block);
+        let mut pos = start;
+        while pos < self.history.len() {
+            let (length, distance) = self.longest_match(pos);
+            if length >= MIN_MATCH {
+                self.write_match(length, distance, out);
+                for inserted in pos..pos + length {
+                    self.insert(inserted);
+                }
+                pos += length;
+            } else {
+                self.write_symbol(self.history[pos] as u16, out);
+                self.insert(pos);
+                pos += 1;
+            }
+        }
+        self.write_symbol(256, out);
+
+        if self.history.len() > WINDOW {
+            let discarded = self.history.len() - WINDOW;
+            self.history.drain(..discarded);
+            self.base += discarded;
+        }
+    }
+
+    /// Writes `block` as stored blocks of at most 65535 bytes
+    fn write_stored(&mut self, block: &[u8], last: bool, out: &mut Vec<u8>) {
+        // an empty block still needs a stored block of its own
+        let count = block.len().div_ceil(MAX_STORED).max(1);
+        for index in 0..count {
+            let piece = &block[index * MAX_STORED..((index + 1) * MAX_STORED).min(block.len())];
+            self.write_bits((last && index + 1 == count) as u64, 3, out);
+            if self.bit_count > 0 {
+                self.write_bits(0, 8 - self.bit_count % 8, out);
+            }
+            out.extend_from_slice(&(piece.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(!(piece.len() as u16)).to_le_bytes());
+            out.extend_from_slice(piece);
+        }
+    }
+
+    fn start(&mut self, out: &mut Vec<u8>) {
+        if !self.started {
+            out.extend_from_slice(&HEADER);
+            self.started = true;
+        }
+    }
+
+    fn hash(&self, pos: usize) -> Option<usize> {
+        let bytes = self.history.get(pos..pos + MIN_MATCH)?;
+        let value = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+        Some(value.wrapping_mul(2_654_435_761) >> 7 & (HASH_SIZE - 1))
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if let Some(hash) = self.hash(pos) {
+            let absolute = self.base + pos;
+            self.prev[absolute % WINDOW] = self.head[hash];
+            self.head[hash] = absolute;
+        }
+    }
+
+    /// Returns the length and the distance of the longest earlier sequence that matches the
+    /// bytes at `pos`, with a length of `0` if there is none
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        let Some(hash) = self.hash(pos) else {
+            return (0, 0);
+        };
+        let absolute = self.base + pos;
+        let limit = MAX_MATCH.min(self.history.len() - pos);
+        let (mut best_length, mut best_distance) = (0, 0);
+        let mut candidate = self.head[hash];
+        for _ in 0..MAX_CHAIN {
+            // the chain ends, or runs into a slot reused by a more recent position
+            if candidate == usize::MAX || candidate >= absolute || absolute - candidate > WINDOW {
+                break;
+            }
+            let earlier = candidate - self.base;
+            let length = self.history[earlier..]
+                .iter()
+                .zip(&self.history[pos..pos + limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                (best_length, best_distance) = (length, absolute - candidate);
+                if length == limit {
+                    break;
+                }
+            }
+            let next = self.prev[candidate % WINDOW];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        (best_length, best_distance)
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize, out: &mut Vec<u8>) {
+        let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+        self.write_symbol(257 + code as u16, out);
+        self.write_bits((length - LENGTH_BASE[code] as usize) as u64, LENGTH_EXTRA[code] as u32, out);
+        let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+        self.write_bits(reverse(code as u32, 5), 5, out);
+        self.write_bits((distance - DISTANCE_BASE[code] as usize) as u64, DISTANCE_EXTRA[code] as u32, out);
+    }
+
+    /// Writes a literal, a length or the end of block with the fixed Huffman codes of deflate
+    fn write_symbol(&mut self, symbol: u16, out: &mut Vec<u8>) {
+        let symbol = symbol as u32;
+        let (code, len) = match symbol {
+            0..=143 => (0x30 + symbol, 8),
+            144..=255 => (0x190 + symbol - 144, 9),
+            256..=279 => (symbol - 256, 7),
+            _ => (0xc0 + symbol - 280, 8),
+        };
+        self.write_bits(reverse(code, len), len, out);
+    }
+
+    fn write_bits(&mut self, value: u64, count: u32, out: &mut Vec<u8>) {
+        self.bits |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+}
+
+/// Huffman codes are packed starting from their most significant bit
+fn reverse(code: u32, len: u32) -> u64 {
+    (code.reverse_bits() >> (32 - len)) as u64
+}
@@ -1,146 +1,3061 @@
-use std::{path::PathBuf, task::Poll};
+use std::{collections::{HashMap, VecDeque}, future::Future, io::{Cursor, SeekFrom, Write}, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}, pin::Pin, sync::{Arc, Mutex}, task::{ready, Poll}, time::{Duration, Instant}};
 
-use aws_sdk_s3::types::ByteStream;
-use aws_smithy_http::body::SdkBody;
-use futures::{Stream, Future};
-use hyper::body::Bytes;
-use tokio::{fs::File, io::AsyncReadExt};
+use aws_sdk_s3::{client::fluent_builders::PutObject, output::GetObjectOutput, types::ByteStream};
+use bytes::BytesMut;
+use futures::{channel::mpsc, future::BoxFuture, Stream, StreamExt};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeek, AsyncSeekExt}, sync::{broadcast, watch}, time::{MissedTickBehavior, Sleep}};
+use tokio_util::sync::CancellationToken;
+
+mod body;
+mod builder;
+mod checkpoint;
+mod checksum;
+mod copy;
+#[cfg(target_os = "linux")]
+mod direct;
+mod directory;
+mod download;
+mod encryption;
+mod error;
+mod etag;
+mod fanout;
+mod gzip;
+mod manager;
+#[cfg(unix)]
+mod mmap;
+mod multipart;
+mod observer;
+mod pause;
+mod presigned;
+mod progress;
+mod ranged;
+mod retry;
+mod sink;
+mod source;
+mod tar;
+mod tempfile;
+mod throttle;
+mod transform;
+mod upload;
+mod writer;
+
+/// The buffer type of the frames emitted by the streams of this crate, `bytes::Bytes` from
+/// version 1 of the `bytes` crate. It is re-exported here so that consumers can name it without
+/// depending on `bytes` themselves: it will remain the `bytes` 1.x type across upgrades of the
+/// SDK and of `hyper`.
+///
+/// ```
+/// use futures::TryStreamExt;
+/// use trackable_s3_stream::{Bytes, TrackableBodyStream};
+/// # futures::executor::block_on(async {
+/// let data = [1u8; 10];
+/// let frames: Vec<Bytes> = TrackableBodyStream::from(&data[..]).try_collect().await.unwrap();
+/// assert_eq!(frames, vec![Bytes::from_static(&[1u8; 10])]);
+/// # });
+/// ```
+pub use bytes::Bytes;
+
+pub use builder::TrackableBodyStreamBuilder;
+pub use checkpoint::{CheckpointPart, MultipartCheckpoint};
+pub use checksum::ChecksumAlgorithm;
+pub use copy::copy_object_tracked;
+#[cfg(target_os = "linux")]
+pub use direct::DirectFile;
+pub use directory::DirectoryUploader;
+pub use download::{download_object, HttpBodyStream, TrackableByteStream};
+pub use encryption::{Encryption, DEFAULT_SEGMENT_SIZE};
+pub use error::{TimeoutOr, TrackableStreamError, TransferError};
+pub use etag::{ETagOutput, ExpectedETag};
+pub use fanout::FanOutUploader;
+pub use manager::{TransferHandle, TransferManager};
+#[cfg(unix)]
+pub use mmap::MappedFile;
+pub use multipart::{MultipartUploader, ParallelUploadTracker, MAX_PARTS, MIN_PART_SIZE};
+pub use observer::{ProgressObserver, StreamObserver};
+pub use pause::PauseHandle;
+pub use presigned::{PresignedMultipartUploader, PresignedPart};
+pub use progress::{Progress, ProgressHandle, ReadBenchmark};
+pub use ranged::MultipartDownloader;
+pub use sink::ProgressSink;
+pub use source::{BodySource, Chain, Chunks, Segment, StreamSource};
+pub use tar::TarArchive;
+pub use tempfile::TempFileFailurePolicy;
+pub use transform::Compression;
+pub use throttle::{Clock, SystemClock, Throttle};
+pub use upload::{pipe_object, put_object_if_changed, put_object_with_checksum_tag, ConditionalUpload};
+pub use writer::TrackableWriter;
+
+use checksum::Hasher;
+use progress::{estimate_remaining, RateTracker};
+use tempfile::TempFile;
+use transform::Transform;
 
 const DEFAULT_BUFFER_SIZE: usize = 2048;
 
+/// The largest source, in bytes, that `into_memory_cached` accepts
+pub const MEMORY_CACHE_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// The user metadata key used by `object_metadata` for the base64 encoded SHA-256 of the source
+pub const METADATA_SHA256_KEY: &str = "x-content-sha256";
+/// The user metadata key used by `object_metadata` for the length of the source in bytes
+pub const METADATA_LENGTH_KEY: &str = "x-content-length";
+
 /// The callback function triggered every time a chunck of the source file is read
 /// in the buffer.
 /// 
 /// # Arguments
-/// * `u64`: The total length of the buffer (or size of the file if created from a `PathBuf`)
+/// * `u64`: The total length of the buffer (or size of the file if created from a path), `0`
+///   when the length is unknown
 /// * `u64`: The total number of bytes read so far
 /// * `u64`: The number of bytes read in the current chunck
 type CallbackFn = dyn Fn(u64, u64, u64) + Sync + Send + 'static;
 
+/// The callback of a `TrackableBodyStream`, with the same arguments as `CallbackFn`. The stream
+/// only calls it from `poll_next`, through a mutable reference, so it can keep state of its own.
+type CallbackMutFn = dyn FnMut(u64, u64, u64) + Send + 'static;
+
+/// The callback set with `set_progress_callback`, receiving a `Progress` with the throughput
+/// and the estimated time remaining.
+type ProgressCallbackFn = dyn FnMut(&Progress) + Send + 'static;
+
+/// The callback set with `set_async_callback`, with the same arguments as `CallbackFn`. The
+/// stream awaits the returned future before it reads or emits anything else.
+type AsyncCallbackFn = dyn Fn(u64, u64, u64) -> BoxFuture<'static, ()> + Sync + Send + 'static;
+
+/// The callback set with `set_stall_callback`, receiving the number of bytes read so far and
+/// how long the stream has been idle.
+type StallCallbackFn = dyn Fn(u64, Duration) + Sync + Send + 'static;
+
+/// The callback set with `set_retry_callback`, receiving the number of the attempt, `1` for the
+/// first retry, and the number of bytes read by the previous attempt.
+type RetryCallbackFn = dyn Fn(u32, u64) + Sync + Send + 'static;
+
+/// The callback set with `set_checksum_callback`: the same arguments as `CallbackFn`, followed
+/// by the base64 encoded checksum of the bytes read so far.
+type ChecksumCallbackFn = dyn Fn(u64, u64, u64, &str) + Sync + Send + 'static;
+
+/// The callback set with `set_delta_callback`, receiving the number of bytes read in the
+/// current chunk and the total length of the source.
+type DeltaCallbackFn = dyn Fn(u64, u64) + Sync + Send + 'static;
+
+/// The callback set with `set_block_callback`, receiving the number of blocks sent so far and
+/// the total number of blocks of the source.
+type BlockCallbackFn = dyn Fn(u64, u64) + Sync + Send + 'static;
+
+/// The callback set with `set_estimated_progress_callback`, receiving the estimated length of
+/// the encoded body, the bytes emitted so far and the estimated percentage.
+type EstimateCallbackFn = dyn Fn(u64, u64, f64) + Sync + Send + 'static;
+
+/// Controls what a `TrackableBodyStream` does when the progress callback panics.
+///
+/// `Ignore` and `Abort` catch the panic with `std::panic::catch_unwind`. The callback is
+/// treated as unwind safe, so after a caught panic any state it captured may be left
+/// inconsistent: with `Ignore` the same callback keeps being invoked for the following chunks.
+/// Panics are only caught when the crate is built with `panic = "unwind"`, and the panic hook
+/// still runs, printing the panic message to stderr by default.
+///
+/// # Examples
+/// ```
+/// # use futures::StreamExt;
+/// # use trackable_s3_stream::{CallbackPanicPolicy, TrackableBodyStream, TrackableStreamError};
+/// # futures::executor::block_on(async {
+/// let data = [0u8; 4096];
+///
+/// let mut body = TrackableBodyStream::from(&data[..]);
+/// body.set_callback(|_, _, _| panic!("broken progress bar"));
+/// body.set_panic_policy(CallbackPanicPolicy::Abort);
+/// assert!(matches!(body.next().await, Some(Err(TrackableStreamError::CallbackPanicked(_)))));
+/// // the stream has ended, the callback is not called again
+/// assert!(body.next().await.is_none());
+///
+/// let mut body = TrackableBodyStream::from(&data[..]);
+/// body.set_callback(|_, _, _| panic!("broken progress bar"));
+/// body.set_panic_policy(CallbackPanicPolicy::Ignore);
+/// assert_eq!(body.next().await.unwrap().unwrap().len(), 2048);
+/// # });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CallbackPanicPolicy {
+    /// The panic unwinds through `poll_next` into the code polling the stream. This is the default.
+    #[default]
+    Propagate,
+    /// The panic is caught and discarded, the stream carries on as if the callback had returned
+    Ignore,
+    /// The panic is caught and the stream returns a `TrackableStreamError::CallbackPanicked` error
+    Abort,
+}
+
 /// A `futures::Stream` implementation that can be used to track uploads to S3. As the S3 client
 /// reads data from the stream it triggers a callback that can be used to update a UI.
 /// 
-/// A `TrackableBodyStream` can be constructed from a `PathBuf` with the `try_from` implementation
-/// and from a `&[u8]`.
+/// A `TrackableBodyStream` can be constructed from a path with `from_path`, from a `&[u8]`, from
+/// a list of `Bytes` buffers with `from_chunks`, from a stream of buffers with `from_stream`, and
+/// from any `tokio::io::AsyncRead` of known length with `new`. Other sources can be used by
+/// implementing the `BodySource` trait.
+///
+/// A stream is single-use: every conversion, such as `to_s3_stream` or `into_memory_cached`,
+/// consumes it. To upload the same source again create a new stream or, for seekable sources,
+/// call `reset` before converting it.
 /// 
 /// # Examples
-/// ```
-/// let mut body = TrackableBodyStream::try_from(PathBuf::from("./examples/sample.jpeg"))?;
-/// let bar = ProgressBar::new(body.content_length() as u64);
+/// ```no_run
+/// # use indicatif::ProgressBar;
+/// # use trackable_s3_stream::TrackableBodyStream;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), std::io::Error> {
+/// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+/// let bar = ProgressBar::new(body.content_length().unwrap_or(0) as u64);
 ///    
 /// body.set_callback(move |tot_size: u64, sent: u64, cur_buf: u64| {
-///    bar.inc(cur_buf as u64);
+///    bar.inc(cur_buf);
 ///    if sent == tot_size {
 ///        bar.finish();
 ///    }
 ///});
 /// // this object can be passed to the put_object method of the AWS SDK for Rust.
 /// let s3_stream = body.to_s3_stream();
+/// # Ok(())
+/// # }
 /// ```
-pub struct TrackableBodyStream<I: AsyncReadExt + Unpin> {
+pub struct TrackableBodyStream<I: BodySource> {
     input: I,
-    file_size: u64,
+    file_size: Option<u64>,
     cur_read: u64,
-    callback: Option<Box<CallbackFn>>,
+    callback: Option<Mutex<Box<CallbackMutFn>>>,
+    progress_callback: Option<Mutex<Box<ProgressCallbackFn>>>,
+    rates: RateTracker,
+    callback_interval: Option<Duration>,
+    callback_every_bytes: u64,
+    last_reported_sent: u64,
+    last_reported_at: Option<Instant>,
+    checksum_callback: Option<Box<ChecksumCallbackFn>>,
+    async_callback: Option<Box<AsyncCallbackFn>>,
+    pending_callback: Option<Mutex<BoxFuture<'static, ()>>>,
+    delta_callback: Option<Box<DeltaCallbackFn>>,
+    /// The ratio of the encoded length to the source length, and the callback it is reported to
+    estimate_callback: Option<(f64, Box<EstimateCallbackFn>)>,
+    block_size: u64,
+    block_callback: Option<Box<BlockCallbackFn>>,
+    blocks_reported: u64,
+    progress_senders: Vec<mpsc::UnboundedSender<Progress>>,
+    progress_broadcasts: Vec<broadcast::Sender<Progress>>,
     buffer_size: usize,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    hasher: Option<Hasher>,
+    checksum: Option<(ChecksumAlgorithm, String)>,
+    observer: Option<Arc<dyn StreamObserver>>,
+    observers: Vec<Mutex<Box<dyn ProgressObserver>>>,
+    min_final_frame: usize,
+    read_ahead: usize,
+    frames: VecDeque<Bytes>,
+    eof: bool,
+    done: bool,
+    progress_writer: Option<Mutex<Box<dyn Write + Send>>>,
+    tee: Option<Mutex<Box<dyn Write + Send>>>,
+    /// The bytes written to the tee, over all attempts
+    teed: u64,
+    panic_policy: CallbackPanicPolicy,
+    transform: Option<Transform>,
+    alignment: Option<usize>,
+    temp_file: Option<TempFile>,
+    progress_sinks: Vec<Box<dyn ProgressSink>>,
+    deadline: Option<Instant>,
+    deadline_timer: Option<Pin<Box<Sleep>>>,
+    cancellation: Option<Mutex<BoxFuture<'static, ()>>>,
+    pause: Option<PauseHandle>,
+    stall_timeout: Option<Duration>,
+    stall_callback: Option<(Duration, Box<StallCallbackFn>)>,
+    stall_reported: bool,
+    last_activity: Option<tokio::time::Instant>,
+    stall_timer: Option<Pin<Box<Sleep>>>,
+    retry_callback: Option<Box<RetryCallbackFn>>,
+    emit_eof_marker: bool,
+    id: Option<Arc<str>>,
+    shared_checksum: Option<Arc<Mutex<Option<String>>>>,
+    e_tag: Option<Arc<Mutex<ExpectedETag>>>,
+    read_buffer: BytesMut,
+    fill_buffer: bool,
+    /// The bytes of a chunk being filled up to the buffer size with `set_fill_buffer`
+    partial: BytesMut,
+    emitted: u64,
+    throttle: Option<Throttle>,
+    throttle_timer: Option<Pin<Box<Sleep>>>,
+    started: Option<Instant>,
+    status_senders: Vec<mpsc::UnboundedSender<String>>,
+    failure: Option<TrackableStreamError>,
+    progress_watch: Option<watch::Sender<Progress>>,
+    progress_handle: ProgressHandle,
+}
+
+/// Invokes a user callback according to the `CallbackPanicPolicy`
+fn run_callback(policy: CallbackPanicPolicy, callback: impl FnOnce()) -> Result<(), TrackableStreamError> {
+    if policy == CallbackPanicPolicy::Propagate {
+        callback();
+        return Ok(());
+    }
+    let res = panic::catch_unwind(AssertUnwindSafe(callback));
+    if let (Err(payload), CallbackPanicPolicy::Abort) = (res, policy) {
+        let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        return Err(TrackableStreamError::CallbackPanicked(message));
+    }
+    Ok(())
+}
+
+fn validate_alignment(alignment: usize) -> std::io::Result<()> {
+    if !alignment.is_power_of_two() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("alignment must be a power of two, got {}", alignment),
+        ));
+    }
+    Ok(())
 }
 
+/// Deprecated: opens the file and reads its metadata with blocking calls on the current thread.
+/// Use `TrackableBodyStream::from_path` from async code instead.
 impl TryFrom<PathBuf> for TrackableBodyStream<File> {
     type Error = std::io::Error;
 
     fn try_from(value: PathBuf) -> Result<Self, Self::Error> {
-        let file_size = std::fs::metadata(value.clone())?.len();
-        let file = futures::executor::block_on(tokio::fs::File::open(value))?;
-        Ok(Self {
-            input: file, 
-            file_size,
-            cur_read: 0,
-            callback: None,
-            buffer_size: DEFAULT_BUFFER_SIZE,
-        })
+        let file = std::fs::File::open(value)?;
+        let file_size = file.metadata()?.len();
+        Ok(Self::with_input(File::from_std(file), file_size))
+    }
+}
+
+impl TrackableBodyStream<File> {
+    /// Returns a builder that opens a file and configures the stream in one expression, see
+    /// `TrackableBodyStreamBuilder`
+    pub fn builder() -> TrackableBodyStreamBuilder {
+        TrackableBodyStreamBuilder::default()
+    }
+
+    /// Opens the file at `path` and reads its size, without blocking the runtime. This
+    /// replaces the `TryFrom<PathBuf>` implementation, which blocks the current thread.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), std::io::Error> {
+    /// let body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// assert_eq!(body.content_length(), Some(std::fs::metadata("./examples/sample.jpeg")?.len() as i64));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::open(path).await?;
+        let file_size = file.metadata().await?.len();
+        Ok(Self::with_input(file, file_size))
+    }
+
+    /// Opens a file staged for upload, such as a temporary file, that is deleted once the stream
+    /// has read it to the end. Deletion happens as soon as the last byte has been handed to the
+    /// HTTP client, before S3 confirms the object, so a request that fails afterwards cannot be
+    /// retried from the same file. If the stream fails or is dropped before reaching the end of
+    /// the file, the `TempFileFailurePolicy` set with `set_tempfile_failure_policy` decides
+    /// whether the file is kept, the default, or deleted. On Windows, where open files cannot be
+    /// deleted, removal is attempted anyway and failures are logged.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let path = std::env::temp_dir().join("trackable_s3_stream_staged_upload");
+    /// std::fs::write(&path, vec![7u8; 10_000])?;
+    ///
+    /// let mut body = TrackableBodyStream::from_tempfile(&path).await?;
+    /// while let Some(chunk) = body.next().await {
+    ///     chunk?;
+    /// }
+    /// assert!(!path.exists());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_tempfile(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = File::open(&path).await?;
+        let file_size = file.metadata().await?.len();
+        let mut stream = Self::with_input(file, file_size);
+        stream.temp_file = Some(TempFile::new(path));
+        Ok(stream)
+    }
+
+    /// Sets what happens to the file of a stream created with `from_tempfile` when the upload
+    /// fails. Has no effect on other streams.
+    pub fn set_tempfile_failure_policy(&mut self, policy: TempFileFailurePolicy) {
+        if let Some(temp_file) = &mut self.temp_file {
+            temp_file.policy = policy;
+        }
+    }
+}
+
+impl TrackableBodyStream<Chunks> {
+    /// Creates a stream over a list of buffers, for scatter-gather uploads of data that is
+    /// already in memory. The buffers are not copied or concatenated: every frame the stream
+    /// emits is a slice of one of the buffers sharing its memory. Buffers larger than the buffer
+    /// size are split into several frames, smaller ones are emitted as they are.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::{Bytes, TrackableBodyStream};
+    /// # futures::executor::block_on(async {
+    /// let chunks = vec![Bytes::from(vec![1u8; 3000]), Bytes::from(vec![2u8; 100])];
+    /// let mut body = TrackableBodyStream::from_chunks(chunks.clone());
+    /// body.set_buffer_size(2048);
+    /// assert_eq!(body.content_length(), Some(3100));
+    ///
+    /// let frames: Vec<_> = body.try_collect().await.unwrap();
+    /// let sizes: Vec<_> = frames.iter().map(|frame| frame.len()).collect();
+    /// assert_eq!(sizes, vec![2048, 952, 100]);
+    /// // no frame was reallocated, they all point into the original buffers
+    /// assert_eq!(frames[0].as_ptr(), chunks[0].as_ptr());
+    /// assert_eq!(frames[1].as_ptr(), chunks[0][2048..].as_ptr());
+    /// assert_eq!(frames[2].as_ptr(), chunks[1].as_ptr());
+    /// # });
+    /// ```
+    pub fn from_chunks(chunks: impl IntoIterator<Item = Bytes>) -> Self {
+        let chunks = Chunks::new(chunks);
+        let length = chunks.len();
+        Self::with_input(chunks, length)
+    }
+}
+
+impl TrackableBodyStream<Chain> {
+    /// Concatenates several segments, such as files, slices and buffers, into a single body,
+    /// for example to assemble an object from pre-generated segments without writing them to a
+    /// temporary file first. The content length is the sum of the lengths of the segments and
+    /// the progress callback reports the bytes read over all of them, as for a single source.
+    /// Each segment is read up to its length, a segment that ends earlier fails the stream.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::{Bytes, Segment, TrackableBodyStream};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let segments = vec![
+    ///     Segment::from(&b"header,"[..]),
+    ///     Segment::from(Bytes::from("body,")),
+    ///     Segment::new(&b"footer and more"[..], 6),
+    /// ];
+    /// let mut body = TrackableBodyStream::chain(segments);
+    /// body.set_callback(|total, sent, _| assert!(sent <= total));
+    /// assert_eq!(body.content_length(), Some(18));
+    ///
+    /// let frames: Vec<_> = body.try_collect().await?;
+    /// assert_eq!(frames.concat(), b"header,body,footer");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// ```no_run
+    /// # use trackable_s3_stream::{Segment, TrackableBodyStream};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    ///
+    /// let mut segments = Vec::new();
+    /// for index in 0..4 {
+    ///     segments.push(Segment::from_path(format!("./segments/{}.ts", index)).await?);
+    /// }
+    /// let body = TrackableBodyStream::chain(segments)
+    ///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total));
+    /// client.put_object().bucket("bucket").key("video.ts").body(body.to_s3_stream()).send().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn chain(segments: impl IntoIterator<Item = Segment>) -> Self {
+        let chain = Chain::new(segments);
+        let len = chain.len();
+        Self::with_input(chain, len)
+    }
+}
+
+impl<S, E> TrackableBodyStream<StreamSource<S>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    /// Wraps a stream of buffers, such as the output of a compression pipeline or the body of
+    /// another HTTP response, and reports progress between its chunks. `len` is the total number
+    /// of bytes the stream produces, `None` when it is not known in advance. Buffers are passed
+    /// through without copying, split only when they are larger than the buffer size. An error
+    /// of the stream fails the body with a `TrackableStreamError::Io` error, after which the body
+    /// ends.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::convert::Infallible;
+    /// # use futures::{StreamExt, TryStreamExt};
+    /// # use trackable_s3_stream::{Bytes, TrackableBodyStream};
+    /// # futures::executor::block_on(async {
+    /// let chunks = futures::stream::iter(vec![Bytes::from("hello "), Bytes::from("world")]);
+    /// let mut body = TrackableBodyStream::from_stream(chunks.map(Ok::<_, Infallible>), Some(11));
+    /// body.set_callback(|total, sent, _| assert!(sent <= total));
+    ///
+    /// let frames: Vec<_> = body.try_collect().await.unwrap();
+    /// assert_eq!(frames.concat(), b"hello world");
+    /// # });
+    /// ```
+    ///
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{Bytes, TrackableBodyStream, TrackableStreamError};
+    /// # futures::executor::block_on(async {
+    /// let chunks = futures::stream::iter(vec![
+    ///     Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")),
+    ///     Ok(Bytes::from("never read")),
+    /// ]);
+    /// let mut body = TrackableBodyStream::from_stream(chunks, None);
+    ///
+    /// assert!(matches!(body.next().await, Some(Err(TrackableStreamError::Io { bytes_read: 0, .. }))));
+    /// assert!(body.next().await.is_none());
+    /// # });
+    /// ```
+    pub fn from_stream(stream: S, len: Option<u64>) -> Self {
+        let mut body = Self::with_input(StreamSource::new(stream), len.unwrap_or(0));
+        body.file_size = len;
+        body
+    }
+}
+
+impl TrackableBodyStream<StreamSource<ByteStream>> {
+    /// Wraps the body of a `get_object` response as the body of another request, to copy an
+    /// object between buckets or accounts through this machine without staging it on disk. The
+    /// length of the body is the `content_length` of the response, so the callback covers the
+    /// whole transfer: every chunk is reported as it is handed to the upload, which is also when
+    /// it has been downloaded. See `pipe_object` for the complete transfer.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    /// let output = client.get_object().bucket("source-bucket").key("sample.jpeg").send().await?;
+    ///
+    /// let body = TrackableBodyStream::from_get_object(output)
+    ///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total));
+    /// client
+    ///     .put_object()
+    ///     .bucket("bucket")
+    ///     .key("sample.jpeg")
+    ///     .set_content_length(body.content_length())
+    ///     .body(body.to_s3_stream())
+    ///     .send()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_get_object(output: GetObjectOutput) -> Self {
+        let len = Some(output.content_length()).filter(|length| *length >= 0).map(|length| length as u64);
+        Self::from_stream(output.body, len)
+    }
+}
+
+impl TrackableBodyStream<TarArchive> {
+    /// Creates a stream over a tar archive built on the fly, see `TarArchive`, with the length
+    /// of the archive as the content length. The callback of the stream counts the bytes of the
+    /// archive, the callback of the archive the bytes of the files.
+    pub fn from_tar(archive: TarArchive) -> Self {
+        let len = archive.content_length();
+        Self::with_input(archive, len)
     }
 }
 
 impl<'inputlife> From<&'inputlife [u8]> for TrackableBodyStream<&'inputlife [u8]> {
     fn from(value: &'inputlife [u8]) -> Self {
         let length = value.len();
-        Self{
-            input: value,
-            file_size: length as u64,
+        Self::with_input(value, length as u64)
+    }
+}
+
+impl<I: BodySource> TrackableBodyStream<I> {
+    fn with_input(input: I, file_size: u64) -> Self {
+        let (progress_watch, _) = watch::channel(Progress { total: file_size, ..Default::default() });
+        Self {
+            input,
+            file_size: Some(file_size),
             cur_read: 0,
             callback: None,
+            progress_callback: None,
+            rates: RateTracker::default(),
+            callback_interval: None,
+            callback_every_bytes: 0,
+            last_reported_sent: 0,
+            last_reported_at: None,
+            checksum_callback: None,
+            async_callback: None,
+            pending_callback: None,
+            delta_callback: None,
+            estimate_callback: None,
+            block_size: 0,
+            block_callback: None,
+            blocks_reported: 0,
+            progress_senders: Vec::new(),
+            progress_broadcasts: Vec::new(),
             buffer_size: DEFAULT_BUFFER_SIZE,
+            checksum_algorithm: None,
+            hasher: None,
+            checksum: None,
+            observer: None,
+            observers: Vec::new(),
+            min_final_frame: 0,
+            read_ahead: 0,
+            frames: VecDeque::new(),
+            eof: false,
+            done: false,
+            progress_writer: None,
+            tee: None,
+            teed: 0,
+            panic_policy: CallbackPanicPolicy::default(),
+            transform: None,
+            alignment: None,
+            temp_file: None,
+            progress_sinks: Vec::new(),
+            deadline: None,
+            deadline_timer: None,
+            cancellation: None,
+            pause: None,
+            stall_timeout: None,
+            stall_callback: None,
+            stall_reported: false,
+            last_activity: None,
+            stall_timer: None,
+            retry_callback: None,
+            emit_eof_marker: false,
+            id: None,
+            shared_checksum: None,
+            e_tag: None,
+            read_buffer: BytesMut::new(),
+            fill_buffer: false,
+            partial: BytesMut::new(),
+            emitted: 0,
+            throttle: None,
+            throttle_timer: None,
+            started: None,
+            status_senders: Vec::new(),
+            failure: None,
+            progress_watch: Some(progress_watch),
+            progress_handle: ProgressHandle::new(file_size),
         }
     }
-}
 
-impl<I: AsyncReadExt + Unpin + Send + Sync + 'static> TrackableBodyStream<I> {
+    /// Sets an id for this stream, included in every `Progress` update it produces and in the
+    /// lines written by `set_progress_writer`. When several streams report to the same consumer
+    /// the id tells which stream an update comes from. Streams have no id by default.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let (photo, video) = ([0u8; 100], [0u8; 200]);
+    /// let mut first = TrackableBodyStream::from(&photo[..]);
+    /// first.set_id("photo.jpeg");
+    /// let mut second = TrackableBodyStream::from(&video[..]);
+    /// second.set_id("video.mp4");
+    ///
+    /// let updates = futures::stream::select(first.progress_stream(), second.progress_stream());
+    /// while first.next().await.is_some() {}
+    /// while second.next().await.is_some() {}
+    ///
+    /// let mut ids: Vec<_> = updates.map(|progress| progress.id.unwrap().to_string()).collect().await;
+    /// ids.sort();
+    /// assert_eq!(ids, vec!["photo.jpeg", "video.mp4"]);
+    /// # });
+    /// ```
+    pub fn set_id(&mut self, id: impl Into<String>) {
+        let id: Arc<str> = Arc::from(id.into());
+        if let Some(progress_watch) = &self.progress_watch {
+            progress_watch.send_modify(|progress| progress.id = Some(id.clone()));
+        }
+        self.id = Some(id);
+    }
+
+    /// Returns the id set with `set_id`
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Sets a `StreamObserver` that receives low level events from `poll_next`. When no
+    /// observer is set the instrumentation has no cost.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{StreamObserver, TrackableBodyStream};
+    /// #[derive(Default)]
+    /// struct Recorder(Mutex<Vec<String>>);
+    ///
+    /// impl StreamObserver for Recorder {
+    ///     fn on_read_complete(&self, bytes: usize) {
+    ///         self.0.lock().unwrap().push(format!("read {}", bytes));
+    ///     }
+    ///     fn on_emit(&self, len: usize) {
+    ///         self.0.lock().unwrap().push(format!("emit {}", len));
+    ///     }
+    ///     fn on_complete(&self) {
+    ///         self.0.lock().unwrap().push("complete".to_string());
+    ///     }
+    /// }
+    ///
+    /// # futures::executor::block_on(async {
+    /// let data = [1u8; 3000];
+    /// let recorder = Arc::new(Recorder::default());
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.set_observer(recorder.clone());
+    /// while body.next().await.is_some() {}
+    ///
+    /// assert_eq!(
+    ///     *recorder.0.lock().unwrap(),
+    ///     vec!["read 2048", "emit 2048", "read 952", "emit 952", "read 0", "complete"]
+    /// );
+    /// # });
+    /// ```
+    pub fn set_observer(&mut self, observer: Arc<dyn StreamObserver>) {
+        self.observer = Some(observer);
+    }
+
+    /// Registers a `ProgressObserver`, in addition to the callback and to the observers added
+    /// before. Closures taking the arguments of the callback can be added directly.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let (chunks, bytes) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    /// let (chunk_counter, byte_counter) = (chunks.clone(), bytes.clone());
+    ///
+    /// let data = [0u8; 5000];
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.add_observer(move |_, _, _| { chunk_counter.fetch_add(1, Ordering::SeqCst); });
+    /// body.add_observer(move |_, _, chunk| { byte_counter.fetch_add(chunk, Ordering::SeqCst); });
+    /// body.add_observer(|total, sent, _| println!("{}/{}", sent, total));
+    /// while body.next().await.is_some() {}
+    ///
+    /// assert_eq!(chunks.load(Ordering::SeqCst), 3);
+    /// assert_eq!(bytes.load(Ordering::SeqCst), 5000);
+    /// # });
+    /// ```
+    pub fn add_observer(&mut self, observer: impl ProgressObserver + 'static) {
+        self.observers.push(Mutex::new(Box::new(observer)));
+    }
+
+    /// Computes a checksum of the bytes as they are read from the source. The value is available
+    /// from the `checksum` method once the stream has been read to the end.
+    pub fn set_checksum_algorithm(&mut self, algorithm: ChecksumAlgorithm) {
+        self.checksum_algorithm = Some(algorithm);
+        self.hasher = Some(Hasher::new(algorithm));
+        self.checksum = None;
+    }
+
+    /// Returns the algorithm and the base64 encoded value of the checksum of the source, if
+    /// it has been computed either by streaming the whole source with `set_checksum_algorithm`
+    /// or with `precompute_checksum`.
+    pub fn checksum(&self) -> Option<(ChecksumAlgorithm, &str)> {
+        self.checksum.as_ref().map(|(algorithm, value)| (*algorithm, value.as_str()))
+    }
+
+    /// Returns the base64 encoded MD5 digest of the source, the value of the `Content-MD5` header
+    /// of `put_object`, once it has been computed with `ChecksumAlgorithm::Md5`. Since headers
+    /// are sent before the body, an upload that sets `content_md5` needs the digest from
+    /// `precompute_checksum`. A digest computed while streaming with `set_checksum_algorithm` is
+    /// only available after the upload, for example to compare it with the ETag.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use trackable_s3_stream::{ChecksumAlgorithm, TrackableBodyStream};
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let mut body = TrackableBodyStream::new(Cursor::new(b"hello world".to_vec()), 11);
+    /// body.precompute_checksum(ChecksumAlgorithm::Md5).await?;
+    /// assert_eq!(body.content_md5_base64(), Some("XrY7u+Ae7tCTyyK7j1rNww=="));
+    /// // client.put_object().content_md5(body.content_md5_base64().unwrap()) ...
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn content_md5_base64(&self) -> Option<&str> {
+        match self.checksum() {
+            Some((ChecksumAlgorithm::Md5, value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets the checksum of the source on a `put_object` request so that S3 rejects an upload
+    /// that was corrupted on the way: the matching `x-amz-checksum-*` header for the S3
+    /// additional checksum algorithms, `Content-MD5` for `ChecksumAlgorithm::Md5`. The checksum
+    /// must have been computed before the request is sent, with `precompute_checksum`. Nothing
+    /// is set when the body is encoded, since S3 checks the bytes as sent.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::{ChecksumAlgorithm, TrackableBodyStream};
+    /// # async fn upload(client: aws_sdk_s3::Client) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// body.precompute_checksum(ChecksumAlgorithm::Crc32c).await?;
+    /// let request = body.apply_checksum(client.put_object()).bucket("bucket").key("sample.jpeg");
+    /// request.set_content_length(body.content_length()).body(body.to_s3_stream()).send().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn apply_checksum(&self, request: PutObject) -> PutObject {
+        let Some((algorithm, value)) = self.checksum().filter(|_| self.transform.is_none()) else {
+            return request;
+        };
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => request.checksum_sha256(value),
+            ChecksumAlgorithm::Crc32c => request.checksum_crc32_c(value),
+            ChecksumAlgorithm::Crc32 => request.checksum_crc32(value),
+            ChecksumAlgorithm::Sha1 => request.checksum_sha1(value),
+            ChecksumAlgorithm::Md5 => request.content_md5(value),
+        }
+    }
+
+    /// Feeds the bytes emitted by the stream, as they are sent to S3, to `e_tag` and returns it
+    /// in a slot that can still be read after the stream has been handed over to the SDK, to
+    /// verify the ETag of the new object with `ExpectedETag::verify`. Use `ExpectedETag::new`
+    /// for a `put_object` request and `ExpectedETag::multipart` when the bytes of the stream
+    /// are split in parts of a multipart upload.
+    pub fn track_e_tag(&mut self, e_tag: ExpectedETag) -> Arc<Mutex<ExpectedETag>> {
+        self.e_tag.insert(Arc::new(Mutex::new(e_tag))).clone()
+    }
+
+    /// Returns the user metadata describing the source: its length and, once computed, its
+    /// SHA-256 checksum under the `METADATA_SHA256_KEY` key. Because object metadata is sent
+    /// before the body, the checksum needs to be computed ahead of the upload with
+    /// `precompute_checksum`.
+    pub fn object_metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        if let Some(file_size) = self.file_size {
+            metadata.insert(METADATA_LENGTH_KEY.to_string(), file_size.to_string());
+        }
+        if let Some((ChecksumAlgorithm::Sha256, value)) = &self.checksum {
+            metadata.insert(METADATA_SHA256_KEY.to_string(), value.clone());
+        }
+        metadata
+    }
+
+    /// Adds the values returned by `object_metadata` to a `put_object` request.
+    pub fn apply_metadata(&self, request: PutObject) -> PutObject {
+        self.object_metadata()
+            .into_iter()
+            .fold(request, |request, (key, value)| request.metadata(key, value))
+    }
+
     /// Sets the callback method for the `TrackableBodyStream` and returns the populated
     /// stream.
-    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
-        self.callback = Some(Box::new(callback));
+    pub fn with_callback(mut self, callback: impl FnMut(u64, u64, u64) + Send + 'static) -> Self {
+        self.set_callback(callback);
         self
     }
 
-    /// Sets the callback method
-    pub fn set_callback(&mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) {
-        self.callback = Some(Box::new(callback));
+    /// Sets the callback method. The callback can be a `FnMut` that owns its state: the stream
+    /// calls it from a single place and never concurrently, so the state needs no lock.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 5000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let mut chunks = Vec::new();
+    /// body.set_callback(move |_, sent, chunk| {
+    ///     chunks.push(chunk);
+    ///     if sent == 5000 {
+    ///         assert_eq!(chunks, vec![2048, 2048, 904]);
+    ///     }
+    /// });
+    /// while body.next().await.is_some() {}
+    /// # });
+    /// ```
+    pub fn set_callback(&mut self, callback: impl FnMut(u64, u64, u64) + Send + 'static) {
+        self.callback = Some(Mutex::new(Box::new(callback)));
     }
 
-    /// Makes it easier to customize the size of the buffer used while reading from source
+    /// Coalesces the notifications of the callbacks, the observers and the progress sinks so that
+    /// they run at most once every `interval`, and returns the stream. The bytes of the chunks
+    /// read in between are added to the next notification, and the last chunk is always
+    /// reported, so increments still add up to the length of the source. This keeps progress
+    /// bars from being redrawn hundreds of thousands of times for large files. Progress channels,
+    /// such as `progress_stream`, are not affected.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::{sync::{Arc, Mutex}, time::Duration};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 1024 * 1024];
+    /// let calls = Arc::new(Mutex::new(Vec::new()));
+    /// let recorder = calls.clone();
+    /// let mut body = TrackableBodyStream::from(data.as_slice())
+    ///     .with_callback_interval(Duration::from_secs(60))
+    ///     .with_callback(move |_, _, chunk| recorder.lock().unwrap().push(chunk));
+    /// while body.next().await.is_some() {}
+    ///
+    /// // the first chunk and the last one, carrying all the bytes read in between
+    /// assert_eq!(*calls.lock().unwrap(), vec![2048, 1024 * 1024 - 2048]);
+    /// # });
+    /// ```
+    pub fn with_callback_interval(mut self, interval: Duration) -> Self {
+        self.callback_interval = Some(interval);
+        self
+    }
+
+    /// Coalesces the notifications like `with_callback_interval`, so that they run once at least
+    /// `bytes` bytes have been read since the previous one, and returns the stream. When both are
+    /// set a notification runs as soon as either condition is met.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 1024 * 1024];
+    /// let calls = Arc::new(AtomicU64::new(0));
+    /// let counter = calls.clone();
+    /// let mut body = TrackableBodyStream::from(data.as_slice())
+    ///     .with_callback_every_bytes(64 * 1024)
+    ///     .with_callback(move |_, _, _| { counter.fetch_add(1, Ordering::SeqCst); });
+    /// while body.next().await.is_some() {}
+    /// assert_eq!(calls.load(Ordering::SeqCst), 16);
+    /// # });
+    /// ```
+    pub fn with_callback_every_bytes(mut self, bytes: u64) -> Self {
+        self.callback_every_bytes = bytes;
+        self
+    }
+
+    /// Sets a callback that receives a `Progress` every time a chunk is read, with the elapsed
+    /// time, the throughput of the chunk, the smoothed throughput and the estimated time
+    /// remaining, in addition to the counters passed to the callback set with `set_callback`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 10_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_progress_callback(|progress| {
+    ///     assert!(progress.smoothed_throughput > 0.0);
+    ///     if progress.is_complete() {
+    ///         assert_eq!(progress.eta, Some(std::time::Duration::ZERO));
+    ///     }
+    ///     println!("{:.0} bytes/s, {:?} left", progress.smoothed_throughput, progress.eta);
+    /// });
+    /// while body.next().await.is_some() {}
+    /// # });
+    /// ```
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(&Progress) + Send + 'static) {
+        self.progress_callback = Some(Mutex::new(Box::new(callback)));
+    }
+
+    /// Sets a callback that returns a future, for progress updates that need to `.await`, such as
+    /// sending a message over a websocket. The callback receives the same arguments as the
+    /// callback set with `set_callback`, and the stream awaits the future it returns before it
+    /// reads the next chunk or yields the next frame, so the upload waits for slow receivers.
+    /// Streams without an async callback do not pay for this.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::{FutureExt, StreamExt};
+    /// # use tokio::sync::mpsc;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let (sender, mut receiver) = mpsc::channel(1);
+    /// let data = vec![0u8; 5000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_async_callback(move |_, sent, _| {
+    ///     let sender = sender.clone();
+    ///     async move {
+    ///         let _ = sender.send(sent).await;
+    ///     }
+    ///     .boxed()
+    /// });
+    ///
+    /// let socket = tokio::spawn(async move {
+    ///     let mut updates = Vec::new();
+    ///     while let Some(sent) = receiver.recv().await {
+    ///         updates.push(sent);
+    ///     }
+    ///     updates
+    /// });
+    /// while body.next().await.is_some() {}
+    /// drop(body);
+    /// assert_eq!(socket.await.unwrap(), vec![2048, 4096, 5000]);
+    /// # }
+    /// ```
+    pub fn set_async_callback(&mut self, callback: impl Fn(u64, u64, u64) -> BoxFuture<'static, ()> + Sync + Send + 'static) {
+        self.async_callback = Some(Box::new(callback));
+    }
+
+    /// Sets a callback that receives the number of bytes read in the current chunk and the total
+    /// length of the source, for consumers that track progress by increments, such as
+    /// `indicatif::ProgressBar::inc`. The deltas add up to the length of the source.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 10_001];
+    /// let sum = Arc::new(AtomicU64::new(0));
+    /// let deltas = sum.clone();
+    ///
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_delta_callback(move |delta, _total| { deltas.fetch_add(delta, Ordering::SeqCst); });
+    /// while body.next().await.is_some() {}
+    /// assert_eq!(sum.load(Ordering::SeqCst), 10_001);
+    /// # });
+    /// ```
+    pub fn set_delta_callback(&mut self, callback: impl Fn(u64, u64) + Sync + Send + 'static) {
+        self.delta_callback = Some(Box::new(callback));
+    }
+
+    /// Computes a checksum with `algorithm`, like `set_checksum_algorithm`, and sets a callback
+    /// that receives the arguments of the progress callback followed by the checksum of the
+    /// bytes read so far, after every chunk. Intermediate values are only meaningful for
+    /// composable checksums such as `ChecksumAlgorithm::Crc32c`: for a cryptographic digest such
+    /// as SHA-256 only the final value, once the whole source has been read, is of any use, and
+    /// computing the intermediate ones costs a copy of the hasher state per chunk. The callback
+    /// follows the same `CallbackPanicPolicy` as the progress callback.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use base64::{engine::general_purpose::STANDARD, Engine};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{ChecksumAlgorithm, TrackableBodyStream};
+    /// # futures::executor::block_on(async {
+    /// let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+    /// let last = Arc::new(Mutex::new(String::new()));
+    ///
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let updates = last.clone();
+    /// body.set_checksum_callback(ChecksumAlgorithm::Crc32c, move |_, _, _, crc| {
+    ///     *updates.lock().unwrap() = crc.to_string();
+    /// });
+    /// while body.next().await.is_some() {}
+    ///
+    /// let expected = STANDARD.encode(crc32c::crc32c(&data).to_be_bytes());
+    /// assert_eq!(*last.lock().unwrap(), expected);
+    /// assert_eq!(body.checksum(), Some((ChecksumAlgorithm::Crc32c, expected.as_str())));
+    /// # });
+    /// ```
+    pub fn set_checksum_callback(
+        &mut self,
+        algorithm: ChecksumAlgorithm,
+        callback: impl Fn(u64, u64, u64, &str) + Sync + Send + 'static,
+    ) {
+        self.set_checksum_algorithm(algorithm);
+        self.checksum_callback = Some(Box::new(callback));
+    }
+
+    /// Sets the size of the blocks reported to the callback set with `set_block_callback`, for
+    /// storage backends and UIs that count fixed-size blocks rather than bytes. This only changes
+    /// how progress is reported, not how the source is read. A size of `0`, the default, disables
+    /// block reporting.
+    pub fn set_block_size(&mut self, block_size: u64) {
+        self.block_size = block_size;
+    }
+
+    /// Sets a callback that receives the number of blocks sent so far and the total number of
+    /// blocks, computed from the byte counts and the size set with `set_block_size`. The last
+    /// block may be partial: it is counted in the total and reported as sent once the whole
+    /// source has been read. The callback is only triggered when the number of blocks sent
+    /// changes, so each value is reported once.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 10_000];
+    /// let reports = Arc::new(Mutex::new(Vec::new()));
+    /// let log = reports.clone();
+    ///
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_buffer_size(1000);
+    /// body.set_block_size(4096);
+    /// body.set_block_callback(move |sent, total| log.lock().unwrap().push((sent, total)));
+    /// while body.next().await.is_some() {}
+    ///
+    /// assert_eq!(*reports.lock().unwrap(), vec![(1, 3), (2, 3), (3, 3)]);
+    /// # });
+    /// ```
+    pub fn set_block_callback(&mut self, callback: impl Fn(u64, u64) + Sync + Send + 'static) {
+        self.block_callback = Some(Box::new(callback));
+    }
+
+    /// Makes it easier to customize the size of the buffer used while reading from source. When
+    /// an alignment is set the size is rounded up to a multiple of it. The size can be changed
+    /// at any time, including between chunks of a stream that is being read: the new size is
+    /// used from the next read on, chunks already read or buffered are not affected.
+    ///
+    /// # Panics
+    /// Panics if `buffer_size` is `0`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 10_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_buffer_size(2000);
+    ///
+    /// let mut sizes = Vec::new();
+    /// while let Some(frame) = body.next().await {
+    ///     sizes.push(frame.unwrap().len());
+    ///     if sizes.len() == 2 {
+    ///         body.set_buffer_size(1000);
+    ///     }
+    /// }
+    /// assert_eq!(sizes, vec![2000, 2000, 1000, 1000, 1000, 1000, 1000, 1000]);
+    /// assert_eq!(sizes.iter().sum::<usize>(), data.len());
+    /// # });
+    /// ```
     pub fn set_buffer_size(&mut self, buffer_size: usize) {
-        self.buffer_size = buffer_size;
+        assert!(buffer_size > 0, "the buffer size must be greater than zero");
+        self.buffer_size = match self.alignment {
+            Some(alignment) => buffer_size.next_multiple_of(alignment),
+            None => buffer_size,
+        };
+    }
+
+    /// Keeps reading from the source until a whole buffer has been filled, or the source has
+    /// ended, before a chunk is emitted and reported to the callback. Sources such as sockets,
+    /// pipes or other streams can return far fewer bytes per read than the buffer size, which
+    /// makes for tiny chunks and chatty callbacks. With this mode every chunk but the last has
+    /// exactly the buffer size. Bytes of short reads are copied into the chunk being filled.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::convert::Infallible;
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::{Bytes, TrackableBodyStream};
+    /// # futures::executor::block_on(async {
+    /// let pieces = (0..25).map(|_| Ok::<_, Infallible>(Bytes::from(vec![0u8; 100])));
+    /// let mut body = TrackableBodyStream::from_stream(futures::stream::iter(pieces), Some(2500));
+    /// body.set_buffer_size(1000);
+    /// body.set_fill_buffer(true);
+    ///
+    /// let frames: Vec<_> = body.try_collect().await.unwrap();
+    /// let sizes: Vec<_> = frames.iter().map(|frame| frame.len()).collect();
+    /// assert_eq!(sizes, vec![1000, 1000, 500]);
+    /// # });
+    /// ```
+    pub fn set_fill_buffer(&mut self, fill_buffer: bool) {
+        self.fill_buffer = fill_buffer;
+    }
+
+    /// Returns the effective size of the buffer used while reading from source, after any
+    /// rounding to the alignment set with `set_alignment`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// let data = [0u8; 16];
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.set_buffer_size(8192);
+    /// assert_eq!(body.buffer_size(), 8192);
+    ///
+    /// body.set_alignment(4096).unwrap();
+    /// body.set_buffer_size(5000);
+    /// assert_eq!(body.buffer_size(), 8192);
+    /// ```
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Requires reads from the source to be a multiple of `alignment` bytes, which must be a
+    /// power of two. The current and future buffer sizes are rounded up to a multiple of the
+    /// alignment. This is meant for sources using direct IO, such as `DirectFile` on Linux,
+    /// where read sizes must match the logical block size of the device.
+    pub fn set_alignment(&mut self, alignment: usize) -> std::io::Result<()> {
+        validate_alignment(alignment)?;
+        self.alignment = Some(alignment);
+        self.set_buffer_size(self.buffer_size);
+        Ok(())
+    }
+
+    /// Prevents the stream from emitting a final frame smaller than `min_final_frame` bytes by
+    /// merging small frames into the one before them. To do this the stream holds one frame
+    /// of lookahead: each chunk is emitted only once the following chunk has been read, so the
+    /// consumer always lags one read behind the progress callback. A value of `0`, the default,
+    /// disables the lookahead.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 1024 * 3 + 5];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_buffer_size(1024);
+    /// body.set_min_final_frame(64);
+    ///
+    /// let frames: Vec<_> = body.try_collect().await.unwrap();
+    /// let sizes: Vec<_> = frames.iter().map(|frame| frame.len()).collect();
+    /// assert_eq!(sizes, vec![1024, 1024, 1029]);
+    /// # });
+    /// ```
+    pub fn set_min_final_frame(&mut self, min_final_frame: usize) {
+        self.min_final_frame = min_final_frame;
+    }
+
+    /// Keeps up to `chunks` chunks read from the source buffered ahead of the consumer. While
+    /// the source is ready the stream keeps reading until the buffer is full, when the source
+    /// is pending buffered chunks are emitted instead of waiting. Buffered chunks are always
+    /// emitted before the stream completes. The progress callback reports bytes as they are read
+    /// from the source, so it runs ahead of the bytes actually handed to the consumer.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// body.set_buffer_size(1000);
+    /// body.set_read_ahead(16);
+    /// let expected = body.content_length().unwrap() as usize;
+    ///
+    /// let frames: Vec<_> = body.try_collect().await?;
+    /// assert_eq!(frames.iter().map(|frame| frame.len()).sum::<usize>(), expected);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_read_ahead(&mut self, chunks: usize) {
+        self.read_ahead = chunks;
+    }
+
+    /// Encodes the bytes of the source as base64 while streaming, for sinks that only accept
+    /// text bodies. `content_length` returns the length of the encoded body, while the progress
+    /// callback keeps reporting bytes read from the source. Chunks do not need to be aligned
+    /// to 3-byte groups: incomplete groups are carried over to the following chunk.
+    ///
+    /// # Examples
+    /// ```
+    /// # use base64::{engine::general_purpose::STANDARD, Engine};
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?.with_base64();
+    /// body.set_buffer_size(1000);
+    /// let expected = STANDARD.encode(std::fs::read("./examples/sample.jpeg")?);
+    /// assert_eq!(body.content_length(), Some(expected.len() as i64));
+    ///
+    /// let frames: Vec<_> = body.try_collect().await?;
+    /// assert_eq!(frames.concat(), expected.as_bytes());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_base64(mut self) -> Self {
+        self.transform = Some(Transform::base64());
+        self
+    }
+
+    /// Compresses the bytes of the source while streaming, replacing the encoding set with
+    /// `with_base64`. The progress callback keeps reporting the bytes read from the source, so
+    /// a progress bar tracks the original file; `set_estimated_progress_callback` tracks the
+    /// compressed bytes against an estimated length instead. The source is compressed in blocks
+    /// of at least 16 KB, against the last 32 KB of the source, whatever the buffer size, and
+    /// blocks that do not compress are stored as they are.
+    ///
+    /// The length of the compressed body cannot be known before streaming, `content_length`
+    /// returns `None`. Since `put_object` needs the length of its body, upload it with a
+    /// `FanOutUploader`, which streams a body of unknown length with a multipart upload, one
+    /// part at a time, and sets the `content_encoding` of the object to `gzip` so that clients
+    /// decompress it. When uploading it otherwise, set the `content_encoding` returned by
+    /// `content_encoding`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::{Compression, TrackableBodyStream};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let data = "hello world ".repeat(1000);
+    /// let mut body = TrackableBodyStream::from(data.as_bytes()).with_compression(Compression::Gzip);
+    /// assert_eq!(body.content_length(), None);
+    ///
+    /// let compressed = body.try_collect::<Vec<_>>().await?.concat();
+    /// assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+    /// assert!(compressed.len() < data.len() / 10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// ```no_run
+    /// # use trackable_s3_stream::{Compression, FanOutUploader, TrackableBodyStream};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let client = aws_sdk_s3::Client::new(&aws_config::from_env().load().await);
+    ///
+    /// let body = TrackableBodyStream::from_path("./server.log").await?
+    ///     .with_compression(Compression::Gzip)
+    ///     .with_callback(|total, read, _| println!("{}/{} bytes", read, total));
+    /// let outcome = FanOutUploader::new(body)
+    ///     .with_destination(client, "bucket", "server.log.gz")
+    ///     .upload()
+    ///     .await;
+    /// outcome.into_iter().try_for_each(|outcome| outcome)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.transform = Some(Transform::compression(compression));
+        self
+    }
+
+    /// Sets a callback that tracks the bytes emitted by the stream, the compressed bytes sent to
+    /// S3 with `with_compression`, against a length estimated from the length of the source and
+    /// `ratio`, the expected ratio of the compressed length to the source length, `0.3` for
+    /// logs for example. The callback receives the estimated length, the bytes emitted so far
+    /// and the estimated percentage.
+    ///
+    /// This is only an estimate: the percentage is clamped between `0.0` and `99.0` while the
+    /// stream runs, whatever the actual ratio, and snaps to `100.0` once the stream completes,
+    /// when the last call receives the actual length as the estimated length. Without a
+    /// source length the percentage stays at `0.0` until then.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::{Compression, TrackableBodyStream};
+    /// # futures::executor::block_on(async {
+    /// let data = "GET /index.html 200\n".repeat(5000);
+    /// let updates = Arc::new(Mutex::new(Vec::new()));
+    /// let recorder = updates.clone();
+    ///
+    /// let mut body = TrackableBodyStream::from(data.as_bytes()).with_compression(Compression::Gzip);
+    /// body.set_estimated_progress_callback(0.3, move |estimated, emitted, percent| {
+    ///     recorder.lock().unwrap().push((estimated, emitted, percent));
+    /// });
+    /// let compressed = body.try_collect::<Vec<_>>().await.unwrap().concat();
+    ///
+    /// let updates = updates.lock().unwrap();
+    /// assert!(updates.iter().all(|&(_, _, percent)| (0.0..=100.0).contains(&percent)));
+    /// assert!(updates[..updates.len() - 1].iter().all(|&(_, _, percent)| percent <= 99.0));
+    /// let len = compressed.len() as u64;
+    /// assert_eq!(updates.last(), Some(&(len, len, 100.0)));
+    /// # });
+    /// ```
+    pub fn set_estimated_progress_callback(&mut self, ratio: f64, callback: impl Fn(u64, u64, f64) + Sync + Send + 'static) {
+        self.estimate_callback = Some((ratio, Box::new(callback)));
+    }
+
+    /// Encrypts the bytes of the source while streaming, see `Encryption`, replacing the encoding
+    /// set with `with_base64` or `with_compression`. The progress callback keeps reporting
+    /// plaintext bytes read from the source, while `content_length` returns the length of the
+    /// encrypted body. Every attempt to stream the source, after `reset` or a retry, encrypts
+    /// it with a new nonce prefix.
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.transform = Some(Transform::encryption(&encryption));
+        self
+    }
+
+    /// Writes every byte emitted by the stream to `writer` as well, for example a local cache
+    /// file or a hasher, so that a copy of the upload is saved while it is sent. The bytes are
+    /// the ones sent to S3, after any encoding set with `with_base64`, `with_compression` or
+    /// `with_encryption`. They are written when the stream emits them, and `writer` is flushed
+    /// when the stream completes; an error of the writer fails the stream with a
+    /// `TrackableStreamError::Io` error, since the copy would be incomplete.
+    ///
+    /// When the request is retried the stream emits its bytes again, and only those past the
+    /// ones already written reach `writer`, which ends up with a single copy. An encrypted
+    /// stream is encrypted with a new nonce prefix on every attempt, the copy of an encrypted
+    /// stream that has been retried does not match the object.
+    ///
+    /// Writes are blocking, so a file should be wrapped in a `std::io::BufWriter`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::{io::Write, sync::{Arc, Mutex}};
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// #[derive(Clone, Default)]
+    /// struct SharedCopy(Arc<Mutex<Vec<u8>>>);
+    ///
+    /// impl Write for SharedCopy {
+    ///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    ///         self.0.lock().unwrap().write(buf)
+    ///     }
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// # futures::executor::block_on(async {
+    /// let data = vec![7u8; 5000];
+    /// let copy = SharedCopy::default();
+    /// let body = TrackableBodyStream::from(data.as_slice()).with_tee(copy.clone());
+    ///
+    /// let sent = body.try_collect::<Vec<_>>().await.unwrap().concat();
+    /// assert_eq!(*copy.0.lock().unwrap(), sent);
+    /// # });
+    /// ```
+    pub fn with_tee(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.tee = Some(Mutex::new(Box::new(writer)));
+        self
+    }
+
+    /// Returns the number of bytes of the source that have not been read yet, or `None` when
+    /// the length of the source is unknown. This counts bytes of the source, before any
+    /// encoding, not bytes emitted by the stream.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::{Stream, StreamExt};
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 5000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// assert_eq!(body.remaining_bytes(), Some(5000));
+    /// // frames of at most 2048 bytes
+    /// assert_eq!(body.size_hint(), (3, None));
+    ///
+    /// body.next().await;
+    /// assert_eq!(body.remaining_bytes(), Some(2952));
+    /// assert_eq!(body.size_hint(), (2, None));
+    /// # });
+    /// ```
+    pub fn remaining_bytes(&self) -> Option<u64> {
+        self.file_size.map(|file_size| file_size.saturating_sub(self.cur_read))
     }
 
     /// This returns the size of the input file or slice. Can be used to set the `content_length`
     /// property of the `put_object` method in the AWS SDK for Rust to prevent S3 from closing the 
-    /// connection for large objects without a known size
-    pub fn content_length(&self) -> i64 {
-        self.file_size as i64
+    /// connection for large objects without a known size. When the body is encoded with
+    /// `with_base64` this is the length of the encoded body. Returns `None` for sources of
+    /// unknown length, such as streams created with `with_unknown_length`, and for compressed
+    /// bodies.
+    pub fn content_length(&self) -> Option<i64> {
+        self.encoded_content_length().map(|length| length as i64)
     }
 
-    /// Consumes this body stream and returns a `BodyStream` object that can be passed to the `body`
-    /// method of the `put_object` call in the AWS SDK for Rust.
-    pub fn to_s3_stream(self) -> ByteStream {
-        let sdk_body = SdkBody::from(
-            hyper::Body::from(
-                Box::new(self) as Box<dyn Stream<Item = Result<hyper::body::Bytes, Box<(dyn std::error::Error + Sync + std::marker::Send + 'static)>>> + Send>
-            )
-        );
-        ByteStream::new(sdk_body)
+    /// Returns the exact length of the body emitted by the stream once the configured encoding
+    /// has been applied, or `None` when it cannot be known before streaming. Without an encoding
+    /// this is the size of the source. Pass this value as the `content_length` of the request.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// let data = [0u8; 10];
+    /// let body = TrackableBodyStream::from(&data[..]);
+    /// assert_eq!(body.encoded_content_length(), Some(10));
+    ///
+    /// let body = body.with_base64();
+    /// assert_eq!(body.encoded_content_length(), Some(4 * 10_u64.div_ceil(3)));
+    /// ```
+    pub fn encoded_content_length(&self) -> Option<u64> {
+        match &self.transform {
+            Some(transform) => self.file_size.and_then(|file_size| transform.encoded_length(file_size)),
+            None => self.file_size,
+        }
     }
-}
 
-impl<I: AsyncReadExt + Unpin> Stream for TrackableBodyStream<I> {
-    type Item = Result<hyper::body::Bytes, Box<dyn std::error::Error + Sync + std::marker::Send + 'static>>;
+    /// Returns the content encoding of the body, `gzip` when it is compressed with
+    /// `with_compression`, to set as the `content_encoding` of the object so that clients
+    /// decompress it. Returns `None` when the body is not compressed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::{Compression, TrackableBodyStream};
+    /// let body = TrackableBodyStream::from(&b"hello"[..]);
+    /// assert_eq!(body.content_encoding(), None);
+    /// let body = body.with_compression(Compression::Gzip);
+    /// assert_eq!(body.content_encoding(), Some("gzip"));
+    /// ```
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        self.transform.as_ref().and_then(Transform::content_encoding)
+    }
 
-    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut_self = self.get_mut();
-        let mut buf = Vec::with_capacity(mut_self.buffer_size);
-        
-        match Future::poll(Box::pin(mut_self.input.read_buf(&mut buf)).as_mut(), cx) {
+    /// Returns a `futures::Stream` that yields a `Progress` item every time a chunk is read
+    /// from the source. The progress stream completes when the body stream reaches the end of
+    /// the source, fails, or is dropped. This method can be called multiple times, each returned
+    /// stream receives every update.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 10_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let progress = body.progress_stream();
+    ///
+    /// while body.next().await.is_some() {}
+    /// let updates: Vec<_> = progress.collect().await;
+    /// assert_eq!(updates.last().unwrap().percent(), Some(100.0));
+    /// # });
+    /// ```
+    pub fn progress_stream(&mut self) -> impl Stream<Item = Progress> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.progress_senders.push(sender);
+        receiver
+    }
+
+    /// Returns a `futures::Stream` of human readable status lines, such as
+    /// `45% — 22.5/50.0 MB @ 4.5 MB/s — ETA 7s`, one for every `Progress` update, ready to be
+    /// printed by a CLI. The rate is the average since the stream was first polled, the lines
+    /// are formatted by `Progress::status_line`. Like `progress_stream`, the returned stream
+    /// completes when the body stream completes, fails, or is dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 10_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let lines = body.status_lines();
+    ///
+    /// while body.next().await.is_some() {}
+    /// let lines: Vec<String> = lines.collect().await;
+    /// assert_eq!(lines.len(), 5);
+    /// assert!(lines[0].starts_with("20% \u{2014} 2.0/10.0 kB @ "));
+    /// assert!(lines.iter().all(|line| line.contains("B/s")));
+    /// assert!(lines[4].starts_with("100%"));
+    ///
+    /// // without a length there is no percentage nor ETA
+    /// let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>(trackable_s3_stream::Bytes::from("hello"))]);
+    /// let mut body = TrackableBodyStream::from_stream(chunks, None);
+    /// let lines = body.status_lines();
+    /// while body.next().await.is_some() {}
+    /// let lines: Vec<String> = lines.collect().await;
+    /// assert!(lines.iter().all(|line| line.starts_with("5.0 B @ ") && !line.contains("ETA")));
+    /// # });
+    /// ```
+    pub fn status_lines(&mut self) -> impl Stream<Item = String> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.status_senders.push(sender);
+        receiver
+    }
+
+    /// Returns a `futures::Stream` that yields a snapshot of the current progress `fps` times
+    /// per second, whether or not chunks have been read in the meantime, for UIs that redraw at
+    /// a fixed frame rate. Snapshots are read from state shared with the stream, so ticking does
+    /// not slow down the upload. The tick stream yields a last snapshot and completes once the
+    /// body stream completes, fails, or is dropped. It needs a Tokio runtime with the time driver
+    /// enabled.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::Duration;
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let data = vec![0u8; 20_480];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let ticks = body.tick_stream(10);
+    ///
+    /// let upload = async {
+    ///     // a slow upload: one 2 KiB chunk every 100 ms, one second in total
+    ///     while body.next().await.is_some() {
+    ///         tokio::time::sleep(Duration::from_millis(100)).await;
+    ///     }
+    ///     drop(body);
+    /// };
+    /// let (_, snapshots) = tokio::join!(upload, ticks.collect::<Vec<_>>());
+    ///
+    /// assert!((9..=12).contains(&snapshots.len()));
+    /// assert_eq!(snapshots.last().unwrap().sent, 20_480);
+    /// # }
+    /// ```
+    pub fn tick_stream(&self, fps: u32) -> impl Stream<Item = Progress> {
+        let receiver = self.progress_watch.as_ref().map(watch::Sender::subscribe);
+        let period = Duration::from_secs_f64(1.0 / f64::from(fps.max(1)));
+        futures::stream::unfold((receiver, None), move |(receiver, interval)| async move {
+            let receiver = receiver?;
+            let mut interval = interval.unwrap_or_else(|| {
+                let mut interval = tokio::time::interval(period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                interval
+            });
+            interval.tick().await;
+            let progress = receiver.borrow().clone();
+            // once the body stream has dropped its sender the snapshot just taken is the last
+            let receiver = receiver.has_changed().is_ok().then_some(receiver);
+            Some((progress, (receiver, Some(interval))))
+        })
+    }
+
+    /// Returns a `tokio::sync::watch::Receiver` that always holds the latest `Progress` of the
+    /// stream. GUI frameworks can check it from their own event loop, or await `changed`, instead
+    /// of running code inside the poll path of the S3 client: the stream only replaces the value,
+    /// it never waits for receivers. Once the stream completes, fails, or is dropped, `changed`
+    /// returns an error and the receiver keeps the last value.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let data = vec![0u8; 5000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let mut receiver = body.progress_receiver();
+    ///
+    /// let ui = tokio::spawn(async move {
+    ///     while receiver.changed().await.is_ok() {
+    ///         println!("{}", *receiver.borrow());
+    ///     }
+    ///     receiver.borrow().sent
+    /// });
+    /// while body.next().await.is_some() {}
+    /// drop(body);
+    /// assert_eq!(ui.await.unwrap(), 5000);
+    /// # }
+    /// ```
+    pub fn progress_receiver(&self) -> watch::Receiver<Progress> {
+        match &self.progress_watch {
+            Some(progress_watch) => progress_watch.subscribe(),
+            None => {
+                let final_progress = Progress {
+                    total: self.file_size.unwrap_or(0),
+                    sent: self.cur_read,
+                    id: self.id.clone(),
+                    finished: self.eof,
+                    ..Default::default()
+                };
+                watch::channel(final_progress).1
+            }
+        }
+    }
+
+    /// Returns a handle to the progress counters of this stream, which can be cloned and read
+    /// from any task or thread at any time. Reading the handle never blocks the stream.
+    pub fn progress_handle(&self) -> ProgressHandle {
+        self.progress_handle.clone()
+    }
+
+    /// Writes a progress line, formatted with the `Display` implementation of `Progress`, to
+    /// `writer` every time a chunk is read. This is meant for simple logs of headless jobs, for
+    /// example to a file or `std::io::stderr()`. Failing to write a line does not interrupt the
+    /// upload: the error is logged with the `log` crate and the stream carries on.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::{io::Write, sync::{Arc, Mutex}};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// #[derive(Clone, Default)]
+    /// struct SharedLog(Arc<Mutex<Vec<u8>>>);
+    ///
+    /// impl Write for SharedLog {
+    ///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    ///         self.0.lock().unwrap().write(buf)
+    ///     }
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// # futures::executor::block_on(async {
+    /// let data = [0u8; 4096];
+    /// let log = SharedLog::default();
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.set_progress_writer(log.clone());
+    /// while body.next().await.is_some() {}
+    ///
+    /// let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    /// assert_eq!(log, "2048/4096 bytes (50.0%)\n4096/4096 bytes (100.0%)\n");
+    /// # });
+    /// ```
+    pub fn set_progress_writer(&mut self, writer: impl Write + Send + 'static) {
+        self.progress_writer = Some(Mutex::new(Box::new(writer)));
+    }
+
+    /// Sets what happens when the progress callback panics, see `CallbackPanicPolicy`.
+    pub fn set_panic_policy(&mut self, policy: CallbackPanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    /// Returns a `tokio::sync::broadcast::Sender` that publishes a `Progress` update every time a
+    /// chunk is read. Any number of independent consumers can call `subscribe` on it, each
+    /// receiver gets every update sent after it subscribed. At most `capacity` updates are kept
+    /// for slow receivers: a receiver that falls further behind gets a `RecvError::Lagged` error
+    /// with the number of skipped updates, then resumes from the oldest retained one. The stream
+    /// drops its sender when it completes, so receivers return `RecvError::Closed` once all
+    /// clones of the returned sender have been dropped too.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let data = [0u8; 6144];
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// let progress = body.progress_broadcast(16);
+    /// let (mut bar, mut log) = (progress.subscribe(), progress.subscribe());
+    /// drop(progress);
+    ///
+    /// while body.next().await.is_some() {}
+    /// for sent in [2048, 4096, 6144] {
+    ///     assert_eq!(bar.recv().await.unwrap().sent, sent);
+    ///     assert_eq!(log.recv().await.unwrap().sent, sent);
+    /// }
+    /// assert!(bar.recv().await.is_err());
+    /// # }
+    /// ```
+    pub fn progress_broadcast(&mut self, capacity: usize) -> broadcast::Sender<Progress> {
+        let (sender, _) = broadcast::channel(capacity);
+        self.progress_broadcasts.push(sender.clone());
+        sender
+    }
+
+    /// Drives a `ProgressSink`, such as a progress bar, from this stream: its length is set to the
+    /// size of the source right away, its position is updated every time a chunk is read, and it
+    /// is finished once when the stream completes successfully.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{ProgressSink, TrackableBodyStream};
+    /// #[derive(Default)]
+    /// struct Bar {
+    ///     length: AtomicU64,
+    ///     position: AtomicU64,
+    ///     finished: AtomicU64,
+    /// }
+    ///
+    /// impl ProgressSink for Bar {
+    ///     fn set_length(&self, length: u64) {
+    ///         self.length.store(length, Ordering::SeqCst);
+    ///     }
+    ///     fn set_position(&self, position: u64) {
+    ///         self.position.store(position, Ordering::SeqCst);
+    ///     }
+    ///     fn finish(&self) {
+    ///         self.finished.fetch_add(1, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// # futures::executor::block_on(async {
+    /// let data = [0u8; 5000];
+    /// let bar = Arc::new(Bar::default());
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.attach_progress_sink(bar.clone());
+    /// while body.next().await.is_some() {}
+    ///
+    /// assert_eq!(bar.position.load(Ordering::SeqCst), bar.length.load(Ordering::SeqCst));
+    /// assert_eq!(bar.finished.load(Ordering::SeqCst), 1);
+    /// # });
+    /// ```
+    pub fn attach_progress_sink(&mut self, sink: impl ProgressSink + 'static) {
+        sink.set_length(self.file_size.unwrap_or(0));
+        self.progress_sinks.push(Box::new(sink));
+    }
+
+    /// Emits one empty frame after the last chunk when the source has been read to the end
+    /// successfully, before the stream returns `None`. Custom sinks can use it to tell a
+    /// completed stream apart from one that was dropped. Disabled by default, and always
+    /// disabled by `to_s3_stream`, since HTTP bodies have no use for the marker.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = [0u8; 100];
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.set_emit_eof_marker(true);
+    ///
+    /// assert_eq!(body.next().await.unwrap().unwrap().len(), 100);
+    /// assert!(body.next().await.unwrap().unwrap().is_empty());
+    /// assert!(body.next().await.is_none());
+    /// # });
+    /// ```
+    pub fn set_emit_eof_marker(&mut self, emit_eof_marker: bool) {
+        self.emit_eof_marker = emit_eof_marker;
+    }
+
+    /// Aborts the stream with a `TrackableStreamError::DeadlineExceeded` error once `deadline`
+    /// has passed, however much of the source is left. Unlike `next_chunk_timeout` this bounds the
+    /// whole upload. A timer wakes the stream at the deadline even while the source is idle, so
+    /// the stream must be polled within a Tokio runtime with the time driver enabled.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::{Duration, Instant};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{TrackableBodyStream, TrackableStreamError};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let data = vec![0u8; 1024 * 1024];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_deadline(Instant::now() + Duration::from_millis(20));
+    ///
+    /// assert!(body.next().await.unwrap().is_ok());
+    /// tokio::time::sleep(Duration::from_millis(30)).await;
+    /// assert!(matches!(body.next().await, Some(Err(TrackableStreamError::DeadlineExceeded { .. }))));
+    /// assert!(body.next().await.is_none());
+    /// # }
+    /// ```
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+        self.deadline_timer = None;
+    }
+
+    /// Stops the stream when `token` is cancelled, for example from the "Cancel" button of a UI.
+    /// The next poll fails with a `TrackableStreamError::Cancelled` error and the stream
+    /// completes right after, without reading any more of the source. A stream waiting on its
+    /// source is woken up by the cancellation.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use tokio_util::sync::CancellationToken;
+    /// # use trackable_s3_stream::{TrackableBodyStream, TrackableStreamError};
+    /// # futures::executor::block_on(async {
+    /// let data = vec![0u8; 1024 * 1024];
+    /// let token = CancellationToken::new();
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_cancellation_token(token.clone());
+    ///
+    /// assert!(body.next().await.unwrap().is_ok());
+    /// token.cancel();
+    /// assert!(matches!(body.next().await, Some(Err(TrackableStreamError::Cancelled { bytes_read: 2048 }))));
+    /// assert!(body.next().await.is_none());
+    /// # });
+    /// ```
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = Some(Mutex::new(Box::pin(token.cancelled_owned())));
+    }
+
+    /// Fails the stream with a `TrackableStreamError::Stalled` error when no bytes have been read
+    /// from the source or taken by the consumer for `timeout`, so that a dead connection can be
+    /// told apart from a slow one. Time spent paused with a `PauseHandle` does not count. A timer
+    /// wakes the stream when the timeout elapses, so the stream must be polled within a Tokio
+    /// runtime with the time driver enabled. The stall is reported when the consumer polls the
+    /// stream after being woken up: an HTTP client that stops polling its body altogether
+    /// is better bounded by its own timeouts.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::Duration;
+    /// # use futures::StreamExt;
+    /// # use tokio::io::AsyncReadExt;
+    /// # use trackable_s3_stream::{TrackableBodyStream, TrackableStreamError};
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// // a pipe that delivers some bytes and then hangs
+    /// let (mut writer, reader) = tokio::io::duplex(64);
+    /// tokio::io::AsyncWriteExt::write_all(&mut writer, b"first bytes").await.unwrap();
+    /// let mut body = TrackableBodyStream::with_unknown_length(reader);
+    /// body.set_stall_timeout(Duration::from_secs(30));
+    ///
+    /// assert!(body.next().await.unwrap().is_ok());
+    /// match body.next().await {
+    ///     Some(Err(TrackableStreamError::Stalled { bytes_read, idle })) => {
+    ///         assert_eq!(bytes_read, 11);
+    ///         assert!(idle >= Duration::from_secs(30));
+    ///     }
+    ///     _ => panic!("expected a stall"),
+    /// }
+    /// # drop(writer);
+    /// # }
+    /// ```
+    pub fn set_stall_timeout(&mut self, timeout: Duration) {
+        self.stall_timeout = Some(timeout);
+        self.stall_timer = None;
+    }
+
+    /// Calls `callback` once the stream has been idle for `timeout`, with the number of bytes read
+    /// so far and how long the stream has been idle, without failing the stream. The callback
+    /// fires once per stall, it can fire again after bytes have moved. The same conditions as
+    /// `set_stall_timeout` apply.
+    pub fn set_stall_callback(&mut self, timeout: Duration, callback: impl Fn(u64, Duration) + Sync + Send + 'static) {
+        self.stall_callback = Some((timeout, Box::new(callback)));
+        self.stall_timer = None;
+    }
+
+    /// Calls `callback` when the SDK retries a request whose body was built with
+    /// `to_retryable_s3_stream`, with the number of the attempt, starting at `1` for the first
+    /// retry, and the number of bytes read by the previous attempt. The progress is rewound to
+    /// `0` before the callback runs, a progress bar can use it to explain why it went back.
+    pub fn set_retry_callback(&mut self, callback: impl Fn(u32, u64) + Sync + Send + 'static) {
+        self.retry_callback = Some(Box::new(callback));
+    }
+
+    /// Returns a `PauseHandle` to pause and resume this stream from another task. All the handles
+    /// returned by this method control the same stream.
+    pub fn pause_handle(&mut self) -> PauseHandle {
+        self.pause.get_or_insert_with(PauseHandle::default).clone()
+    }
+
+    /// Limits the rate at which the stream emits bytes, see `Throttle`. Reads from the source
+    /// are not throttled, with `set_read_ahead` they can run ahead of the emitted bytes. The
+    /// stream needs to be polled within a Tokio runtime with the time driver enabled.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = Some(throttle);
+        self.throttle_timer = None;
+    }
+
+    /// Limits the stream to `bytes_per_second`, for background jobs that must not saturate the
+    /// uplink, and returns the stream. This is a token bucket holding up to one second worth of
+    /// bytes, a shorthand for `set_throttle` with `Throttle::new(bytes_per_second)` and a burst of
+    /// `bytes_per_second`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::{Duration, Instant};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let data = vec![0u8; 30_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice()).with_max_bandwidth(100_000);
+    ///
+    /// let start = Instant::now();
+    /// while body.next().await.is_some() {}
+    /// assert!(start.elapsed() >= Duration::from_millis(250));
+    /// # }
+    /// ```
+    pub fn with_max_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.set_throttle(Throttle::new(bytes_per_second).with_burst(bytes_per_second));
+        self
+    }
+
+    /// Returns `true` if the throttle allows the next frame to be emitted now, otherwise makes
+    /// sure the task is woken up when it does.
+    fn poll_throttle(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        let throttle = match &mut self.throttle {
+            Some(throttle) => throttle,
+            None => return true,
+        };
+        if let Some(timer) = &mut self.throttle_timer {
+            if timer.as_mut().poll(cx).is_pending() {
+                return false;
+            }
+            self.throttle_timer = None;
+        }
+        match throttle.delay(self.emitted) {
+            None => true,
+            Some(delay) => {
+                let mut timer = Box::pin(tokio::time::sleep(delay));
+                // a timer with a zero delay is ready right away
+                if timer.as_mut().poll(cx).is_ready() {
+                    return true;
+                }
+                self.throttle_timer = Some(timer);
+                false
+            }
+        }
+    }
+
+    /// Records that bytes have moved, for stall detection
+    fn record_activity(&mut self) {
+        if self.stall_timeout.is_some() || self.stall_callback.is_some() {
+            self.last_activity = Some(tokio::time::Instant::now());
+            self.stall_reported = false;
+        }
+    }
+
+    /// Reports a stall if the stream has been idle for longer than the stall timeouts, otherwise
+    /// makes sure the task is woken up when it would be.
+    fn poll_stall(&mut self, cx: &mut std::task::Context<'_>) -> Result<(), TrackableStreamError> {
+        if self.stall_timeout.is_none() && self.stall_callback.is_none() {
+            return Ok(());
+        }
+        // the clock of the runtime, which drives the timer, rather than the system clock
+        let now = tokio::time::Instant::now();
+        let last_activity = *self.last_activity.get_or_insert(now);
+        let idle = now.saturating_duration_since(last_activity);
+        if let Some(timeout) = self.stall_timeout.filter(|timeout| idle >= *timeout) {
+            log::debug!("Stream idle for {:?}, longer than the stall timeout of {:?}", idle, timeout);
+            return Err(TrackableStreamError::Stalled { bytes_read: self.cur_read, idle });
+        }
+        if let Some((timeout, callback)) = &self.stall_callback {
+            if idle >= *timeout && !self.stall_reported {
+                self.stall_reported = true;
+                let bytes_read = self.cur_read;
+                run_callback(self.panic_policy, || callback(bytes_read, idle))?;
+            }
+        }
+        let callback_timeout = self.stall_callback.as_ref().filter(|_| !self.stall_reported).map(|(timeout, _)| *timeout);
+        let next = match (self.stall_timeout, callback_timeout) {
+            (Some(first), Some(second)) => first.min(second),
+            (Some(timeout), None) | (None, Some(timeout)) => timeout,
+            (None, None) => return Ok(()),
+        };
+        let deadline = last_activity + next;
+        match &mut self.stall_timer {
+            Some(timer) => timer.as_mut().reset(deadline),
+            None => self.stall_timer = Some(Box::pin(tokio::time::sleep_until(deadline))),
+        }
+        if let Some(timer) = &mut self.stall_timer {
+            // registers the waker, the deadline is in the future
+            let _ = timer.as_mut().poll(cx);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` once the cancellation token has been cancelled, otherwise makes sure the
+    /// task is woken up when it is.
+    fn poll_cancelled(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        match &mut self.cancellation {
+            Some(cancelled) => {
+                let cancelled = cancelled.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+                cancelled.as_mut().poll(cx).is_ready()
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` once the deadline has passed, otherwise makes sure the task is woken up
+    /// when it does.
+    fn poll_deadline(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return false,
+        };
+        if Instant::now() >= deadline {
+            return true;
+        }
+        let timer = self.deadline_timer
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline.into())));
+        timer.as_mut().poll(cx).is_ready()
+    }
+
+    /// Runs `event` on every `ProgressObserver`, in the order they were added
+    fn notify_observers(&mut self, mut event: impl FnMut(&mut dyn ProgressObserver)) -> Result<(), TrackableStreamError> {
+        for observer in &mut self.observers {
+            let observer = observer.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+            run_callback(self.panic_policy, || event(observer.as_mut()))?;
+        }
+        Ok(())
+    }
+
+    /// Marks the start of the transfer the first time the stream is polled or primed
+    fn start(&mut self) -> Result<(), TrackableStreamError> {
+        if self.started.is_some() {
+            return Ok(());
+        }
+        self.started = Some(Instant::now());
+        let total = self.file_size.unwrap_or(0);
+        self.notify_observers(|observer| observer.on_start(total))
+    }
+
+    /// Polls the future returned by the async callback for the last chunk, returns `true` once
+    /// it has completed or if there is none
+    fn poll_async_callback(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        if let Some(pending) = &mut self.pending_callback {
+            let future = pending.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if future.as_mut().poll(cx).is_pending() {
+                return false;
+            }
+            self.pending_callback = None;
+        }
+        true
+    }
+
+    /// Decides whether the per-chunk callbacks are due for a chunk read at `now`, according to
+    /// `with_callback_interval` and `with_callback_every_bytes`. Returns the number of bytes to
+    /// report, all the bytes read since the last notification, or `None` to skip this chunk.
+    fn coalesce(&mut self, chunk: u64, now: Instant) -> Option<u64> {
+        if self.callback_interval.is_none() && self.callback_every_bytes == 0 {
+            return Some(chunk);
+        }
+        let pending = self.cur_read - self.last_reported_sent;
+        let last = self.file_size.map_or(self.eof, |file_size| self.cur_read >= file_size);
+        let enough_bytes = self.callback_every_bytes > 0 && pending >= self.callback_every_bytes;
+        let enough_time = self.callback_interval.is_some_and(|interval| {
+            self.last_reported_at.is_none_or(|at| now.saturating_duration_since(at) >= interval)
+        });
+        if !(last || enough_bytes || enough_time) {
+            return None;
+        }
+        self.last_reported_sent = self.cur_read;
+        self.last_reported_at = Some(now);
+        Some(pending)
+    }
+
+    /// Runs the callbacks that are notified of every chunk, or of every coalesced group of chunks
+    fn notify_callbacks(&mut self, total: u64, sent: u64, chunk: u64) -> Result<(), TrackableStreamError> {
+        if let Some(callback) = &mut self.callback {
+            // the stream has exclusive access, getting the callback out of the mutex never waits
+            let callback = callback.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+            run_callback(self.panic_policy, || callback(total, sent, chunk))?;
+        }
+        self.notify_observers(|observer| observer.on_chunk(total, sent, chunk))?;
+        if let Some(callback) = &self.async_callback {
+            let mut future = None;
+            run_callback(self.panic_policy, || future = Some(callback(total, sent, chunk)))?;
+            self.pending_callback = future.map(Mutex::new);
+        }
+        if let Some(callback) = &self.delta_callback {
+            run_callback(self.panic_policy, || callback(chunk, total))?;
+        }
+        if let (Some(callback), Some(hasher)) = (&self.checksum_callback, &self.hasher) {
+            let checksum = hasher.current();
+            run_callback(self.panic_policy, || callback(total, sent, chunk, &checksum))?;
+        }
+        for sink in &self.progress_sinks {
+            sink.set_position(sent);
+        }
+        Ok(())
+    }
+
+    fn notify(&mut self, chunk: u64) -> Result<(), TrackableStreamError> {
+        let (total, sent) = (self.file_size.unwrap_or(0), self.cur_read);
+        let now = Instant::now();
+        let started = *self.started.get_or_insert(now);
+        let (throughput, smoothed_throughput) = self.rates.update(chunk, now, started);
+        let reported = self.coalesce(chunk, now);
+        if let Some(chunk) = reported {
+            self.notify_callbacks(total, sent, chunk)?;
+        }
+        if let (Some(callback), true) = (&self.block_callback, self.block_size > 0) {
+            let total_blocks = total.div_ceil(self.block_size);
+            let blocks_sent = if sent >= total { total_blocks } else { sent / self.block_size };
+            if blocks_sent > self.blocks_reported {
+                self.blocks_reported = blocks_sent;
+                run_callback(self.panic_policy, || callback(blocks_sent, total_blocks))?;
+            }
+        }
+        self.progress_handle.store(total, sent);
+        let watched = self.progress_watch.as_ref().is_some_and(|watch| watch.receiver_count() > 0);
+        if self.progress_senders.is_empty()
+            && self.progress_broadcasts.is_empty()
+            && self.progress_writer.is_none()
+            && self.status_senders.is_empty()
+            && (self.progress_callback.is_none() || reported.is_none())
+            && !watched
+        {
+            return Ok(());
+        }
+        let progress = Progress {
+            total,
+            sent: self.cur_read,
+            chunk,
+            id: self.id.clone(),
+            elapsed: now.saturating_duration_since(started),
+            throughput,
+            smoothed_throughput,
+            eta: estimate_remaining(total, sent, smoothed_throughput),
+            finished: self.eof,
+        };
+        if let (Some(callback), Some(reported)) = (&mut self.progress_callback, reported) {
+            let callback = callback.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if reported == chunk {
+                run_callback(self.panic_policy, || callback(&progress))?;
+            } else {
+                let coalesced = Progress { chunk: reported, ..progress.clone() };
+                run_callback(self.panic_policy, || callback(&coalesced))?;
+            }
+        }
+        if let Some(writer) = &mut self.progress_writer {
+            let writer = writer.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Err(err) = writeln!(writer, "{}", progress) {
+                log::warn!("Could not write progress line: {}", err);
+            }
+        }
+        if let (Some(watch), true) = (&self.progress_watch, watched) {
+            watch.send_replace(progress.clone());
+        }
+        if !self.status_senders.is_empty() {
+            let elapsed = self.started.map(|started| started.elapsed()).unwrap_or_default();
+            let line = progress.status_line(elapsed);
+            self.status_senders.retain(|sender| sender.unbounded_send(line.clone()).is_ok());
+        }
+        // receivers that have been dropped are no longer interested in updates
+        self.progress_senders.retain(|sender| sender.unbounded_send(progress.clone()).is_ok());
+        for sender in &self.progress_broadcasts {
+            // sending only fails when there are no subscribers at the moment, more may come later
+            let _ = sender.send(progress.clone());
+        }
+        Ok(())
+    }
+
+    /// Reads the first chunk from the source ahead of time, so that the first call to `poll_next`
+    /// returns it right away. Awaiting this while the connection to S3 is being set up overlaps
+    /// the first read with the request. The chunk triggers the callback when it is read here,
+    /// and is not reported again when it is emitted. Does nothing if a chunk is already buffered.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+    /// let reported = Arc::new(AtomicU64::new(0));
+    /// let counter = reported.clone();
+    ///
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_callback(move |_, _, chunk| { counter.fetch_add(chunk, Ordering::SeqCst); });
+    /// body.prime().await.unwrap();
+    /// assert_eq!(reported.load(Ordering::SeqCst), 2048);
+    ///
+    /// assert_eq!(body.next().await.unwrap().unwrap(), &data[..2048]);
+    /// assert_eq!(reported.load(Ordering::SeqCst), 2048);
+    /// # });
+    /// ```
+    pub async fn prime(&mut self) -> std::io::Result<()> {
+        if self.eof || !self.frames.is_empty() {
+            return Ok(());
+        }
+        self.start().map_err(std::io::Error::other)?;
+        match futures::future::poll_fn(|cx| self.poll_read_chunk(cx)).await {
+            Ok(Some(chunk)) => self.push_frame(chunk).map_err(std::io::Error::other)?,
+            Ok(None) => self.end_of_source().map_err(std::io::Error::other)?,
+            Err(TrackableStreamError::Io { source, .. }) => return Err(source),
+            Err(err) => return Err(std::io::Error::other(err)),
+        }
+        Ok(())
+    }
+
+    /// Waits at most `timeout` for the next chunk of the stream. If the source does not produce a
+    /// chunk in time this returns a `TimeoutOr::Timeout` error, but the read is not aborted: the
+    /// next call picks up where the previous one left off. The timeout applies to each chunk
+    /// individually, it does not bound the duration of the whole upload.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::Duration;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// while let Some(chunk) = body.next_chunk_timeout(Duration::from_secs(5)).await {
+    ///     let chunk = chunk?;
+    ///     assert!(!chunk.is_empty());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next_chunk_timeout(&mut self, timeout: Duration) -> Option<Result<Bytes, TimeoutOr<TrackableStreamError>>> {
+        match tokio::time::timeout(timeout, self.next()).await {
+            Ok(item) => item.map(|res| res.map_err(TimeoutOr::Error)),
+            Err(_) => Some(Err(TimeoutOr::Timeout(timeout))),
+        }
+    }
+
+    /// Marks the upload as failed because of a decision taken outside of the stream. The next
+    /// call to `poll_next` returns `error` instead of reading more of the source, and runs the
+    /// same paths as any other failure: the observer receives `on_error`, the file of a stream
+    /// created with `from_tempfile` is handled according to its failure policy, and progress
+    /// streams are closed. The stream then completes. This has no effect on a stream that has
+    /// already completed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{StreamObserver, TrackableBodyStream, TrackableStreamError};
+    /// #[derive(Default)]
+    /// struct ErrorFlag(AtomicBool);
+    ///
+    /// impl StreamObserver for ErrorFlag {
+    ///     fn on_error(&self, _err: &TrackableStreamError) {
+    ///         self.0.store(true, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// # futures::executor::block_on(async {
+    /// let data = [0u8; 10_000];
+    /// let flag = Arc::new(ErrorFlag::default());
+    /// let mut body = TrackableBodyStream::from(&data[..]);
+    /// body.set_observer(flag.clone());
+    ///
+    /// assert!(body.next().await.unwrap().is_ok());
+    /// body.fail(TrackableStreamError::CallbackPanicked("quota exceeded".to_string()));
+    /// assert!(matches!(body.next().await, Some(Err(TrackableStreamError::CallbackPanicked(_)))));
+    /// assert!(body.next().await.is_none());
+    /// assert!(flag.0.load(Ordering::SeqCst));
+    /// # });
+    /// ```
+    pub fn fail(&mut self, error: TrackableStreamError) {
+        if !self.done {
+            self.failure = Some(error);
+        }
+    }
+
+    /// Drains the stream, discarding the bytes, to measure how fast the source can be read
+    /// independently of S3. The stream behaves exactly as it would during an upload: the
+    /// callback is triggered and the checksum, if enabled, is computed and returned in the
+    /// benchmark.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// let size = body.content_length().unwrap() as u64;
+    ///
+    /// let benchmark = body.measure_read_throughput().await?;
+    /// assert_eq!(benchmark.bytes, size);
+    /// println!("{:.0} bytes/s", benchmark.bytes_per_second);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn measure_read_throughput(mut self) -> Result<ReadBenchmark, TrackableStreamError> {
+        let start = Instant::now();
+        while let Some(chunk) = self.next().await {
+            chunk?;
+        }
+        let duration = start.elapsed();
+        Ok(ReadBenchmark {
+            bytes: self.cur_read,
+            duration,
+            bytes_per_second: self.cur_read as f64 / duration.as_secs_f64().max(f64::EPSILON),
+            checksum: self.checksum.map(|(_, value)| value),
+        })
+    }
+
+    /// Reports the whole source as sent without reading it, for uploads that turn out to be
+    /// unnecessary. The callback receives a final chunk of `0` bytes.
+    pub(crate) fn mark_skipped(&mut self) {
+        self.cur_read = self.file_size.unwrap_or(self.cur_read);
+        // the source was not read, there is nothing to compute a checksum of
+        self.hasher = None;
+        // nothing was read, a panicking callback has nothing to abort
+        let _ = self.notify(0);
+        // the stream will not be polled again to drive an async callback
+        self.pending_callback = None;
+        let _ = self.notify_observers(|observer| observer.on_complete());
+        for sink in &self.progress_sinks {
+            sink.finish();
+        }
+        self.done = true;
+        self.finish(false);
+    }
+
+    /// Computes a checksum with `algorithm` while streaming and returns a slot that receives its
+    /// value once the whole source has been read, so that it can still be retrieved after the
+    /// stream has been handed over to the SDK, for example to check it with
+    /// `ChecksumAlgorithm::verify`.
+    pub fn share_checksum(&mut self, algorithm: ChecksumAlgorithm) -> Arc<Mutex<Option<String>>> {
+        self.set_checksum_algorithm(algorithm);
+        self.shared_checksum.get_or_insert_with(Default::default).clone()
+    }
+
+    /// Rewinds the progress after the source has been rewound to its beginning
+    fn rewind(&mut self) {
+        self.cur_read = 0;
+        self.failure = None;
+        self.progress_handle.store(self.file_size.unwrap_or(0), 0);
+        self.progress_handle.set_finished(false);
+        let initial = Progress { total: self.file_size.unwrap_or(0), id: self.id.clone(), ..Default::default() };
+        self.rates = RateTracker::default();
+        self.last_reported_sent = 0;
+        self.last_reported_at = None;
+        match &self.progress_watch {
+            Some(progress_watch) => {
+                progress_watch.send_replace(initial);
+            }
+            None => self.progress_watch = Some(watch::channel(initial).0),
+        }
+        self.started = None;
+        self.emitted = 0;
+        self.blocks_reported = 0;
+        if let Some(throttle) = &mut self.throttle {
+            throttle.restart();
+        }
+        self.throttle_timer = None;
+        self.pending_callback = None;
+        self.last_activity = None;
+        self.stall_reported = false;
+        self.stall_timer = None;
+        self.hasher = self.checksum_algorithm.map(Hasher::new);
+        self.partial.clear();
+        if let Some(e_tag) = &self.e_tag {
+            e_tag.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).restart();
+        }
+        self.frames.clear();
+        self.eof = false;
+        self.done = false;
+        self.transform = self.transform.take().map(Transform::restart);
+    }
+
+    /// Rewinds the progress for a new attempt of the request, after the source has been
+    /// rewound, and notifies the retry callback and the observers
+    pub(crate) fn retry(&mut self, attempt: u32) {
+        let discarded = self.cur_read;
+        self.rewind();
+        let result = match &self.retry_callback {
+            Some(callback) => run_callback(self.panic_policy, || callback(attempt, discarded)),
+            None => Ok(()),
+        };
+        if let Err(err) = result.and_then(|_| self.notify_observers(|observer| observer.on_retry(attempt, discarded))) {
+            self.fail(err);
+        }
+    }
+
+    fn replace_input<J: BodySource>(self, input: J, file_size: Option<u64>) -> TrackableBodyStream<J> {
+        TrackableBodyStream {
+            input,
+            file_size,
+            cur_read: 0,
+            callback: self.callback,
+            progress_callback: self.progress_callback,
+            rates: RateTracker::default(),
+            callback_interval: self.callback_interval,
+            callback_every_bytes: self.callback_every_bytes,
+            last_reported_sent: 0,
+            last_reported_at: None,
+            checksum_callback: self.checksum_callback,
+            async_callback: self.async_callback,
+            pending_callback: None,
+            delta_callback: self.delta_callback,
+            estimate_callback: self.estimate_callback,
+            block_size: self.block_size,
+            block_callback: self.block_callback,
+            blocks_reported: 0,
+            progress_senders: self.progress_senders,
+            progress_broadcasts: self.progress_broadcasts,
+            buffer_size: self.buffer_size,
+            checksum_algorithm: self.checksum_algorithm,
+            hasher: self.checksum_algorithm.map(Hasher::new),
+            checksum: self.checksum,
+            observer: self.observer,
+            observers: self.observers,
+            min_final_frame: self.min_final_frame,
+            read_ahead: self.read_ahead,
+            frames: VecDeque::new(),
+            eof: false,
+            done: false,
+            progress_writer: self.progress_writer,
+            tee: self.tee,
+            teed: self.teed,
+            panic_policy: self.panic_policy,
+            transform: self.transform.map(|transform| transform.restart()),
+            alignment: self.alignment,
+            temp_file: self.temp_file,
+            progress_sinks: self.progress_sinks,
+            deadline: self.deadline,
+            deadline_timer: self.deadline_timer,
+            cancellation: self.cancellation,
+            pause: self.pause,
+            stall_timeout: self.stall_timeout,
+            stall_callback: self.stall_callback,
+            stall_reported: false,
+            last_activity: None,
+            stall_timer: None,
+            retry_callback: self.retry_callback,
+            emit_eof_marker: self.emit_eof_marker,
+            id: self.id,
+            shared_checksum: self.shared_checksum,
+            e_tag: self.e_tag,
+            read_buffer: self.read_buffer,
+            fill_buffer: self.fill_buffer,
+            partial: BytesMut::new(),
+            emitted: 0,
+            throttle: self.throttle.map(|mut throttle| {
+                throttle.restart();
+                throttle
+            }),
+            throttle_timer: None,
+            started: None,
+            status_senders: self.status_senders,
+            failure: self.failure,
+            progress_watch: self.progress_watch,
+            progress_handle: self.progress_handle,
+        }
+    }
+
+    fn finish(&mut self, complete: bool) {
+        if let Some(hasher) = self.hasher.take() {
+            // the checksum is only meaningful if the whole source was read
+            if complete {
+                let algorithm = hasher.algorithm();
+                let checksum = hasher.finalize();
+                if let Some(shared) = &self.shared_checksum {
+                    *shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(checksum.clone());
+                }
+                self.checksum = Some((algorithm, checksum));
+            }
+        }
+        self.progress_senders.clear();
+        self.progress_broadcasts.clear();
+        self.status_senders.clear();
+        self.progress_watch = None;
+    }
+}
+
+impl<I: BodySource> TrackableBodyStream<I> {
+    /// Reads the next chunk from the source, updating the progress counters and checksum.
+    /// Returns `None` once the end of the source is reached.
+    fn poll_read_chunk(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<Option<Bytes>, TrackableStreamError>> {
+        match self.poll_source(cx) {
             Poll::Ready(res) => {
-                if res.is_err() {
-                    return Poll::Ready(Some(Err(Box::new(res.err().unwrap()))));
+                let chunk = match res {
+                    Ok(chunk) => chunk,
+                    Err(source) => return Poll::Ready(Err(TrackableStreamError::Io {
+                        source,
+                        bytes_read: self.cur_read,
+                    })),
+                };
+                let read_op = chunk.len();
+                if let Some(observer) = &self.observer {
+                    observer.on_read_complete(read_op);
                 }
-                let read_op = res.unwrap();
                 if read_op == 0 {
-                    return Poll::Ready(None);
+                    return Poll::Ready(Ok(None));
                 }
-                mut_self.cur_read += read_op as u64;
-                //buf.resize(read_op, 0u8);
-                if mut_self.callback.is_some() {
-                    mut_self.callback.as_ref().unwrap()(mut_self.file_size, mut_self.cur_read, read_op as u64);
+                self.cur_read += read_op as u64;
+                self.record_activity();
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.update(&chunk);
                 }
-                Poll::Ready(Some(Ok(Bytes::from(Vec::from(&buf[0..read_op])))))
+                self.notify(read_op as u64)?;
+                Poll::Ready(Ok(Some(chunk)))
             },
             Poll::Pending => {
+                if let Some(observer) = &self.observer {
+                    observer.on_pending();
+                }
                 Poll::Pending
             }
         }
     }
 
+    /// Returns the next chunk of the source, filled up to the buffer size with `set_fill_buffer`
+    fn poll_source(&mut self, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<Bytes>> {
+        if !self.fill_buffer {
+            return self.input.poll_chunk_into(cx, &mut self.read_buffer, self.buffer_size);
+        }
+        loop {
+            let missing = self.buffer_size.saturating_sub(self.partial.len()).max(1);
+            let chunk = ready!(self.input.poll_chunk_into(cx, &mut self.read_buffer, missing))?;
+            if chunk.is_empty() {
+                return Poll::Ready(Ok(self.partial.split().freeze()));
+            }
+            if self.partial.is_empty() && chunk.len() >= self.buffer_size {
+                return Poll::Ready(Ok(chunk));
+            }
+            // short reads are the only ones copied
+            self.partial.extend_from_slice(&chunk);
+            if self.partial.len() >= self.buffer_size {
+                return Poll::Ready(Ok(self.partial.split().freeze()));
+            }
+        }
+    }
+
+    fn push_frame(&mut self, chunk: Bytes) -> Result<(), TrackableStreamError> {
+        let frame = match &mut self.transform {
+            Some(transform) => match transform.encode(&chunk) {
+                Ok(encoded) => Bytes::from(encoded),
+                Err(source) => return Err(TrackableStreamError::Io { source, bytes_read: self.cur_read }),
+            },
+            None => chunk,
+        };
+        self.push_encoded_frame(frame);
+        Ok(())
+    }
+
+    fn push_encoded_frame(&mut self, chunk: Bytes) {
+        if chunk.is_empty() {
+            return;
+        }
+        if self.min_final_frame > 0 && chunk.len() < self.min_final_frame {
+            if let Some(last) = self.frames.back_mut() {
+                let mut merged = Vec::with_capacity(last.len() + chunk.len());
+                merged.extend_from_slice(last);
+                merged.extend_from_slice(&chunk);
+                *last = Bytes::from(merged);
+                return;
+            }
+        }
+        self.frames.push_back(chunk);
+    }
+
+    fn end_of_source(&mut self) -> Result<(), TrackableStreamError> {
+        self.eof = true;
+        if let Some(transform) = &mut self.transform {
+            let tail = transform
+                .finish()
+                .map_err(|source| TrackableStreamError::Io { source, bytes_read: self.cur_read })?;
+            self.push_encoded_frame(Bytes::from(tail));
+        }
+        self.progress_handle.set_finished(true);
+        // the last bytes of a source of unknown length may not have been reported yet, and
+        // without a length only this update tells the consumers that the source has ended
+        let coalesced = self.callback_interval.is_some() || self.callback_every_bytes > 0;
+        if (self.last_reported_sent < self.cur_read && coalesced) || self.file_size.unwrap_or(0) == 0 {
+            self.notify(0)?;
+        }
+        Ok(())
+    }
+
+    fn abort(&mut self, err: TrackableStreamError) -> Poll<Option<<Self as Stream>::Item>> {
+        if let Some(observer) = &self.observer {
+            observer.on_error(&err);
+        }
+        // the stream is failing already, a panic in an observer cannot make it fail harder
+        let _ = self.notify_observers(|observer| observer.on_error(&err));
+        if let Some(temp_file) = &mut self.temp_file {
+            temp_file.failed();
+        }
+        self.finish(false);
+        Poll::Ready(Some(Err(err)))
+    }
+
+    /// Fails the stream with `err`, discarding buffered frames, so that it completes right after
+    fn terminate(&mut self, err: TrackableStreamError) -> Poll<Option<<Self as Stream>::Item>> {
+        self.done = true;
+        self.eof = true;
+        self.frames.clear();
+        self.abort(err)
+    }
+
+    fn emit(&mut self, frame: Bytes) -> Poll<Option<<Self as Stream>::Item>> {
+        self.record_activity();
+        if let Some(observer) = &self.observer {
+            observer.on_emit(frame.len());
+        }
+        if let Some(tee) = &mut self.tee {
+            // a new attempt emits the bytes already written again, only the rest is written
+            let skip = self.teed.saturating_sub(self.emitted).min(frame.len() as u64) as usize;
+            let written = tee.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).write_all(&frame[skip..]);
+            if let Err(source) = written {
+                let bytes_read = self.cur_read;
+                return self.terminate(TrackableStreamError::Io { source, bytes_read });
+            }
+            self.teed += (frame.len() - skip) as u64;
+        }
+        self.emitted += frame.len() as u64;
+        if let (Some((ratio, callback)), false) = (&self.estimate_callback, frame.is_empty()) {
+            let estimated = (self.file_size.unwrap_or(0) as f64 * ratio).ceil().max(0.0) as u64;
+            let percent = if estimated == 0 { 0.0 } else { self.emitted as f64 / estimated as f64 * 100.0 };
+            let emitted = self.emitted;
+            if let Err(err) = run_callback(self.panic_policy, || callback(estimated, emitted, percent.clamp(0.0, 99.0))) {
+                return self.terminate(err);
+            }
+        }
+        if let Some(e_tag) = &self.e_tag {
+            e_tag.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).update(&frame);
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+}
+
+impl<I: AsyncReadExt + Unpin> TrackableBodyStream<I> {
+    /// Creates a stream over any `tokio::io::AsyncRead`, such as a decrypting reader, a pipe or
+    /// a network connection, that will produce `len` bytes. The length is reported to the
+    /// callback as the total size and used as the content length of the upload. If the reader
+    /// ends after a different number of bytes the stream fails with a
+    /// `TrackableStreamError::LengthMismatch` error.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use tokio::io::AsyncReadExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let reader = (&[1u8; 300][..]).chain(&[2u8; 200][..]);
+    /// let mut body = TrackableBodyStream::new(reader, 500);
+    /// body.set_callback(|total, sent, _| assert!(sent <= total));
+    /// assert_eq!(body.content_length(), Some(500));
+    ///
+    /// let frames: Vec<_> = body.try_collect().await.unwrap();
+    /// assert_eq!(frames.concat().len(), 500);
+    /// # });
+    /// ```
+    pub fn new(reader: I, len: u64) -> Self {
+        Self::with_input(reader, len)
+    }
+
+    /// Creates a stream over a reader whose length is not known in advance, such as
+    /// `tokio::io::stdin()` or a pipe. The stream reads until the reader ends. The callback
+    /// and `Progress` updates receive a total of `0` and the bytes sent so far, and
+    /// `content_length` returns `None`. A single `put_object` request needs a content length,
+    /// bodies of unknown length are best uploaded in parts.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let piped = &[0u8; 1000][..];
+    /// let mut body = TrackableBodyStream::with_unknown_length(piped);
+    /// assert_eq!(body.content_length(), None);
+    ///
+    /// let sent = Arc::new(AtomicU64::new(0));
+    /// let counter = sent.clone();
+    /// body.set_callback(move |total, sent, _| {
+    ///     assert_eq!(total, 0);
+    ///     counter.store(sent, Ordering::SeqCst);
+    /// });
+    /// let frames: Vec<_> = body.try_collect().await.unwrap();
+    /// assert_eq!(frames.concat().len(), 1000);
+    /// assert_eq!(sent.load(Ordering::SeqCst), 1000);
+    /// # });
+    /// ```
+    pub fn with_unknown_length(reader: I) -> Self {
+        let mut stream = Self::with_input(reader, 0);
+        stream.file_size = None;
+        stream
+    }
+
+    /// Reads the rest of the source in memory and returns a stream that serves it from a `Bytes`
+    /// cursor, keeping the callback and all other settings. This makes retries of small
+    /// uploads cheap: `reset` no longer needs any IO. Sources larger than `MEMORY_CACHE_LIMIT`
+    /// are rejected with an `InvalidInput` error.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// let mut body = body.into_memory_cached().await?;
+    ///
+    /// let first: Vec<_> = (&mut body).try_collect().await?;
+    /// body.reset().await?;
+    /// let second: Vec<_> = (&mut body).try_collect().await?;
+    /// assert_eq!(first.concat(), second.concat());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn into_memory_cached(mut self) -> std::io::Result<TrackableBodyStream<Cursor<Bytes>>> {
+        let remaining = self.file_size.map_or(0, |file_size| file_size.saturating_sub(self.cur_read));
+        if remaining > MEMORY_CACHE_LIMIT {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("sources larger than {} bytes cannot be cached in memory", MEMORY_CACHE_LIMIT),
+            ));
+        }
+        let mut cache = Vec::with_capacity(remaining as usize);
+        // the reported size may be wrong: never read more than the limit
+        (&mut self.input).take(MEMORY_CACHE_LIMIT + 1).read_to_end(&mut cache).await?;
+        if cache.len() as u64 > MEMORY_CACHE_LIMIT {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("sources larger than {} bytes cannot be cached in memory", MEMORY_CACHE_LIMIT),
+            ));
+        }
+        let length = cache.len() as u64;
+        Ok(self.replace_input(Cursor::new(Bytes::from(cache)), Some(length)))
+    }
+}
+
+impl<I: AsyncReadExt + AsyncSeek + Unpin> TrackableBodyStream<I> {
+    /// Creates a stream over the rest of a seekable source, sized by seeking to its end and back
+    /// rather than from file metadata. This is meant for sources where `metadata().len()` does
+    /// not reflect what can be read: block devices and some character devices report a length
+    /// of `0` although they can be read and seeked, and a file that is being appended to may
+    /// have grown since its metadata was read. The size is the distance from the current
+    /// position to the end, the stream reads from the current position. If the source cannot
+    /// seek to its end the stream is created with an unknown length, as with
+    /// `with_unknown_length`. Only failing to seek back is an
+    /// error, since the source would then be left at its end.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> std::io::Result<()> {
+    /// let mut source = Cursor::new(vec![0u8; 100]);
+    /// source.set_position(10);
+    ///
+    /// let body = TrackableBodyStream::from_seekable(source).await?;
+    /// assert_eq!(body.content_length(), Some(90));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_seekable(mut input: I) -> std::io::Result<Self> {
+        let size = match input.stream_position().await {
+            Ok(start) => match input.seek(SeekFrom::End(0)).await {
+                Ok(end) => {
+                    input.seek(SeekFrom::Start(start)).await?;
+                    Some(end.saturating_sub(start))
+                }
+                Err(_) => None,
+            },
+            Err(_) => None,
+        };
+        let mut stream = Self::with_input(input, size.unwrap_or(0));
+        stream.file_size = size;
+        Ok(stream)
+    }
+
+    /// Rewinds the source to its beginning so the stream can be read again, for example to retry
+    /// a failed upload. The progress counters and any checksum being computed are reset.
+    pub async fn reset(&mut self) -> std::io::Result<()> {
+        self.input.seek(SeekFrom::Start(0)).await?;
+        self.rewind();
+        Ok(())
+    }
+
+    /// Reads the rest of the source to compute its checksum, then seeks back so that the stream
+    /// can still be uploaded in full. This is the first pass of a two-pass upload where the
+    /// checksum must be known before the request is sent, for example to store it in the
+    /// object metadata. The callback is not triggered while computing the checksum.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::{ChecksumAlgorithm, TrackableBodyStream, METADATA_SHA256_KEY};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), std::io::Error> {
+    /// let mut body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+    /// let checksum = body.precompute_checksum(ChecksumAlgorithm::Sha256).await?;
+    ///
+    /// let metadata = body.object_metadata();
+    /// assert_eq!(metadata.get(METADATA_SHA256_KEY), Some(&checksum));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn precompute_checksum(&mut self, algorithm: ChecksumAlgorithm) -> std::io::Result<String> {
+        let start = self.input.stream_position().await?;
+        let mut hasher = Hasher::new(algorithm);
+        let mut buf = vec![0u8; self.buffer_size.max(DEFAULT_BUFFER_SIZE)];
+        loop {
+            let read_op = self.input.read(&mut buf).await?;
+            if read_op == 0 {
+                break;
+            }
+            hasher.update(&buf[0..read_op]);
+        }
+        self.input.seek(SeekFrom::Start(start)).await?;
+
+        let checksum = hasher.finalize();
+        self.checksum = Some((algorithm, checksum.clone()));
+        Ok(checksum)
+    }
+}
+
+impl<I: BodySource + Send + Sync + 'static> TrackableBodyStream<I> {
+    /// Consumes this body stream and returns a `BodyStream` object that can be passed to the `body`
+    /// method of the `put_object` call in the AWS SDK for Rust. The end of stream marker of
+    /// `set_emit_eof_marker` is turned off. Because the stream is consumed it cannot be converted
+    /// twice:
+    ///
+    /// ```compile_fail
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// let body = TrackableBodyStream::from(&b"sample"[..]);
+    /// let first = body.to_s3_stream();
+    /// let second = body.to_s3_stream();
+    /// ```
+    pub fn to_s3_stream(mut self) -> ByteStream {
+        self.emit_eof_marker = false;
+        ByteStream::new(body::sdk_body(self))
+    }
+}
+
+impl<I: AsyncReadExt + AsyncSeek + Unpin + Send + 'static> TrackableBodyStream<I> {
+    /// Consumes this body stream and returns a `ByteStream` the SDK can replay when it retries
+    /// the request, unlike the one returned by `to_s3_stream`. Every attempt seeks the source
+    /// back to its beginning and rewinds the progress, so a retried upload never reports more
+    /// than 100%, and calls the callback set with `set_retry_callback`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::{io::Cursor, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+    /// # use aws_sdk_s3::types::ByteStream;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let sent = Arc::new(AtomicU64::new(0));
+    /// let recorder = sent.clone();
+    /// let mut body = TrackableBodyStream::new(Cursor::new(vec![1u8; 5000]), 5000)
+    ///     .with_callback(move |_, sent, _| recorder.store(sent, Ordering::SeqCst));
+    /// body.set_retry_callback(|attempt, discarded| println!("retry {}, {} bytes discarded", attempt, discarded));
+    ///
+    /// let first = body.to_retryable_s3_stream().into_inner();
+    /// // the SDK clones the body before every retry
+    /// let retry = first.try_clone().unwrap();
+    /// assert_eq!(ByteStream::new(first).collect().await.unwrap().into_bytes().len(), 5000);
+    /// assert_eq!(ByteStream::new(retry).collect().await.unwrap().into_bytes().len(), 5000);
+    /// assert_eq!(sent.load(Ordering::SeqCst), 5000);
+    /// # }
+    /// ```
+    pub fn to_retryable_s3_stream(mut self) -> ByteStream {
+        self.emit_eof_marker = false;
+        ByteStream::new(retry::retryable_body(self))
+    }
+}
+
+impl<I: BodySource> TrackableBodyStream<I> {
+    /// Consumes this body stream and returns a `tokio::io::AsyncRead` over the bytes it emits,
+    /// for clients that upload from a reader rather than a stream, such as `put_object_stream` of
+    /// the `rust-s3` crate. The callbacks are triggered as the reader is consumed, as with the
+    /// stream. Errors of the stream are converted to `std::io::Error`. The end of stream marker
+    /// of `set_emit_eof_marker` is turned off.
+    ///
+    /// # Examples
+    /// ```
+    /// # use tokio::io::AsyncReadExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![1u8; 5000];
+    /// let mut reader = TrackableBodyStream::from(data.as_slice())
+    ///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+    ///     .into_async_read();
+    ///
+    /// let mut read = Vec::new();
+    /// reader.read_to_end(&mut read).await.unwrap();
+    /// assert_eq!(read, data);
+    /// # });
+    /// ```
+    pub fn into_async_read(mut self) -> impl tokio::io::AsyncRead + Unpin {
+        self.emit_eof_marker = false;
+        tokio_util::io::StreamReader::new(self)
+    }
+}
+
+impl<I: BodySource> Stream for TrackableBodyStream<I> {
+    type Item = Result<Bytes, TrackableStreamError>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut_self = self.get_mut();
+        if let Some(observer) = &mut_self.observer {
+            observer.on_poll_start();
+        }
+        if let Err(err) = mut_self.start() {
+            return mut_self.terminate(err);
+        }
+        if let Some(err) = mut_self.failure.take() {
+            return mut_self.terminate(err);
+        }
+        if !mut_self.done && mut_self.poll_deadline(cx) {
+            let bytes_read = mut_self.cur_read;
+            return mut_self.terminate(TrackableStreamError::DeadlineExceeded { bytes_read });
+        }
+        if !mut_self.done && mut_self.poll_cancelled(cx) {
+            let bytes_read = mut_self.cur_read;
+            return mut_self.terminate(TrackableStreamError::Cancelled { bytes_read });
+        }
+        if !mut_self.done && mut_self.pause.as_ref().is_some_and(|pause| pause.poll_paused(cx)) {
+            // a paused stream is not stalled
+            mut_self.record_activity();
+            return Poll::Pending;
+        }
+        if !mut_self.done {
+            if let Err(err) = mut_self.poll_stall(cx) {
+                return mut_self.terminate(err);
+            }
+        }
+
+        // the last frame is held back while it may still absorb a small final chunk
+        let reserved = usize::from(mut_self.min_final_frame > 0);
+        let target = mut_self.read_ahead + reserved + 1;
+
+        loop {
+            if !mut_self.poll_async_callback(cx) {
+                return Poll::Pending;
+            }
+            if !mut_self.eof && mut_self.frames.len() < target {
+                match mut_self.poll_read_chunk(cx) {
+                    Poll::Ready(Ok(Some(chunk))) => {
+                        if let Err(err) = mut_self.push_frame(chunk) {
+                            return mut_self.terminate(err);
+                        }
+                        continue;
+                    }
+                    Poll::Ready(Ok(None)) => {
+                        if let Err(err) = mut_self.end_of_source() {
+                            return mut_self.terminate(err);
+                        }
+                        continue;
+                    }
+                    Poll::Ready(Err(err)) => return mut_self.terminate(err),
+                    Poll::Pending => {
+                        if mut_self.frames.len() <= reserved {
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            }
+
+            if !mut_self.frames.is_empty() && !mut_self.poll_throttle(cx) {
+                return Poll::Pending;
+            }
+            // buffered frames are always flushed before the stream completes
+            if let Some(frame) = mut_self.frames.pop_front() {
+                return mut_self.emit(frame);
+            }
+            if mut_self.done {
+                return Poll::Ready(None);
+            }
+            mut_self.done = true;
+            if let Some(expected) = mut_self.file_size.filter(|expected| *expected != mut_self.cur_read) {
+                return mut_self.terminate(TrackableStreamError::LengthMismatch {
+                    expected,
+                    actual: mut_self.cur_read,
+                });
+            }
+            if let Some(observer) = &mut_self.observer {
+                observer.on_complete();
+            }
+            // every byte has been read, a panicking observer has nothing left to abort
+            let _ = mut_self.notify_observers(|observer| observer.on_complete());
+            if let Some(temp_file) = &mut mut_self.temp_file {
+                temp_file.remove();
+            }
+            for sink in &mut_self.progress_sinks {
+                sink.finish();
+            }
+            let flushed = match &mut mut_self.tee {
+                Some(tee) => tee.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).flush(),
+                None => Ok(()),
+            };
+            if let Err(source) = flushed {
+                let bytes_read = mut_self.cur_read;
+                return mut_self.terminate(TrackableStreamError::Io { source, bytes_read });
+            }
+            if let Some((_, callback)) = &mut_self.estimate_callback {
+                let emitted = mut_self.emitted;
+                if let Err(err) = run_callback(mut_self.panic_policy, || callback(emitted, emitted, 100.0)) {
+                    return mut_self.terminate(err);
+                }
+            }
+            mut_self.finish(true);
+            if mut_self.emit_eof_marker {
+                return mut_self.emit(Bytes::new());
+            }
+            return Poll::Ready(None);
+        }
+    }
+
+    /// Reports a number of frames, not of bytes, see `remaining_bytes` for the latter. The lower
+    /// bound counts the buffered frames and, without an encoding or `set_min_final_frame`,
+    /// which can merge frames, the buffers still needed to read the rest of the source. There
+    /// is no upper bound until the end of the source has been reached, since a source can
+    /// return fewer bytes than the buffer size on every read.
     fn size_hint(&self) -> (usize, Option<usize>) {
-        ((self.file_size - self.cur_read) as usize, Some(self.file_size as usize))
+        if self.done {
+            return (0, Some(0));
+        }
+        if self.eof {
+            let left = self.frames.len() + usize::from(self.emit_eof_marker);
+            return (left, Some(left));
+        }
+        let unread = match self.transform.is_none() && self.min_final_frame == 0 {
+            true => self.remaining_bytes().unwrap_or(0).div_ceil(self.buffer_size as u64) as usize,
+            false => 0,
+        };
+        (self.frames.len() + unread, None)
     }
 }
@@ -0,0 +1,325 @@
+use std::{cmp::Reverse, collections::{BTreeMap, HashMap}, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, MutexGuard}, time::Instant};
+
+use aws_sdk_s3::Client;
+use tokio::{sync::{oneshot, watch}, task::JoinHandle};
+
+use crate::{directory::{upload_path, DEFAULT_MULTIPART_THRESHOLD}, Progress, TransferError};
+
+/// The bytes of all the jobs submitted to a `TransferManager`
+#[derive(Default)]
+struct Totals {
+    total: u64,
+    sent: u64,
+}
+
+/// The jobs waiting for a transfer slot, highest priority first and in the order they were
+/// submitted within a priority
+struct Queue {
+    concurrency: usize,
+    running: usize,
+    next_id: u64,
+    /// Signals a waiting job that it can start, dropped to cancel it
+    waiting: BTreeMap<(Reverse<i32>, u64), oneshot::Sender<()>>,
+    /// The priority of every waiting job, by id
+    priorities: HashMap<u64, i32>,
+}
+
+impl Queue {
+    /// Starts waiting jobs until all the transfer slots are taken
+    fn dispatch(&mut self) {
+        while self.running < self.concurrency {
+            let Some(((_, id), start)) = self.waiting.pop_first() else {
+                break;
+            };
+            self.priorities.remove(&id);
+            if start.send(()).is_ok() {
+                self.running += 1;
+            }
+        }
+    }
+
+    fn take(&mut self, id: u64) -> Option<(i32, oneshot::Sender<()>)> {
+        let priority = self.priorities.remove(&id)?;
+        self.waiting.remove(&(Reverse(priority), id)).map(|start| (priority, start))
+    }
+}
+
+/// A transfer slot taken by a running job, released when the job completes
+struct Slot(Arc<Shared>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut queue = self.0.queue();
+        queue.running -= 1;
+        queue.dispatch();
+    }
+}
+
+/// The state shared by a `TransferManager` and the tasks of its jobs
+struct Shared {
+    client: Client,
+    queue: Mutex<Queue>,
+    started: Instant,
+    /// Held while the progress is sent, so that the feed observes the totals in order
+    totals: Mutex<Totals>,
+    progress: watch::Sender<Progress>,
+}
+
+impl Shared {
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Updates the totals with `update`, which returns the bytes sent since the last update,
+    /// and publishes the new progress
+    fn update(&self, update: impl FnOnce(&mut Totals) -> u64) {
+        let mut totals = self.totals.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let chunk = update(&mut totals);
+        self.progress.send_replace(Progress {
+            total: totals.total,
+            sent: totals.sent,
+            chunk,
+            elapsed: self.started.elapsed(),
+            ..Default::default()
+        });
+    }
+}
+
+/// Runs many uploads with a limit on the number of transfers in flight, and aggregates their
+/// progress in a single feed.
+///
+/// Each job uploads a file, with a single `put_object` request or, from
+/// `with_multipart_threshold`, with a `MultipartUploader`. Jobs can be submitted at any time,
+/// each runs on its own Tokio task once fewer than `concurrency` transfers are running. Waiting
+/// jobs start by priority, highest first, and in the order they were submitted within a
+/// priority. Until it starts, the priority of a job can be changed and the job can be
+/// cancelled from its `TransferHandle`, so that an upload requested by the user can overtake a
+/// background batch.
+///
+/// The feed of `progress_receiver` holds a `Progress` whose `total` is the size of all the jobs
+/// submitted so far and `sent` the bytes sent over all of them, which drives a single overall
+/// progress bar. The throughput fields are not computed. A job that fails is taken out of both
+/// counters, so that the feed can still reach completion.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::TransferManager;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+/// let manager = TransferManager::new(client, 4);
+///
+/// let mut receiver = manager.progress_receiver();
+/// tokio::spawn(async move {
+///     while receiver.changed().await.is_ok() {
+///         let progress = receiver.borrow().clone();
+///         println!("{}/{} bytes", progress.sent, progress.total);
+///     }
+/// });
+///
+/// let mut jobs = Vec::new();
+/// for name in ["a.tar", "b.tar", "c.tar"] {
+///     jobs.push(manager.upload("bucket", name, format!("./{}", name)).await?);
+/// }
+/// for job in jobs {
+///     job.wait().await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct TransferManager {
+    shared: Arc<Shared>,
+    multipart_threshold: u64,
+}
+
+/// A job submitted to a `TransferManager`
+pub struct TransferHandle {
+    id: u64,
+    shared: Arc<Shared>,
+    task: JoinHandle<Result<(), TransferError>>,
+}
+
+impl TransferHandle {
+    /// Changes the priority of the job. Returns `false` if the job has already started, or has
+    /// been cancelled, and the priority no longer matters.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::TransferManager;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    /// let manager = TransferManager::new(client, 2);
+    ///
+    /// let mut batch = Vec::new();
+    /// for index in 0..100 {
+    ///     batch.push(manager.upload_with_priority("bucket", format!("sync/{}", index), format!("./sync/{}", index), -1).await?);
+    /// }
+    /// // requested by the user, starts before the waiting jobs of the batch
+    /// let requested = manager.upload("bucket", "report.pdf", "./report.pdf").await?;
+    /// // the user opened this file of the batch, it goes next
+    /// batch[50].set_priority(1);
+    /// // and deleted this one, it does not need to be synced anymore
+    /// batch[99].cancel();
+    /// requested.wait().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_priority(&self, priority: i32) -> bool {
+        let mut queue = self.shared.queue();
+        let Some((_, start)) = queue.take(self.id) else {
+            return false;
+        };
+        queue.priorities.insert(self.id, priority);
+        queue.waiting.insert((Reverse(priority), self.id), start);
+        true
+    }
+
+    /// Cancels the job if it has not started yet, `wait` then returns an `Interrupted` error
+    /// and the size of the job is taken out of the feed. Returns `false` if the job has already
+    /// started. A running job cannot be cancelled.
+    pub fn cancel(&self) -> bool {
+        self.shared.queue().take(self.id).is_some()
+    }
+
+    /// Waits for the job to complete and returns its outcome. Dropping the handle does not
+    /// stop the job.
+    pub async fn wait(self) -> Result<(), TransferError> {
+        match self.task.await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, err).into()),
+        }
+    }
+}
+
+impl TransferManager {
+    /// Creates a manager that runs at most `concurrency` transfers at the same time
+    pub fn new(client: Client, concurrency: usize) -> Self {
+        let shared = Shared {
+            client,
+            queue: Mutex::new(Queue {
+                concurrency: concurrency.max(1),
+                running: 0,
+                next_id: 0,
+                waiting: BTreeMap::new(),
+                priorities: HashMap::new(),
+            }),
+            started: Instant::now(),
+            totals: Mutex::new(Totals::default()),
+            progress: watch::channel(Progress::default()).0,
+        };
+        Self { shared: Arc::new(shared), multipart_threshold: DEFAULT_MULTIPART_THRESHOLD }
+    }
+
+    /// Sets the size from which files are uploaded with a multipart upload, 16 MB by default.
+    /// A multipart upload counts as one transfer, it has up to four parts in flight.
+    pub fn with_multipart_threshold(mut self, threshold: u64) -> Self {
+        self.multipart_threshold = threshold;
+        self
+    }
+
+    /// Submits the upload of the file at `path` to `bucket`/`key`, with a priority of `0`, and
+    /// returns a handle to wait for it. The size of the file is added to the total of the feed
+    /// right away, the upload starts once a transfer slot is free. Must be called within a
+    /// Tokio runtime.
+    pub async fn upload(
+        &self,
+        bucket: impl Into<String>,
+        key: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> Result<TransferHandle, TransferError> {
+        self.upload_with_priority(bucket, key, path, 0).await
+    }
+
+    /// Submits an upload like `upload`, with the given priority. Waiting jobs with a higher
+    /// priority start first.
+    pub async fn upload_with_priority(
+        &self,
+        bucket: impl Into<String>,
+        key: impl Into<String>,
+        path: impl Into<PathBuf>,
+        priority: i32,
+    ) -> Result<TransferHandle, TransferError> {
+        let (bucket, key, path) = (bucket.into(), key.into(), path.into());
+        let len = tokio::fs::metadata(&path).await?.len();
+        self.shared.update(|totals| {
+            totals.total += len;
+            0
+        });
+
+        let (start, started) = oneshot::channel();
+        let id = {
+            let mut queue = self.shared.queue();
+            let id = queue.next_id;
+            queue.next_id += 1;
+            queue.priorities.insert(id, priority);
+            queue.waiting.insert((Reverse(priority), id), start);
+            queue.dispatch();
+            id
+        };
+
+        let (shared, multipart_threshold) = (self.shared.clone(), self.multipart_threshold);
+        let task = tokio::spawn(async move {
+            let reported = Arc::new(AtomicU64::new(0));
+            let result = match started.await {
+                Ok(()) => {
+                    let _slot = Slot(shared.clone());
+                    Self::run(&shared, &bucket, &key, &path, len, multipart_threshold, reported.clone()).await
+                }
+                Err(_) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "the job was cancelled").into()),
+            };
+            if result.is_err() {
+                let sent = reported.load(Ordering::SeqCst);
+                shared.update(|totals| {
+                    totals.total -= len;
+                    totals.sent -= sent;
+                    0
+                });
+            }
+            result
+        });
+        Ok(TransferHandle { id, shared: self.shared.clone(), task })
+    }
+
+    async fn run(
+        shared: &Arc<Shared>,
+        bucket: &str,
+        key: &str,
+        path: &Path,
+        len: u64,
+        multipart_threshold: u64,
+        reported: Arc<AtomicU64>,
+    ) -> Result<(), TransferError> {
+        let (report_to, reported_by) = (shared.clone(), reported.clone());
+        let report = move |_, _, chunk| {
+            reported_by.fetch_add(chunk, Ordering::SeqCst);
+            report_to.update(|totals| {
+                totals.sent += chunk;
+                chunk
+            });
+        };
+        let (retract_from, retracted_by) = (shared.clone(), reported);
+        let retract = move |discarded| {
+            retracted_by.fetch_sub(discarded, Ordering::SeqCst);
+            retract_from.update(|totals| {
+                totals.sent -= discarded;
+                0
+            });
+        };
+        upload_path(&shared.client, bucket, key, path, len, multipart_threshold, report, retract).await
+    }
+
+    /// Returns a receiver of the aggregated progress of all the jobs, updated every time a chunk
+    /// of any job is sent and every time a job is submitted or fails
+    pub fn progress_receiver(&self) -> watch::Receiver<Progress> {
+        self.shared.progress.subscribe()
+    }
+
+    /// Returns the aggregated progress of all the jobs
+    pub fn progress(&self) -> Progress {
+        self.shared.progress.borrow().clone()
+    }
+}
@@ -0,0 +1,102 @@
+use std::{fs::File, os::unix::io::AsRawFd, path::Path, task::{Context, Poll}};
+
+use crate::{BodySource, Bytes, TrackableBodyStream};
+
+/// A read-only mapping of a whole file, unmapped when the last chunk referring to it is dropped
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// the mapping is read-only and owned exclusively, like a `Box<[u8]>`
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl AsRef<[u8]> for Mapping {
+    fn as_ref(&self) -> &[u8] {
+        // SAFETY: the mapping is valid for `len` bytes until it is dropped
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the pointer and the length are those returned by `mmap` in `map`
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// A source over a memory-mapped file, created with `TrackableBodyStream::from_mmap`. Chunks
+/// are views into the mapping: nothing is read with a system call and nothing is copied, the
+/// pages are loaded by the kernel as the chunks are sent.
+pub struct MappedFile {
+    rest: Bytes,
+}
+
+impl MappedFile {
+    fn map(file: &File) -> std::io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        // an empty mapping is invalid
+        if len == 0 {
+            return Ok(Self { rest: Bytes::new() });
+        }
+        // SAFETY: a new read-only shared mapping of the file descriptor, checked for failure
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        // the file is read front to back, a failed hint only costs read-ahead
+        // SAFETY: the range is the mapping created above
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Self { rest: Bytes::from_owner(Mapping { ptr, len }) })
+    }
+}
+
+impl BodySource for MappedFile {
+    fn poll_chunk(&mut self, _cx: &mut Context<'_>, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        let len = self.rest.len().min(max_len.max(1));
+        Poll::Ready(Ok(self.rest.split_to(len)))
+    }
+}
+
+impl TrackableBodyStream<MappedFile> {
+    /// Creates a stream over a memory-mapped file. Chunks are `Bytes` views into the mapping,
+    /// which avoids the read system calls and the copies of `from_path` for large local files.
+    /// The progress callback is triggered as with any other source. The mapping is released
+    /// once the stream and all the chunks it emitted have been dropped.
+    ///
+    /// Reading a chunk never blocks, but sending it can fault pages in from disk on the thread
+    /// polling the request. For files on slow or network storage prefer `from_path`.
+    ///
+    /// # Safety
+    /// The file must not be truncated or modified, by this process or another one, until the
+    /// stream and its chunks have been dropped. Truncating it makes further accesses to the
+    /// mapping fail with `SIGBUS`, and modifying it changes bytes that the stream and the S3
+    /// client treat as immutable.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::TryStreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// // SAFETY: the sample file is not modified while it is uploaded
+    /// let body = unsafe { TrackableBodyStream::from_mmap("./examples/sample.jpeg")? }
+    ///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total));
+    /// assert_eq!(body.content_length(), Some(std::fs::metadata("./examples/sample.jpeg")?.len() as i64));
+    ///
+    /// let frames: Vec<_> = body.try_collect().await?;
+    /// assert_eq!(frames.concat(), std::fs::read("./examples/sample.jpeg")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub unsafe fn from_mmap(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let source = MappedFile::map(&file)?;
+        let len = source.rest.len() as u64;
+        Ok(Self::with_input(source, len))
+    }
+}
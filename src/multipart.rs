@@ -0,0 +1,469 @@
+use std::{io::{self, SeekFrom}, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex as SyncMutex}};
+
+use aws_sdk_s3::{model::CompletedMultipartUpload, output::CompleteMultipartUploadOutput, Client};
+use futures::{StreamExt, TryStreamExt};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt}, sync::Mutex};
+
+use crate::{CallbackFn, CheckpointPart, ExpectedETag, MultipartCheckpoint, TrackableBodyStream, TransferError};
+
+/// The smallest part S3 accepts in a multipart upload, except for the last one
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// The largest number of parts S3 accepts in a multipart upload
+pub const MAX_PARTS: u64 = 10_000;
+
+/// The callback set with `MultipartUploader::with_part_callback`, receiving the number and
+/// the size of every part once it has been uploaded
+pub(crate) type PartCallbackFn = dyn Fn(i32, u64) + Sync + Send + 'static;
+
+/// The callback set with `MultipartUploader::with_part_progress_callback`: the number of the
+/// part followed by the arguments of `CallbackFn`
+type PartProgressFn = dyn Fn(i32, u64, u64, u64) + Sync + Send + 'static;
+
+/// The name `MultipartUploader` was first introduced under, for uploads with several parts in
+/// flight
+pub type ParallelUploadTracker = MultipartUploader;
+
+/// Uploads a file to S3 as a multipart upload, for files too large for a single `put_object`
+/// request, which is limited to 5 GB, optionally with several parts in flight at the same time.
+///
+/// The uploader creates the multipart upload, uploads the parts and completes the upload. The
+/// file is split in parts of `with_part_size` bytes, each read by its own
+/// `TrackableBodyStream` over its range of the file. At most `with_concurrency` parts are
+/// uploaded at once. The callback receives the progress of the whole file, aggregated over
+/// all parts in flight, while the part callback is triggered as each part completes. A part
+/// that fails is retried on its own, up to `with_max_retries` times. The aggregate progress
+/// never goes backwards: the bytes a failed attempt had read are read again by the next
+/// attempt, and are only reported once the total exceeds what was reported before. If a part
+/// still fails the multipart upload is aborted, so that S3 does not keep the parts already
+/// uploaded.
+///
+/// With `with_checkpoint` the state of the upload is saved to a file as parts complete, and a
+/// failed upload is left open on S3 instead, so that it can be resumed later.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::MultipartUploader;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+///
+/// MultipartUploader::new(client, "bucket", "backup.tar", "./backup.tar")
+///     .with_part_size(8 * 1024 * 1024)
+///     .with_concurrency(4)
+///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+///     .with_part_callback(|part, size| println!("part {} ({} bytes) done", part, size))
+///     .upload()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MultipartUploader {
+    client: Client,
+    bucket: String,
+    key: String,
+    path: PathBuf,
+    part_size: u64,
+    concurrency: usize,
+    max_retries: usize,
+    callback: Option<Arc<CallbackFn>>,
+    part_callback: Option<Arc<PartCallbackFn>>,
+    part_progress: Option<Arc<PartProgressFn>>,
+    checkpoint: Option<PathBuf>,
+    verify_e_tags: bool,
+}
+
+/// The progress of the whole upload, shared by the parts in flight
+pub(crate) struct AggregateProgress {
+    total: AtomicU64,
+    /// The bytes read by all current attempts and all completed parts
+    sent: AtomicU64,
+    /// The highest value passed to the callbacks so far. The lock is held while the callbacks
+    /// run, so that they observe the values in increasing order.
+    reported: SyncMutex<u64>,
+}
+
+impl AggregateProgress {
+    pub(crate) fn new(total: u64, completed: u64) -> Self {
+        Self { total: AtomicU64::new(total), sent: AtomicU64::new(completed), reported: SyncMutex::new(completed) }
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    /// Adds the bytes of a chunk and calls `notify` with the new total and the increase over
+    /// the last reported total, unless the total has not exceeded it yet
+    pub(crate) fn add(&self, chunk: u64, notify: impl FnOnce(u64, u64)) {
+        let sent = self.sent.fetch_add(chunk, Ordering::SeqCst) + chunk;
+        let mut reported = self.reported.lock().unwrap_or_else(|err| err.into_inner());
+        if sent > *reported {
+            let increase = sent - *reported;
+            *reported = sent;
+            notify(sent, increase);
+        }
+    }
+
+    /// Takes back the bytes read by a failed attempt, they will be read again
+    pub(crate) fn retract(&self, bytes: u64) {
+        self.sent.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    /// Takes a transfer that failed for good out of the progress, `len` bytes out of the total
+    /// and the `sent` bytes it had read out of the bytes sent, and calls `notify` with the new
+    /// total and bytes sent
+    pub(crate) fn remove(&self, len: u64, sent: u64, notify: impl FnOnce(u64, u64)) {
+        let mut reported = self.reported.lock().unwrap_or_else(|err| err.into_inner());
+        let total = self.total.fetch_sub(len, Ordering::SeqCst) - len;
+        *reported = self.sent.fetch_sub(sent, Ordering::SeqCst) - sent;
+        notify(total, *reported);
+    }
+}
+
+/// A range of the file uploaded as one part
+struct Part {
+    number: i32,
+    offset: u64,
+    len: u64,
+}
+
+impl MultipartUploader {
+    /// Prepares the upload of the file at `path` to `bucket`/`key`, in parts of `MIN_PART_SIZE`
+    /// bytes, four at a time, retrying each part up to three times.
+    pub fn new(client: Client, bucket: impl Into<String>, key: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+            key: key.into(),
+            path: path.into(),
+            part_size: MIN_PART_SIZE,
+            concurrency: 4,
+            max_retries: 3,
+            callback: None,
+            part_callback: None,
+            part_progress: None,
+            checkpoint: None,
+            verify_e_tags: false,
+        }
+    }
+
+    /// Sets the size of the parts, the last part may be smaller. Sizes below `MIN_PART_SIZE`
+    /// are rejected by S3 for all parts but the last. The size is increased if the file would
+    /// otherwise need more than `MAX_PARTS` parts.
+    pub fn with_part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Sets the maximum number of parts uploaded at the same time. Each part in flight holds
+    /// one connection and reads its own range of the file, the callback still receives a
+    /// single total that only increases.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// # use trackable_s3_stream::MultipartUploader;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    ///
+    /// let last = Arc::new(AtomicU64::new(0));
+    /// MultipartUploader::new(client, "bucket", "backup.tar", "./backup.tar")
+    ///     .with_concurrency(8)
+    ///     .with_callback(move |_, sent, _| assert!(last.swap(sent, Ordering::SeqCst) < sent))
+    ///     .upload()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets how many times a failed part is retried before the upload is aborted
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the progress callback, with the same arguments as the callback of a
+    /// `TrackableBodyStream`: the size of the file, the bytes sent so far over all parts, and
+    /// the increase since the previous call. The callback is called from the tasks of all the
+    /// parts in flight, one call at a time.
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback that receives the number and the size of every part once S3 has
+    /// confirmed it. Parts complete in no particular order.
+    pub fn with_part_callback(mut self, callback: impl Fn(i32, u64) + Sync + Send + 'static) -> Self {
+        self.part_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback that receives the number of the part a chunk was read from, followed by
+    /// the same arguments as the progress callback: the size of the file, the bytes sent so far
+    /// over all parts, and the increase since the previous call.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::MultipartUploader;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    ///
+    /// MultipartUploader::new(client, "bucket", "backup.tar", "./backup.tar")
+    ///     .with_concurrency(1)
+    ///     .with_part_progress_callback(|part, total, sent, _| {
+    ///         println!("part {}: {}/{} bytes", part, sent, total)
+    ///     })
+    ///     .upload()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_part_progress_callback(mut self, callback: impl Fn(i32, u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.part_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Saves the state of the upload to the file at `path` each time a part completes, and
+    /// resumes the upload recorded there if the file already exists. Parts confirmed before the
+    /// interruption are not uploaded again and the progress callback starts from the bytes
+    /// they cover. The file is removed once the upload completes.
+    ///
+    /// The checkpoint must have been saved for the same bucket, key and file size, otherwise
+    /// the upload fails with an `InvalidData` error. The part size of the checkpoint takes
+    /// precedence over `with_part_size`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use trackable_s3_stream::MultipartUploader;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// let sdk_config = aws_config::from_env().load().await;
+    /// let client = aws_sdk_s3::Client::new(&sdk_config);
+    ///
+    /// // run again after an interruption to continue where the previous run stopped
+    /// MultipartUploader::new(client, "bucket", "backup.tar", "./backup.tar")
+    ///     .with_checkpoint("./backup.tar.checkpoint")
+    ///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+    ///     .upload()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// Computes the MD5 digest of every part as it is uploaded and compares it with the ETag S3
+    /// returns for the part, then compares the ETag of the completed object with the one
+    /// derived from the parts, see `ExpectedETag`. A part with a wrong ETag is retried like a
+    /// part that failed to upload. The ETags of objects encrypted with SSE-KMS or SSE-C are not
+    /// MD5 digests, do not enable the verification for those.
+    pub fn with_e_tag_verification(mut self) -> Self {
+        self.verify_e_tags = true;
+        self
+    }
+
+    /// Runs the multipart upload and returns the output of `complete_multipart_upload`
+    pub async fn upload(self) -> Result<CompleteMultipartUploadOutput, TransferError> {
+        let total = tokio::fs::metadata(&self.path).await?.len();
+        let checkpoint = match self.resume(total).await? {
+            Some(checkpoint) => checkpoint,
+            None => self.create(total).await?,
+        };
+        let part_size = checkpoint.part_size;
+        let parts: Vec<_> = (0..total.div_ceil(part_size).max(1))
+            .map(|index| Part {
+                number: index as i32 + 1,
+                offset: index * part_size,
+                len: part_size.min(total - index * part_size),
+            })
+            .filter(|part| !checkpoint.is_completed(part.number))
+            .collect();
+        let upload_id = checkpoint.upload_id.clone();
+
+        let progress = Arc::new(AggregateProgress::new(total, checkpoint.completed_bytes()));
+        let checkpoint = Mutex::new(checkpoint);
+        let uploads = futures::stream::iter(parts)
+            .map(|part| self.upload_part_with_retries(&upload_id, part, progress.clone(), &checkpoint))
+            .buffer_unordered(self.concurrency)
+            .try_collect::<()>()
+            .await;
+        if let Err(err) = uploads {
+            if self.checkpoint.is_none() {
+                // the error of the part matters more than a failure to clean up after it
+                let _ = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .upload_id(&upload_id)
+                    .send()
+                    .await;
+            }
+            return Err(err);
+        }
+        let mut completed = checkpoint.into_inner().completed_parts();
+        completed.sort_by_key(|part| part.part_number());
+        let expected = match self.verify_e_tags {
+            true => Some(ExpectedETag::from_part_e_tags(completed.iter().map(|part| part.e_tag().unwrap_or_default()))?),
+            false => None,
+        };
+
+        let output = self
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(completed)).build())
+            .send()
+            .await
+            .map_err(|err| TransferError::s3("CompleteMultipartUpload", err))?;
+        if let Some(expected) = expected {
+            expected.verify(&output)?;
+        }
+        if let Some(path) = &self.checkpoint {
+            match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(output)
+    }
+
+    /// Loads the checkpoint of an interrupted upload, if there is one
+    async fn resume(&self, total: u64) -> Result<Option<MultipartCheckpoint>, TransferError> {
+        let Some(path) = &self.checkpoint else {
+            return Ok(None);
+        };
+        let checkpoint = match MultipartCheckpoint::load(path).await {
+            Ok(checkpoint) => checkpoint,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if checkpoint.bucket != self.bucket || checkpoint.key != self.key || checkpoint.file_size != total {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is the checkpoint of a different upload", path.display()),
+            )
+            .into());
+        }
+        log::info!(
+            "Resuming upload {} with {} parts already completed",
+            checkpoint.upload_id,
+            checkpoint.parts.len()
+        );
+        Ok(Some(checkpoint))
+    }
+
+    /// Creates the multipart upload and saves its first checkpoint
+    async fn create(&self, total: u64) -> Result<MultipartCheckpoint, TransferError> {
+        let created = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await
+            .map_err(|err| TransferError::s3("CreateMultipartUpload", err))?;
+        let checkpoint = MultipartCheckpoint::new(
+            created.upload_id().unwrap_or_default(),
+            &self.bucket,
+            &self.key,
+            total,
+            self.part_size.max(total.div_ceil(MAX_PARTS)),
+        );
+        if let Some(path) = &self.checkpoint {
+            checkpoint.save(path).await?;
+        }
+        Ok(checkpoint)
+    }
+
+    async fn upload_part_with_retries(
+        &self,
+        upload_id: &str,
+        part: Part,
+        progress: Arc<AggregateProgress>,
+        checkpoint: &Mutex<MultipartCheckpoint>,
+    ) -> Result<(), TransferError> {
+        let mut attempt = 0;
+        loop {
+            let reported = Arc::new(AtomicU64::new(0));
+            match self.upload_part(upload_id, &part, progress.clone(), reported.clone()).await {
+                Ok(e_tag) => {
+                    let mut checkpoint = checkpoint.lock().await;
+                    checkpoint.parts.push(CheckpointPart { number: part.number, offset: part.offset, len: part.len, e_tag });
+                    if let Some(path) = &self.checkpoint {
+                        checkpoint.save(path).await?;
+                    }
+                    drop(checkpoint);
+                    if let Some(callback) = &self.part_callback {
+                        callback(part.number, part.len);
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    // the bytes of the failed attempt will be reported again by the next one
+                    progress.retract(reported.load(Ordering::SeqCst));
+                    if attempt >= self.max_retries {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    log::warn!("Retrying part {} after error: {}", part.number, err);
+                }
+            }
+        }
+    }
+
+    async fn upload_part(
+        &self,
+        upload_id: &str,
+        part: &Part,
+        progress: Arc<AggregateProgress>,
+        reported: Arc<AtomicU64>,
+    ) -> Result<String, TransferError> {
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(part.offset)).await?;
+        let mut body = TrackableBodyStream::with_input(file.take(part.len), part.len);
+        let (callback, part_progress, number) = (self.callback.clone(), self.part_progress.clone(), part.number);
+        body.set_callback(move |_, _, chunk| {
+            reported.fetch_add(chunk, Ordering::SeqCst);
+            progress.add(chunk, |sent, increase| {
+                if let Some(callback) = &callback {
+                    callback(progress.total(), sent, increase);
+                }
+                if let Some(part_progress) = &part_progress {
+                    part_progress(number, progress.total(), sent, increase);
+                }
+            });
+        });
+        let e_tag = self.verify_e_tags.then(|| body.track_e_tag(ExpectedETag::new()));
+
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .part_number(part.number)
+            .content_length(part.len as i64)
+            .body(body.to_s3_stream())
+            .send()
+            .await
+            .map_err(|err| TransferError::s3("UploadPart", err))?;
+        if let Some(e_tag) = e_tag {
+            e_tag.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).verify(&output)?;
+        }
+        Ok(output.e_tag().unwrap_or_default().to_string())
+    }
+}
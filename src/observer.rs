@@ -0,0 +1,104 @@
+use crate::TrackableStreamError;
+
+/// Low level instrumentation hooks for the `poll_next` implementation of a
+/// `TrackableBodyStream`. This is aimed at profiling and debugging the interaction between the
+/// stream, its source, and the HTTP client polling it. For progress reporting use the callback
+/// or `progress_stream` instead.
+///
+/// All methods have an empty default implementation, only override the events you need. The
+/// hooks are invoked synchronously from `poll_next` and should return quickly.
+pub trait StreamObserver: Send + Sync {
+    /// Called every time the stream is polled, before reading from the source
+    fn on_poll_start(&self) {}
+
+    /// Called when a read from the source completes with the number of bytes read. A value of
+    /// `0` means the end of the source was reached.
+    fn on_read_complete(&self, _bytes: usize) {}
+
+    /// Called when the source is not ready and the stream returns `Poll::Pending`
+    fn on_pending(&self) {}
+
+    /// Called when the stream yields a chunk of `len` bytes to its consumer
+    fn on_emit(&self, _len: usize) {}
+
+    /// Called once when the stream reaches the end of the source
+    fn on_complete(&self) {}
+
+    /// Called when the stream fails to produce the next chunk
+    fn on_error(&self, _err: &TrackableStreamError) {}
+}
+
+/// A progress observer registered with `TrackableBodyStream::add_observer`. A stream can have
+/// any number of observers, for example one driving a progress bar, one emitting metrics and
+/// one writing logs, each notified in the order it was added. Closures with the arguments of
+/// the progress callback implement this trait.
+///
+/// Besides every chunk, observers are told when the transfer starts, completes and fails, so
+/// that a progress bar can be finished or abandoned reliably. All methods have an empty default
+/// implementation.
+///
+/// # Examples
+/// ```
+/// # use std::sync::{Arc, Mutex};
+/// # use futures::StreamExt;
+/// # use trackable_s3_stream::{ProgressObserver, TrackableBodyStream, TrackableStreamError};
+/// struct Bar(Arc<Mutex<Vec<String>>>);
+///
+/// impl ProgressObserver for Bar {
+///     fn on_start(&mut self, total: u64) {
+///         self.0.lock().unwrap().push(format!("start {}", total));
+///     }
+///     fn on_chunk(&mut self, _total: u64, sent: u64, _chunk: u64) {
+///         self.0.lock().unwrap().push(format!("at {}", sent));
+///     }
+///     fn on_complete(&mut self) {
+///         self.0.lock().unwrap().push("done".to_string());
+///     }
+///     fn on_error(&mut self, err: &TrackableStreamError) {
+///         self.0.lock().unwrap().push(format!("failed: {}", err));
+///     }
+/// }
+///
+/// # futures::executor::block_on(async {
+/// let events = Arc::new(Mutex::new(Vec::new()));
+/// let data = [0u8; 3000];
+/// let mut body = TrackableBodyStream::from(&data[..]);
+/// body.add_observer(Bar(events.clone()));
+/// while body.next().await.is_some() {}
+/// assert_eq!(*events.lock().unwrap(), vec!["start 3000", "at 2048", "at 3000", "done"]);
+///
+/// events.lock().unwrap().clear();
+/// let mut body = TrackableBodyStream::from(&data[..]);
+/// body.add_observer(Bar(events.clone()));
+/// body.fail(TrackableStreamError::DeadlineExceeded { bytes_read: 0 });
+/// while body.next().await.is_some() {}
+/// assert_eq!(*events.lock().unwrap(), vec!["start 3000", "failed: deadline exceeded after 0 bytes"]);
+/// # });
+/// ```
+pub trait ProgressObserver: Send {
+    /// Called when the stream is first polled, or primed, with the total length of the source,
+    /// `0` when it is unknown. Called again after `reset`.
+    fn on_start(&mut self, _total: u64) {}
+
+    /// Called every time a chunk is read from the source, with the total length of the source
+    /// (`0` when it is unknown), the bytes read so far, and the bytes read in the chunk
+    fn on_chunk(&mut self, _total: u64, _sent: u64, _chunk: u64) {}
+
+    /// Called once when the whole source has been read and the stream completes, or when the
+    /// upload is skipped because it is unnecessary
+    fn on_complete(&mut self) {}
+
+    /// Called when the stream fails to produce the next chunk
+    fn on_error(&mut self, _err: &TrackableStreamError) {}
+
+    /// Called when the SDK retries a request whose body was built with `to_retryable_s3_stream`,
+    /// with the number of the attempt and the bytes read by the previous one. The progress
+    /// starts again from `0`, `on_start` follows when the new attempt is polled.
+    fn on_retry(&mut self, _attempt: u32, _discarded: u64) {}
+}
+
+impl<F: FnMut(u64, u64, u64) + Send> ProgressObserver for F {
+    fn on_chunk(&mut self, total: u64, sent: u64, chunk: u64) {
+        self(total, sent, chunk)
+    }
+}
@@ -0,0 +1,75 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, task::Context};
+
+use futures::task::AtomicWaker;
+
+/// A handle to pause and resume a `TrackableBodyStream`, returned by `pause_handle`. The handle
+/// can be cloned and used from any task or thread, for example by the "Pause" button of a UI
+/// while the S3 client owns the stream.
+///
+/// While paused the stream returns `Poll::Pending` without reading from the source and without
+/// busy-looping: it is woken up by `resume`. A deadline or a cancellation still stops a paused
+/// stream. Note that S3 may close a connection that stays idle for too long.
+///
+/// # Examples
+/// ```
+/// # use std::time::Duration;
+/// # use futures::StreamExt;
+/// # use trackable_s3_stream::TrackableBodyStream;
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let data = vec![0u8; 5000];
+/// let mut body = TrackableBodyStream::from(data.as_slice());
+/// let handle = body.pause_handle();
+///
+/// assert!(body.next().await.is_some());
+/// handle.pause();
+/// assert!(tokio::time::timeout(Duration::from_secs(1), body.next()).await.is_err());
+///
+/// let resumer = handle.clone();
+/// tokio::spawn(async move {
+///     tokio::time::sleep(Duration::from_secs(1)).await;
+///     resumer.resume();
+/// });
+/// assert_eq!(body.next().await.unwrap().unwrap().len(), 2048);
+/// assert!(!handle.is_paused());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle {
+    state: Arc<PauseState>,
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    paused: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl PauseHandle {
+    /// Pauses the stream, it stops at its next poll
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resumes a paused stream, waking up the task polling it
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        self.state.waker.wake();
+    }
+
+    /// Returns `true` while the stream is paused
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the stream is paused, after registering the task to be woken up when
+    /// it is resumed
+    pub(crate) fn poll_paused(&self, cx: &mut Context<'_>) -> bool {
+        if !self.is_paused() {
+            return false;
+        }
+        self.state.waker.register(cx.waker());
+        // `resume` may have been called before the waker was registered
+        self.is_paused()
+    }
+}
@@ -0,0 +1,205 @@
+use std::{future::Future, io::{self, SeekFrom}, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+use futures::{StreamExt, TryStreamExt};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt, Take}};
+
+use crate::{multipart::{AggregateProgress, PartCallbackFn}, CallbackFn, CheckpointPart, TrackableBodyStream, TransferError};
+
+/// A part of a presigned multipart upload, handed to the function that sends it
+pub struct PresignedPart {
+    /// The number of the part, starting at 1
+    pub number: i32,
+    /// The presigned `UploadPart` URL of the part
+    pub url: String,
+    /// The size of the part, to send as its `Content-Length`
+    pub len: u64,
+    /// The body of the part, a stream over its range of the file
+    pub body: TrackableBodyStream<Take<File>>,
+}
+
+/// Uploads the parts of a multipart upload to presigned URLs, for clients that never see AWS
+/// credentials: a server creates the multipart upload and presigns an `UploadPart` URL for
+/// every part, the client streams each part from its range of the file and hands the ETags
+/// back to the server, which completes the upload.
+///
+/// The uploader does not depend on an HTTP client. `upload` calls a function with every part,
+/// which sends a `PUT` request with the body of the part to its URL, with any client, and
+/// returns the `ETag` header of the response. The progress is aggregated over all parts in
+/// flight as with `MultipartUploader`, and a part that fails is sent again with a new body, up
+/// to `with_max_retries` times.
+///
+/// # Examples
+/// ```
+/// # use futures::TryStreamExt;
+/// # use trackable_s3_stream::{PresignedMultipartUploader, PresignedPart};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// // one URL per part of 16 KB, presigned by the server
+/// let urls = (1..=3).map(|part| format!("https://bucket.s3.amazonaws.com/key?partNumber={}", part));
+///
+/// let parts = PresignedMultipartUploader::new("./examples/sample.jpeg", 16 * 1024, urls)
+///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+///     .upload(|part: PresignedPart| async move {
+///         // send `part.body` to `part.url` with an HTTP client instead
+///         let body: Vec<_> = part.body.try_collect().await?;
+///         assert_eq!(body.concat().len() as u64, part.len);
+///         Ok::<_, trackable_s3_stream::TrackableStreamError>(format!("\"etag-{}\"", part.number))
+///     })
+///     .await?;
+///
+/// assert_eq!(parts.len(), 3);
+/// assert_eq!(parts[2].e_tag, "\"etag-3\"");
+/// # Ok(())
+/// # }
+/// ```
+pub struct PresignedMultipartUploader {
+    path: PathBuf,
+    part_size: u64,
+    urls: Vec<String>,
+    concurrency: usize,
+    max_retries: usize,
+    callback: Option<Arc<CallbackFn>>,
+    part_callback: Option<Arc<PartCallbackFn>>,
+}
+
+impl PresignedMultipartUploader {
+    /// Prepares the upload of the file at `path` in parts of `part_size` bytes, the last part
+    /// may be smaller, to the presigned `urls` of the parts in the order of their numbers. The
+    /// parts are sent four at a time, each retried up to three times.
+    pub fn new(path: impl Into<PathBuf>, part_size: u64, urls: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            path: path.into(),
+            part_size: part_size.max(1),
+            urls: urls.into_iter().map(Into::into).collect(),
+            concurrency: 4,
+            max_retries: 3,
+            callback: None,
+            part_callback: None,
+        }
+    }
+
+    /// Sets the maximum number of parts sent at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets how many times a failed part is sent again before the upload fails
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the progress callback, with the same arguments as `MultipartUploader::with_callback`:
+    /// the size of the file, the bytes sent so far over all parts, and the increase since the
+    /// previous call
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback that receives the number and the size of every part once it has been
+    /// sent. Parts complete in no particular order.
+    pub fn with_part_callback(mut self, callback: impl Fn(i32, u64) + Sync + Send + 'static) -> Self {
+        self.part_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sends every part with `send`, which returns the ETag of the part, and returns the parts
+    /// with their ETags in the order of their numbers, as `complete_multipart_upload` expects
+    /// them. Returns an `InvalidInput` error if the number of URLs does not match the number of
+    /// parts of the file.
+    pub async fn upload<F, Fut, E>(self, send: F) -> Result<Vec<CheckpointPart>, TransferError>
+    where
+        F: Fn(PresignedPart) -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let total = tokio::fs::metadata(&self.path).await?.len();
+        let count = total.div_ceil(self.part_size).max(1);
+        if self.urls.len() as u64 != count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} URLs for a file of {} parts", self.urls.len(), count),
+            )
+            .into());
+        }
+
+        let progress = Arc::new(AggregateProgress::new(total, 0));
+        let mut parts: Vec<_> = futures::stream::iter(self.urls.iter().enumerate())
+            .map(|(index, url)| {
+                let offset = index as u64 * self.part_size;
+                let part = CheckpointPart {
+                    number: index as i32 + 1,
+                    offset,
+                    len: self.part_size.min(total - offset),
+                    e_tag: String::new(),
+                };
+                self.send_with_retries(&send, url, part, progress.clone())
+            })
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        parts.sort_by_key(|part| part.number);
+        Ok(parts)
+    }
+
+    async fn send_with_retries<F, Fut, E>(
+        &self,
+        send: &F,
+        url: &str,
+        mut part: CheckpointPart,
+        progress: Arc<AggregateProgress>,
+    ) -> Result<CheckpointPart, TransferError>
+    where
+        F: Fn(PresignedPart) -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut attempt = 0;
+        loop {
+            let reported = Arc::new(AtomicU64::new(0));
+            let body = self.part_body(&part, progress.clone(), reported.clone()).await?;
+            let presigned = PresignedPart { number: part.number, url: url.to_string(), len: part.len, body };
+            match send(presigned).await {
+                Ok(e_tag) => {
+                    if let Some(callback) = &self.part_callback {
+                        callback(part.number, part.len);
+                    }
+                    part.e_tag = e_tag;
+                    return Ok(part);
+                }
+                Err(err) => {
+                    // the bytes of the failed attempt will be reported again by the next one
+                    progress.retract(reported.load(Ordering::SeqCst));
+                    if attempt >= self.max_retries {
+                        return Err(TransferError::s3("UploadPart", err));
+                    }
+                    attempt += 1;
+                    log::warn!("Retrying part {} after error: {}", part.number, err);
+                }
+            }
+        }
+    }
+
+    async fn part_body(
+        &self,
+        part: &CheckpointPart,
+        progress: Arc<AggregateProgress>,
+        reported: Arc<AtomicU64>,
+    ) -> io::Result<TrackableBodyStream<Take<File>>> {
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(part.offset)).await?;
+        let mut body = TrackableBodyStream::with_input(file.take(part.len), part.len);
+        let callback = self.callback.clone();
+        body.set_callback(move |_, _, chunk| {
+            reported.fetch_add(chunk, Ordering::SeqCst);
+            progress.add(chunk, |sent, increase| {
+                if let Some(callback) = &callback {
+                    callback(progress.total(), sent, increase);
+                }
+            });
+        });
+        Ok(body)
+    }
+}
@@ -0,0 +1,294 @@
+use std::{fmt, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
+
+/// The weight of the latest chunk in the smoothed throughput of `Progress`
+const EWMA_WEIGHT: f64 = 0.2;
+
+/// A snapshot of the state of a `TrackableBodyStream`, produced every time a chunk of the
+/// source is read in the buffer. Besides the counters it carries the throughput and the
+/// estimated time remaining, computed by the stream so that consumers do not need to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    /// The total length of the buffer (or size of the file if created from a path), `0` when the
+    /// length is unknown
+    pub total: u64,
+    /// The total number of bytes read so far
+    pub sent: u64,
+    /// The number of bytes read in the current chunk
+    pub chunk: u64,
+    /// The id of the stream set with `set_id`, to tell apart updates of several streams
+    pub id: Option<Arc<str>>,
+    /// The time since the stream was first polled
+    pub elapsed: Duration,
+    /// The throughput of the current chunk, in bytes per second, measured since the previous one
+    pub throughput: f64,
+    /// The exponentially weighted moving average of the throughput, in bytes per second, which
+    /// is steadier than `throughput`
+    pub smoothed_throughput: f64,
+    /// The estimated time until the whole source has been read at the smoothed throughput.
+    /// `None` when the length of the source is unknown or nothing has been read yet.
+    pub eta: Option<Duration>,
+    /// `true` once the stream has reached the end of its source. This is the only way to tell a
+    /// source of unknown length has been read completely.
+    pub finished: bool,
+}
+
+impl Progress {
+    /// Returns the percentage of the source that has been read so far, between `0.0` and `100.0`.
+    /// When the length is unknown there is no percentage until the stream has ended, then it is
+    /// `100.0`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use trackable_s3_stream::Progress;
+    /// let progress = Progress { total: 200, sent: 50, ..Default::default() };
+    /// assert_eq!(progress.percent(), Some(25.0));
+    ///
+    /// let unknown = Progress { sent: 50, ..Default::default() };
+    /// assert_eq!(unknown.percent(), None);
+    /// assert!(!unknown.is_complete());
+    /// let ended = Progress { finished: true, ..unknown };
+    /// assert_eq!(ended.percent(), Some(100.0));
+    /// assert!(ended.is_complete());
+    /// ```
+    pub fn percent(&self) -> Option<f64> {
+        if self.total == 0 {
+            return self.finished.then_some(100.0);
+        }
+        Some((self.sent as f64 / self.total as f64 * 100.0).min(100.0))
+    }
+
+    /// Returns `true` once all the bytes of the source have been read. When the length is
+    /// unknown, that is once the stream has ended.
+    pub fn is_complete(&self) -> bool {
+        self.finished || (self.total > 0 && self.sent >= self.total)
+    }
+
+    /// Formats a human readable status line for this update, with the average rate and the
+    /// estimated time remaining computed from the `elapsed` time since the transfer started.
+    /// Sizes use decimal units. When the length is unknown, the line only has the bytes read
+    /// and the rate.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::Duration;
+    /// # use trackable_s3_stream::Progress;
+    /// let progress = Progress { total: 50_000_000, sent: 22_500_000, chunk: 2048, ..Default::default() };
+    /// assert_eq!(
+    ///     progress.status_line(Duration::from_secs(5)),
+    ///     "45% \u{2014} 22.5/50.0 MB @ 4.5 MB/s \u{2014} ETA 7s"
+    /// );
+    ///
+    /// let unknown = Progress { sent: 22_500_000, chunk: 2048, ..Default::default() };
+    /// assert_eq!(unknown.status_line(Duration::from_secs(5)), "22.5 MB @ 4.5 MB/s");
+    /// ```
+    pub fn status_line(&self, elapsed: Duration) -> String {
+        let rate = self.sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut line = String::new();
+        if let Some(id) = &self.id {
+            line.push_str(&format!("{}: ", id));
+        }
+        let Some(percent) = self.percent().filter(|_| self.total > 0) else {
+            line.push_str(&format!("{} @ {}/s", format_bytes(self.sent as f64), format_bytes(rate)));
+            return line;
+        };
+        let (scale, unit) = unit_of(self.total.max(self.sent) as f64);
+        let eta = if self.sent >= self.total {
+            "0s".to_string()
+        } else if self.sent == 0 {
+            "unknown".to_string()
+        } else {
+            format_duration(Duration::from_secs_f64(((self.total - self.sent) as f64 / rate).ceil()))
+        };
+        line.push_str(&format!(
+            "{:.0}% \u{2014} {:.1}/{:.1} {} @ {}/s \u{2014} ETA {}",
+            percent.floor(),
+            self.sent as f64 / scale,
+            self.total as f64 / scale,
+            unit,
+            format_bytes(rate),
+            eta
+        ));
+        line
+    }
+}
+
+const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+
+/// Returns the divisor and name of the largest decimal unit not larger than `bytes`
+fn unit_of(bytes: f64) -> (f64, &'static str) {
+    let mut scale = 1.0;
+    for unit in &UNITS[..UNITS.len() - 1] {
+        if bytes < scale * 1000.0 {
+            return (scale, unit);
+        }
+        scale *= 1000.0;
+    }
+    (scale, UNITS[UNITS.len() - 1])
+}
+
+fn format_bytes(bytes: f64) -> String {
+    let (scale, unit) = unit_of(bytes);
+    format!("{:.1} {}", bytes / scale, unit)
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, secs) => format!("{}s", secs),
+        (0, mins, secs) => format!("{}m {}s", mins, secs),
+        (hours, mins, _) => format!("{}h {}m", hours, mins),
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = &self.id {
+            write!(f, "{}: ", id)?;
+        }
+        match self.percent().filter(|_| self.total > 0) {
+            Some(percent) => write!(f, "{}/{} bytes ({:.1}%)", self.sent, self.total, percent),
+            None => write!(f, "{} bytes", self.sent),
+        }
+    }
+}
+
+/// A cheap, cloneable view of the progress of a `TrackableBodyStream`, returned by
+/// `progress_handle`. The stream stores its counters in atomics shared by all the clones of the
+/// handle, so a UI running in another task or thread can read them whenever it redraws,
+/// without a callback and without any work in the poll path of the S3 client.
+///
+/// # Examples
+/// ```
+/// # use futures::StreamExt;
+/// # use trackable_s3_stream::TrackableBodyStream;
+/// # futures::executor::block_on(async {
+/// let data = vec![0u8; 5000];
+/// let mut body = TrackableBodyStream::from(data.as_slice());
+/// let handle = body.progress_handle();
+/// assert_eq!((handle.sent(), handle.total()), (0, 5000));
+///
+/// body.next().await;
+/// assert_eq!(handle.sent(), 2048);
+/// while body.next().await.is_some() {}
+/// assert!(handle.is_complete());
+///
+/// // a source of unknown length is only complete once the stream has ended
+/// let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>(trackable_s3_stream::Bytes::from("hello"))]);
+/// let mut body = TrackableBodyStream::from_stream(chunks, None);
+/// let handle = body.progress_handle();
+/// body.next().await;
+/// assert_eq!((handle.sent(), handle.percent()), (5, None));
+/// assert!(!handle.is_complete());
+/// assert!(body.next().await.is_none());
+/// assert_eq!(handle.percent(), Some(100.0));
+/// assert!(handle.is_complete());
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProgressHandle {
+    state: Arc<HandleState>,
+}
+
+#[derive(Debug, Default)]
+struct HandleState {
+    total: AtomicU64,
+    sent: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl ProgressHandle {
+    pub(crate) fn new(total: u64) -> Self {
+        let handle = Self::default();
+        handle.store(total, 0);
+        handle
+    }
+
+    pub(crate) fn store(&self, total: u64, sent: u64) {
+        self.state.total.store(total, Ordering::Relaxed);
+        self.state.sent.store(sent, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_finished(&self, finished: bool) {
+        self.state.finished.store(finished, Ordering::Relaxed);
+    }
+
+    /// Returns the total length of the source, `0` when the length is unknown
+    pub fn total(&self) -> u64 {
+        self.state.total.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn sent(&self) -> u64 {
+        self.state.sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the percentage of the source that has been read so far, computed as
+    /// `Progress::percent`
+    pub fn percent(&self) -> Option<f64> {
+        self.snapshot().percent()
+    }
+
+    /// Returns `true` once all the bytes of the source have been read, or the stream has ended
+    /// when the length is unknown
+    pub fn is_complete(&self) -> bool {
+        self.snapshot().is_complete()
+    }
+
+    /// Returns the current counters as a `Progress`, with a `chunk` of `0` and no id
+    pub fn snapshot(&self) -> Progress {
+        Progress {
+            total: self.total(),
+            sent: self.sent(),
+            finished: self.state.finished.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+/// Measures the throughput of a stream from the time between its chunks
+#[derive(Debug, Default)]
+pub(crate) struct RateTracker {
+    last: Option<Instant>,
+    smoothed: f64,
+}
+
+impl RateTracker {
+    /// Returns the throughput of a chunk of `chunk` bytes read at `now`, measured since the
+    /// previous chunk or `started` for the first one, and the updated smoothed throughput
+    pub(crate) fn update(&mut self, chunk: u64, now: Instant, started: Instant) -> (f64, f64) {
+        let interval = now.saturating_duration_since(self.last.unwrap_or(started));
+        self.last = Some(now);
+        let throughput = chunk as f64 / interval.as_secs_f64().max(1e-6);
+        self.smoothed = if self.smoothed == 0.0 {
+            throughput
+        } else {
+            EWMA_WEIGHT * throughput + (1.0 - EWMA_WEIGHT) * self.smoothed
+        };
+        (throughput, self.smoothed)
+    }
+}
+
+/// Estimates the time left to read `total` bytes when `sent` have been read, at `rate` bytes
+/// per second
+pub(crate) fn estimate_remaining(total: u64, sent: u64, rate: f64) -> Option<Duration> {
+    if total == 0 {
+        return None;
+    }
+    if sent >= total {
+        return Some(Duration::ZERO);
+    }
+    (rate > 0.0).then(|| Duration::from_secs_f64((total - sent) as f64 / rate))
+}
+
+/// The result of draining a stream with `TrackableBodyStream::measure_read_throughput`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadBenchmark {
+    /// The number of bytes read from the source
+    pub bytes: u64,
+    /// The time it took to read the whole source
+    pub duration: Duration,
+    /// The average read throughput, in bytes per second
+    pub bytes_per_second: f64,
+    /// The base64 encoded checksum of the source, if a checksum algorithm was set
+    pub checksum: Option<String>,
+}
@@ -0,0 +1,214 @@
+use std::{io::{self, SeekFrom}, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+use aws_sdk_s3::Client;
+use futures::{StreamExt, TryStreamExt};
+use tokio::{fs::{File, OpenOptions}, io::{AsyncSeekExt, AsyncWriteExt}};
+
+use crate::{multipart::{AggregateProgress, PartCallbackFn}, CallbackFn, TrackableStreamError, TrackableWriter, TransferError, MIN_PART_SIZE};
+
+/// A range of the object downloaded with one ranged `get_object` request
+struct Range {
+    number: i32,
+    offset: u64,
+    len: u64,
+}
+
+/// Downloads a large object from S3 to a file with several ranged `get_object` requests in
+/// flight at the same time, the download-side mirror of `MultipartUploader`.
+///
+/// The size and the ETag of the object are read with a `head_object` request first, the file is
+/// then created with the size of the object and split in ranges of `with_part_size` bytes. Every
+/// range is downloaded by its own request, at most `with_concurrency` at once, and written at its
+/// offset in the file. Each request is conditional on the ETag, so a range of a different
+/// version of the object is never mixed in. The callback receives the progress of the whole
+/// object, aggregated over all ranges in flight; a range that fails is retried on its own, up
+/// to `with_max_retries` times, and the progress never goes backwards.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::MultipartDownloader;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+///
+/// MultipartDownloader::new(client, "bucket", "backup.tar", "./backup.tar")
+///     .with_part_size(16 * 1024 * 1024)
+///     .with_concurrency(8)
+///     .with_callback(|total, sent, _| println!("{}/{} bytes", sent, total))
+///     .download()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MultipartDownloader {
+    client: Client,
+    bucket: String,
+    key: String,
+    path: PathBuf,
+    part_size: u64,
+    concurrency: usize,
+    max_retries: usize,
+    callback: Option<Arc<CallbackFn>>,
+    part_callback: Option<Arc<PartCallbackFn>>,
+}
+
+impl MultipartDownloader {
+    /// Prepares the download of `bucket`/`key` to the file at `path`, replacing it, in ranges of
+    /// `MIN_PART_SIZE` bytes, four at a time, retrying each range up to three times.
+    pub fn new(client: Client, bucket: impl Into<String>, key: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+            key: key.into(),
+            path: path.into(),
+            part_size: MIN_PART_SIZE,
+            concurrency: 4,
+            max_retries: 3,
+            callback: None,
+            part_callback: None,
+        }
+    }
+
+    /// Sets the size of the ranges, the last range may be smaller
+    pub fn with_part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Sets the maximum number of ranges downloaded at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets how many times a failed range is retried before the download fails
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the progress callback, with the same arguments as the callback of a
+    /// `TrackableBodyStream`: the size of the object, the bytes downloaded so far over all
+    /// ranges, and the increase since the previous call. The callback is called from the tasks
+    /// of all the ranges in flight, one call at a time.
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback that receives the number, starting at 1, and the size of every range
+    /// once it has been written. Ranges complete in no particular order.
+    pub fn with_part_callback(mut self, callback: impl Fn(i32, u64) + Sync + Send + 'static) -> Self {
+        self.part_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Runs the download, syncs the file to disk and returns the size of the object
+    pub async fn download(self) -> Result<u64, TransferError> {
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await
+            .map_err(|err| TransferError::s3("HeadObject", err))?;
+        let total = head.content_length().max(0) as u64;
+        let e_tag = head.e_tag().map(str::to_string);
+
+        let file = File::create(&self.path).await?;
+        file.set_len(total).await?;
+        let ranges = (0..total.div_ceil(self.part_size)).map(|index| Range {
+            number: index as i32 + 1,
+            offset: index * self.part_size,
+            len: self.part_size.min(total - index * self.part_size),
+        });
+        let progress = Arc::new(AggregateProgress::new(total, 0));
+        futures::stream::iter(ranges)
+            .map(|range| self.download_range_with_retries(range, e_tag.as_deref(), progress.clone()))
+            .buffer_unordered(self.concurrency)
+            .try_collect::<()>()
+            .await?;
+        file.sync_all().await?;
+        Ok(total)
+    }
+
+    async fn download_range_with_retries(
+        &self,
+        range: Range,
+        e_tag: Option<&str>,
+        progress: Arc<AggregateProgress>,
+    ) -> Result<(), TransferError> {
+        let mut attempt = 0;
+        loop {
+            let reported = Arc::new(AtomicU64::new(0));
+            match self.download_range(&range, e_tag, progress.clone(), reported.clone()).await {
+                Ok(()) => {
+                    if let Some(callback) = &self.part_callback {
+                        callback(range.number, range.len);
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    // the bytes of the failed attempt will be reported again by the next one
+                    progress.retract(reported.load(Ordering::SeqCst));
+                    if attempt >= self.max_retries {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    log::warn!("Retrying range {} after error: {}", range.number, err);
+                }
+            }
+        }
+    }
+
+    async fn download_range(
+        &self,
+        range: &Range,
+        e_tag: Option<&str>,
+        progress: Arc<AggregateProgress>,
+        reported: Arc<AtomicU64>,
+    ) -> Result<(), TransferError> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .range(format!("bytes={}-{}", range.offset, range.offset + range.len - 1))
+            .set_if_match(e_tag.map(str::to_string))
+            .send()
+            .await
+            .map_err(|err| TransferError::s3("GetObject", err))?;
+
+        let mut file = OpenOptions::new().write(true).open(&self.path).await?;
+        file.seek(SeekFrom::Start(range.offset)).await?;
+        let callback = self.callback.clone();
+        let mut writer = TrackableWriter::new(file, range.len).with_callback(move |_, _, chunk| {
+            reported.fetch_add(chunk, Ordering::SeqCst);
+            progress.add(chunk, |sent, increase| {
+                if let Some(callback) = &callback {
+                    callback(progress.total(), sent, increase);
+                }
+            });
+        });
+        let mut body = output.body;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|err| TrackableStreamError::Io {
+                source: io::Error::other(err),
+                bytes_read: writer.bytes_written(),
+            })?;
+            // a range larger than requested would overwrite the next one
+            let actual = writer.bytes_written() + chunk.len() as u64;
+            if actual > range.len {
+                return Err(TrackableStreamError::LengthMismatch { expected: range.len, actual }.into());
+            }
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        if writer.bytes_written() != range.len {
+            return Err(TrackableStreamError::LengthMismatch { expected: range.len, actual: writer.bytes_written() }.into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use std::{io::SeekFrom, pin::Pin, sync::{Arc, Mutex, MutexGuard}, task::{ready, Context, Poll}};
+
+use aws_smithy_http::body::SdkBody;
+use futures::Stream;
+use tokio::io::{AsyncRead, AsyncSeek};
+
+use crate::{BodySource, Bytes, TrackableBodyStream, TrackableStreamError};
+
+/// The stream shared by all the attempts of a request built with `to_retryable_s3_stream`
+struct Attempts<I: BodySource> {
+    stream: TrackableBodyStream<I>,
+    /// The number of bodies handed out to the SDK, the last one is the current attempt
+    created: u32,
+    /// The attempt the source is positioned for
+    current: u32,
+    /// Whether the source is being rewound, between `start_seek` and `poll_complete`
+    seeking: bool,
+}
+
+impl<I: AsyncRead + AsyncSeek + Unpin> Attempts<I> {
+    fn poll_rewind(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let input = Pin::new(&mut self.stream.input);
+        if !self.seeking {
+            input.start_seek(SeekFrom::Start(0))?;
+            self.seeking = true;
+        }
+        let result = ready!(Pin::new(&mut self.stream.input).poll_complete(cx));
+        self.seeking = false;
+        Poll::Ready(result.map(|_| ()))
+    }
+}
+
+/// The body of one attempt. The first time a body of a new attempt is polled it rewinds the
+/// shared source, bodies of older attempts fail from then on.
+struct AttemptBody<I: BodySource> {
+    attempts: Arc<Mutex<Attempts<I>>>,
+    attempt: u32,
+}
+
+impl<I: AsyncRead + AsyncSeek + Unpin> Stream for AttemptBody<I> {
+    type Item = Result<Bytes, TrackableStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let attempt = self.attempt;
+        let mut attempts = lock(&self.attempts);
+        if attempt < attempts.current {
+            let source = std::io::Error::other("the request was retried with a new body");
+            return Poll::Ready(Some(Err(TrackableStreamError::Io { source, bytes_read: attempts.stream.cur_read })));
+        }
+        if attempt > attempts.current {
+            if let Err(source) = ready!(attempts.poll_rewind(cx)) {
+                return Poll::Ready(Some(Err(TrackableStreamError::Io { source, bytes_read: 0 })));
+            }
+            attempts.current = attempt;
+            attempts.stream.retry(attempt);
+        }
+        Pin::new(&mut attempts.stream).poll_next(cx)
+    }
+}
+
+/// Builds a body the SDK can rebuild for every attempt of a request, all of them reading
+/// `stream` from the beginning
+pub(crate) fn retryable_body<I>(stream: TrackableBodyStream<I>) -> SdkBody
+where
+    I: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+{
+    let attempts = Arc::new(Mutex::new(Attempts { stream, created: 0, current: 0, seeking: false }));
+    SdkBody::retryable(move || {
+        let attempt = {
+            let mut attempts = lock(&attempts);
+            attempts.created += 1;
+            attempts.created - 1
+        };
+        crate::body::sdk_body(AttemptBody { attempts: attempts.clone(), attempt })
+    })
+}
+
+fn lock<I: BodySource>(attempts: &Mutex<Attempts<I>>) -> MutexGuard<'_, Attempts<I>> {
+    attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,42 @@
+/// A minimal interface over progress bar implementations, so that any progress UI can be
+/// driven by a `TrackableBodyStream` with `attach_progress_sink`. An implementation for
+/// `indicatif::ProgressBar` is available with the `indicatif` feature.
+pub trait ProgressSink: Send + Sync {
+    /// Sets the total length of the transfer, called once when the sink is attached
+    fn set_length(&self, length: u64);
+
+    /// Sets the number of bytes transferred so far
+    fn set_position(&self, position: u64);
+
+    /// Called once when the transfer completes successfully
+    fn finish(&self);
+}
+
+impl<T: ProgressSink + ?Sized> ProgressSink for std::sync::Arc<T> {
+    fn set_length(&self, length: u64) {
+        (**self).set_length(length);
+    }
+
+    fn set_position(&self, position: u64) {
+        (**self).set_position(position);
+    }
+
+    fn finish(&self) {
+        (**self).finish();
+    }
+}
+
+#[cfg(feature = "indicatif")]
+impl ProgressSink for indicatif::ProgressBar {
+    fn set_length(&self, length: u64) {
+        indicatif::ProgressBar::set_length(self, length);
+    }
+
+    fn set_position(&self, position: u64) {
+        indicatif::ProgressBar::set_position(self, position);
+    }
+
+    fn finish(&self) {
+        indicatif::ProgressBar::finish(self);
+    }
+}
@@ -0,0 +1,275 @@
+use std::{collections::VecDeque, path::Path, pin::Pin, task::{ready, Context, Poll}};
+
+use bytes::BytesMut;
+use futures::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::Bytes;
+
+/// A source of bytes for a `TrackableBodyStream`. This is implemented for every
+/// `tokio::io::AsyncRead`, which is read into a buffer reused from chunk to chunk, and for
+/// `Chunks`, which hands out the buffers it holds without copying them.
+///
+/// # Pinning
+/// Sources must be `Unpin`, which makes `TrackableBodyStream` `Unpin` as well: the stream never
+/// holds references into itself and can be moved freely between polls. No future is kept
+/// across polls either, readers are polled directly with `AsyncRead::poll_read` and a `ReadBuf`
+/// over the spare capacity of the chunk buffer, so a read that returns `Poll::Pending` leaves
+/// its state in the reader, where it belongs. Readers that are not `Unpin` can be used by
+/// pinning them on the heap with `Box::pin`.
+///
+/// # Examples
+/// ```
+/// # use std::{io::Cursor, marker::PhantomPinned, pin::Pin, sync::Mutex, task::{Context, Poll}};
+/// # use tokio::io::{AsyncRead, ReadBuf};
+/// # use trackable_s3_stream::BodySource;
+/// /// A reader that must not be moved once pinned
+/// struct PinnedReader {
+///     data: Mutex<Cursor<Vec<u8>>>,
+///     _pinned: PhantomPinned,
+/// }
+///
+/// impl AsyncRead for PinnedReader {
+///     fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+///         Pin::new(&mut *self.data.lock().unwrap()).poll_read(cx, buf)
+///     }
+/// }
+///
+/// # futures::executor::block_on(async {
+/// let reader = PinnedReader { data: Mutex::new(Cursor::new(vec![7u8; 100])), _pinned: PhantomPinned };
+/// let mut source = Box::pin(reader);
+/// let chunk = futures::future::poll_fn(|cx| source.poll_chunk(cx, 64)).await.unwrap();
+/// assert_eq!(chunk, vec![7u8; 64]);
+/// # });
+/// ```
+pub trait BodySource: Unpin {
+    /// Returns the next chunk of at most `max_len` bytes. An empty chunk marks the end of the
+    /// source.
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, max_len: usize) -> Poll<std::io::Result<Bytes>>;
+
+    /// Returns the next chunk like `poll_chunk`, reading it into the spare capacity of `buf` when
+    /// the source copies its bytes anyway. The stream passes the same buffer on every poll: the
+    /// chunk is split off the buffer without copying, and the allocation is reused as soon as
+    /// the chunks emitted before have been dropped, by the S3 client once they are sent.
+    /// Sources that already hold their bytes keep the default, which ignores `buf`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::TrackableBodyStream;
+    /// # futures::executor::block_on(async {
+    /// let data = vec![1u8; 10_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// let first = body.next().await.unwrap().unwrap();
+    /// let allocation = first.as_ptr();
+    /// drop(first);
+    ///
+    /// // the second chunk is read into the memory of the first one
+    /// let second = body.next().await.unwrap().unwrap();
+    /// assert_eq!(second.as_ptr(), allocation);
+    /// # });
+    /// ```
+    fn poll_chunk_into(&mut self, cx: &mut Context<'_>, buf: &mut BytesMut, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        let _ = buf;
+        self.poll_chunk(cx, max_len)
+    }
+}
+
+impl<T: AsyncRead + Unpin> BodySource for T {
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        let mut buf = Vec::with_capacity(max_len);
+        let mut read_buf = ReadBuf::uninit(&mut buf.spare_capacity_mut()[..max_len]);
+        ready!(Pin::new(&mut *self).poll_read(cx, &mut read_buf))?;
+        let filled = read_buf.filled().len();
+        // SAFETY: `ReadBuf` guarantees that its first `filled` bytes have been initialized
+        unsafe { buf.set_len(filled) };
+        Poll::Ready(Ok(Bytes::from(buf)))
+    }
+
+    fn poll_chunk_into(&mut self, cx: &mut Context<'_>, buf: &mut BytesMut, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        // reclaims the allocation once the chunks split off it have all been dropped
+        buf.reserve(max_len);
+        let start = buf.len();
+        let mut read_buf = ReadBuf::uninit(&mut buf.spare_capacity_mut()[..max_len]);
+        ready!(Pin::new(&mut *self).poll_read(cx, &mut read_buf))?;
+        let filled = read_buf.filled().len();
+        // SAFETY: `ReadBuf` guarantees that its first `filled` bytes have been initialized
+        unsafe { buf.set_len(start + filled) };
+        Poll::Ready(Ok(buf.split_to(start + filled).freeze()))
+    }
+}
+
+/// A scatter source made of a list of buffers, created with `TrackableBodyStream::from_chunks`.
+/// Each buffer is emitted as is, frames are slices of the original buffers that share their
+/// memory. A buffer is only split, still without copying, when it is larger than the buffer
+/// size of the stream.
+pub struct Chunks {
+    chunks: VecDeque<Bytes>,
+}
+
+impl Chunks {
+    pub(crate) fn new(chunks: impl IntoIterator<Item = Bytes>) -> Self {
+        Self { chunks: chunks.into_iter().filter(|chunk| !chunk.is_empty()).collect() }
+    }
+
+    /// Returns the number of bytes left in the source
+    pub(crate) fn len(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.len() as u64).sum()
+    }
+}
+
+impl BodySource for Chunks {
+    fn poll_chunk(&mut self, _cx: &mut Context<'_>, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        let chunk = match self.chunks.front_mut() {
+            Some(chunk) if chunk.len() > max_len => chunk.split_to(max_len.max(1)),
+            Some(_) => self.chunks.pop_front().unwrap_or_default(),
+            None => Bytes::new(),
+        };
+        Poll::Ready(Ok(chunk))
+    }
+}
+
+/// A source wrapping a `futures::Stream` of buffers, created with
+/// `TrackableBodyStream::from_stream`. Buffers are handed out without copying, a buffer larger
+/// than the buffer size of the stream is split and the rest is kept for the next poll. Errors
+/// of the inner stream are turned into `std::io::Error`s.
+pub struct StreamSource<S> {
+    inner: S,
+    pending: Bytes,
+}
+
+impl<S> StreamSource<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self { inner, pending: Bytes::new() }
+    }
+}
+
+impl<S, E> BodySource for StreamSource<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        while self.pending.is_empty() {
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(chunk)) => self.pending = chunk,
+                Some(Err(err)) => return Poll::Ready(Err(std::io::Error::other(err))),
+                None => return Poll::Ready(Ok(Bytes::new())),
+            }
+        }
+        let len = self.pending.len().min(max_len.max(1));
+        Poll::Ready(Ok(self.pending.split_to(len)))
+    }
+}
+
+/// A part of a `Chain`: a source and the number of bytes it contributes
+pub struct Segment {
+    source: Box<dyn BodySource + Send + Sync>,
+    len: u64,
+}
+
+impl Segment {
+    /// Creates a segment of `len` bytes read from any source, such as a reader. The source is
+    /// read up to `len` bytes, if it ends before the chain fails with an `InvalidData` error.
+    pub fn new(source: impl BodySource + Send + Sync + 'static, len: u64) -> Self {
+        Self { source: Box::new(source), len }
+    }
+
+    /// Creates a segment holding a buffer, emitted without copying
+    pub fn from_bytes(bytes: impl Into<Bytes>) -> Self {
+        let bytes = bytes.into();
+        let len = bytes.len() as u64;
+        Self::new(Chunks::new([bytes]), len)
+    }
+
+    /// Opens the file at `path` as a segment of its current size
+    pub async fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        Ok(Self::new(file, len))
+    }
+
+    /// Returns the number of bytes of the segment
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the segment has no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl From<Bytes> for Segment {
+    fn from(bytes: Bytes) -> Self {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl From<&'static [u8]> for Segment {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Segment {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::from_bytes(bytes)
+    }
+}
+
+/// A source that reads several segments one after the other, created with
+/// `TrackableBodyStream::chain`
+pub struct Chain {
+    segments: VecDeque<Segment>,
+}
+
+impl Chain {
+    pub(crate) fn new(segments: impl IntoIterator<Item = Segment>) -> Self {
+        Self { segments: segments.into_iter().collect() }
+    }
+
+    /// Returns the number of bytes left in the source
+    pub(crate) fn len(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.len).sum()
+    }
+
+    /// Polls the current segment with `poll`, which reads at most the given number of bytes,
+    /// moving on to the next segment once the current one has been read
+    fn poll_segment(
+        &mut self,
+        cx: &mut Context<'_>,
+        max_len: usize,
+        mut poll: impl FnMut(&mut Segment, &mut Context<'_>, usize) -> Poll<std::io::Result<Bytes>>,
+    ) -> Poll<std::io::Result<Bytes>> {
+        loop {
+            let Some(segment) = self.segments.front_mut() else {
+                return Poll::Ready(Ok(Bytes::new()));
+            };
+            if segment.len == 0 {
+                self.segments.pop_front();
+                continue;
+            }
+            let max_len = max_len.min(usize::try_from(segment.len).unwrap_or(usize::MAX));
+            let chunk = ready!(poll(segment, cx, max_len))?;
+            if chunk.is_empty() {
+                let message = format!("a segment ended {} bytes before its length", segment.len);
+                return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message)));
+            }
+            // a source may return more than asked, the bytes past the segment are dropped
+            let chunk = chunk.slice(..chunk.len().min(max_len));
+            segment.len -= chunk.len() as u64;
+            return Poll::Ready(Ok(chunk));
+        }
+    }
+}
+
+impl BodySource for Chain {
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        self.poll_segment(cx, max_len, |segment, cx, max_len| segment.source.poll_chunk(cx, max_len))
+    }
+
+    fn poll_chunk_into(&mut self, cx: &mut Context<'_>, buf: &mut BytesMut, max_len: usize) -> Poll<std::io::Result<Bytes>> {
+        self.poll_segment(cx, max_len, |segment, cx, max_len| segment.source.poll_chunk_into(cx, buf, max_len))
+    }
+}
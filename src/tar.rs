@@ -0,0 +1,295 @@
+use std::{collections::VecDeque, io, path::{Path, PathBuf}, pin::Pin, task::{Context, Poll}, time::UNIX_EPOCH};
+
+use tokio::{fs::File, io::{AsyncRead, ReadBuf}};
+
+use crate::CallbackFn;
+
+/// The size of a tar header and the unit file contents are padded to
+const BLOCK_SIZE: u64 = 512;
+
+/// The largest size the octal size field of a ustar header can hold, larger files use the
+/// base-256 encoding of GNU tar
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+/// A file of the directory, with its name in the archive
+struct TarEntry {
+    path: PathBuf,
+    name: String,
+    len: u64,
+    mode: u32,
+    mtime: u64,
+}
+
+/// What the archive emits next
+enum State {
+    /// The header of the next file, with the bytes of it emitted so far
+    Header(Box<[u8; BLOCK_SIZE as usize]>, usize),
+    /// The contents of a file, with the bytes of it left and the padding that follows them
+    Contents(File, u64, u64),
+    /// The zeros left to pad the contents of a file to a block
+    Padding(u64),
+    /// The zeros left of the two blocks that end the archive
+    Trailer(u64),
+    Done,
+}
+
+/// A source that archives a directory tree in the tar format on the fly, so that it can be
+/// uploaded as a single object without staging the archive on disk. Use it with
+/// `TrackableBodyStream::from_tar`.
+///
+/// The directory is walked once, when the archive is created: the archive holds the regular
+/// files found then, in the order of their paths, named after their path relative to the
+/// directory with `/` separators. Symbolic links are not followed, empty directories are left
+/// out. Each file is opened when the archive reaches it. Since the size of every file is written
+/// in its header, the length of the archive is known from the start, `content_length` returns
+/// it. A file that got shorter by the time it is read fails the archive with an `InvalidData`
+/// error, a file that grew is cut to the size it had when the directory was walked.
+///
+/// The callback set with `with_callback` receives the sum of the sizes of the files as the
+/// total and the bytes of files read so far, so that a progress bar tracks the files rather than
+/// the headers and padding of the archive. For a `.tar.gz` object, compress the stream with
+/// `with_compression`.
+///
+/// # Examples
+/// ```
+/// # use futures::TryStreamExt;
+/// # use trackable_s3_stream::{TarArchive, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let directory = std::env::temp_dir().join("trackable_s3_stream_tar");
+/// std::fs::create_dir_all(directory.join("docs"))?;
+/// std::fs::write(directory.join("docs/hello.txt"), "hello world")?;
+///
+/// let archive = TarArchive::new(&directory).await?
+///     .with_callback(|total, read, _| assert!(read <= total));
+/// assert_eq!(archive.files_len(), 11);
+/// let body = TrackableBodyStream::from_tar(archive);
+/// // a header, the contents padded to a block, and two blocks of zeros
+/// assert_eq!(body.content_length(), Some(512 + 512 + 1024));
+///
+/// let tar = body.try_collect::<Vec<_>>().await?.concat();
+/// assert_eq!(&tar[..14], b"docs/hello.txt");
+/// assert_eq!(&tar[257..262], b"ustar");
+/// assert_eq!(&tar[512..523], b"hello world");
+/// # std::fs::remove_dir_all(&directory)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// ```no_run
+/// # use trackable_s3_stream::{TarArchive, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+///
+/// let archive = TarArchive::new("./site").await?
+///     .with_callback(|total, read, _| println!("{}/{} bytes", read, total));
+/// client
+///     .put_object()
+///     .bucket("bucket")
+///     .key("site.tar")
+///     .body(TrackableBodyStream::from_tar(archive).to_s3_stream())
+///     .send()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TarArchive {
+    entries: VecDeque<TarEntry>,
+    state: State,
+    len: u64,
+    files_len: u64,
+    read: u64,
+    callback: Option<Box<CallbackFn>>,
+}
+
+impl TarArchive {
+    /// Walks `directory` and prepares the archive of its files. Fails with an `InvalidInput`
+    /// error if a path does not fit in a ustar header, 255 bytes split at a `/` into at most
+    /// 155 and 100 bytes.
+    pub async fn new(directory: impl AsRef<Path>) -> io::Result<Self> {
+        let directory = directory.as_ref();
+        let mut entries = Vec::new();
+        let mut directories = vec![directory.to_path_buf()];
+        while let Some(current) = directories.pop() {
+            let mut read_dir = tokio::fs::read_dir(&current).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    directories.push(entry.path());
+                } else if file_type.is_file() {
+                    let (path, metadata) = (entry.path(), entry.metadata().await?);
+                    let name = path
+                        .strip_prefix(directory)
+                        .unwrap_or(&path)
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    entries.push(TarEntry {
+                        len: metadata.len(),
+                        mode: mode(&metadata),
+                        mtime: metadata
+                            .modified()
+                            .ok()
+                            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                            .map_or(0, |since| since.as_secs()),
+                        path,
+                        name,
+                    });
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in &entries {
+            split_name(&entry.name)?;
+        }
+
+        let files_len = entries.iter().map(|entry| entry.len).sum();
+        let len = entries.iter().map(|entry| BLOCK_SIZE + entry.len.next_multiple_of(BLOCK_SIZE)).sum::<u64>() + 2 * BLOCK_SIZE;
+        let mut archive = Self { entries: entries.into(), state: State::Done, len, files_len, read: 0, callback: None };
+        archive.state = archive.next_header();
+        Ok(archive)
+    }
+
+    /// Sets the callback method and returns the populated archive. The callback receives the
+    /// same arguments as the `TrackableBodyStream` callback, counted over the contents of the
+    /// files only.
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Returns the length of the archive, headers and padding included
+    pub fn content_length(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the sum of the sizes of the files in the archive
+    pub fn files_len(&self) -> u64 {
+        self.files_len
+    }
+
+    /// Returns the state for the header of the next file, or the end of the archive
+    fn next_header(&self) -> State {
+        match self.entries.front() {
+            Some(entry) => State::Header(Box::new(header(entry)), 0),
+            None => State::Trailer(2 * BLOCK_SIZE),
+        }
+    }
+}
+
+impl AsyncRead for TarArchive {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while buf.remaining() > 0 {
+            match &mut this.state {
+                State::Header(header, written) => {
+                    let len = buf.remaining().min(header.len() - *written);
+                    buf.put_slice(&header[*written..*written + len]);
+                    *written += len;
+                    if *written == header.len() {
+                        let Some(entry) = this.entries.pop_front() else {
+                            unreachable!("a header is only emitted for an entry");
+                        };
+                        // opening a file is quick and keeps the archive free of futures
+                        let file = File::from_std(std::fs::File::open(&entry.path)?);
+                        this.state = State::Contents(file, entry.len, entry.len.next_multiple_of(BLOCK_SIZE) - entry.len);
+                    }
+                }
+                State::Contents(_, 0, padding) => this.state = State::Padding(*padding),
+                State::Contents(file, remaining, _) => {
+                    let limit = buf.remaining().min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    let mut limited = ReadBuf::new(buf.initialize_unfilled_to(limit));
+                    if Pin::new(file).poll_read(cx, &mut limited)?.is_pending() {
+                        // returns what has been filled already, if anything
+                        if buf.filled().is_empty() {
+                            return Poll::Pending;
+                        }
+                        break;
+                    }
+                    let read = limited.filled().len();
+                    if read == 0 {
+                        let message = "a file got shorter while it was archived";
+                        return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, message)));
+                    }
+                    buf.advance(read);
+                    *remaining -= read as u64;
+                    this.read += read as u64;
+                    if let Some(callback) = &this.callback {
+                        callback(this.files_len, this.read, read as u64);
+                    }
+                }
+                State::Padding(0) => this.state = this.next_header(),
+                State::Trailer(0) => this.state = State::Done,
+                State::Padding(remaining) | State::Trailer(remaining) => {
+                    let len = buf.remaining().min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    buf.initialize_unfilled_to(len).fill(0);
+                    buf.advance(len);
+                    *remaining -= len as u64;
+                }
+                State::Done => break,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Returns the permissions of a file, `0o644` where they are not available
+#[cfg(unix)]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Returns the permissions of a file, `0o644` where they are not available
+#[cfg(not(unix))]
+fn mode(_metadata: &std::fs::Metadata) -> u32 {
+    0o644
+}
+
+/// Splits a name into the prefix and name fields of a ustar header
+fn split_name(name: &str) -> io::Result<(&str, &str)> {
+    if name.len() <= 100 {
+        return Ok(("", name));
+    }
+    name.match_indices('/')
+        .map(|(index, _)| (&name[..index], &name[index + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100 && !rest.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("the path {} is too long for a tar header", name)))
+}
+
+/// Writes `value` in octal, padded with zeros and followed by a NUL, into `field`
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(&digits.as_bytes()[digits.len() - field.len()..]);
+}
+
+/// Returns the ustar header of a file
+fn header(entry: &TarEntry) -> [u8; BLOCK_SIZE as usize] {
+    let mut header = [0u8; BLOCK_SIZE as usize];
+    // names have been checked when the archive was created
+    let (prefix, name) = split_name(&entry.name).unwrap_or_default();
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], entry.mode as u64);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    if entry.len <= MAX_OCTAL_SIZE {
+        write_octal(&mut header[124..136], entry.len);
+    } else {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&entry.len.to_be_bytes());
+    }
+    write_octal(&mut header[136..148], entry.mtime.min(MAX_OCTAL_SIZE));
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // the checksum is computed with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    write_octal(&mut header[148..155], checksum as u64);
+    header
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+/// What a stream created with `TrackableBodyStream::from_tempfile` does with its source file
+/// when the stream fails or is dropped before reaching the end of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TempFileFailurePolicy {
+    /// The file is left in place so that the upload can be retried. This is the default.
+    #[default]
+    Keep,
+    /// The file is deleted regardless of the outcome of the upload
+    Delete,
+}
+
+/// Deletes a temporary source file once the stream is done with it.
+pub(crate) struct TempFile {
+    path: PathBuf,
+    pub(crate) policy: TempFileFailurePolicy,
+    removed: bool,
+}
+
+impl TempFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            policy: TempFileFailurePolicy::default(),
+            removed: false,
+        }
+    }
+
+    pub(crate) fn remove(&mut self) {
+        if self.removed {
+            return;
+        }
+        self.removed = true;
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            log::warn!("Could not remove temporary file {}: {}", self.path.display(), err);
+        }
+    }
+
+    pub(crate) fn failed(&mut self) {
+        if self.policy == TempFileFailurePolicy::Delete {
+            self.remove();
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // dropped before the stream completed
+        self.failed();
+    }
+}
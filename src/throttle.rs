@@ -0,0 +1,228 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+/// The longest the stream waits between checks of the permitted rate during the warm-up
+const RAMP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A source of the current time. The stream uses the system clock, tests can inject their own
+/// to control the passing of time.
+pub trait Clock: Send + Sync {
+    /// Returns the current instant
+    fn now(&self) -> Instant;
+}
+
+/// The system clock, `std::time::Instant::now`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Limits the rate at which a `TrackableBodyStream` emits bytes, set with `set_throttle`.
+///
+/// The first frame is emitted right away, following frames are held back until the bytes
+/// emitted so far fit within the permitted rate. With a ramp the permitted rate starts at a
+/// fraction of the target and increases linearly to the full target over a warm-up period,
+/// which starts when the first frame is emitted. This avoids a sudden spike on links that are
+/// sensitive to it.
+///
+/// Without a burst limit bytes that could not be sent in time, for example while the
+/// connection was slow, can be caught up later at any speed. `with_burst` turns the throttle
+/// into a token bucket that holds at most a given number of bytes of credit.
+///
+/// # Examples
+/// ```
+/// # use std::time::{Duration, Instant};
+/// # use futures::StreamExt;
+/// # use trackable_s3_stream::{Throttle, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() {
+/// let data = vec![0u8; 20_000];
+/// let mut body = TrackableBodyStream::from(data.as_slice());
+/// body.set_buffer_size(1000);
+/// body.set_throttle(Throttle::new(100_000));
+///
+/// let start = Instant::now();
+/// while body.next().await.is_some() {}
+/// // the first frame is free, the other 19 000 bytes take 190 ms at 100 000 bytes/s
+/// assert!(start.elapsed() >= Duration::from_millis(180));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Throttle {
+    bytes_per_second: f64,
+    initial_fraction: f64,
+    warm_up: Duration,
+    clock: Arc<dyn Clock>,
+    start: Option<Instant>,
+    burst: Option<u64>,
+    /// The part of the allowance lost while the bucket was full
+    forfeited: f64,
+}
+
+impl Throttle {
+    /// Creates a throttle that lets at most `bytes_per_second` bytes through every second
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1) as f64,
+            initial_fraction: 1.0,
+            warm_up: Duration::ZERO,
+            clock: Arc::new(SystemClock),
+            start: None,
+            burst: None,
+            forfeited: 0.0,
+        }
+    }
+
+    /// Limits the credit the throttle accumulates while fewer bytes than permitted are emitted
+    /// to `burst` bytes, so that the stream never exceeds the rate by more than `burst` bytes
+    /// after being idle.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::{Duration, Instant};
+    /// # use futures::StreamExt;
+    /// # use trackable_s3_stream::{Throttle, TrackableBodyStream};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let data = vec![0u8; 10_000];
+    /// let mut body = TrackableBodyStream::from(data.as_slice());
+    /// body.set_buffer_size(1000);
+    /// body.set_throttle(Throttle::new(100_000).with_burst(1000));
+    ///
+    /// body.next().await;
+    /// // the stream is idle for a while, the credit is capped at 1000 bytes
+    /// tokio::time::sleep(Duration::from_millis(100)).await;
+    /// let start = Instant::now();
+    /// while body.next().await.is_some() {}
+    /// // without the cap the 100 ms of idle time would let all the frames through right away
+    /// assert!(start.elapsed() >= Duration::from_millis(60));
+    /// # }
+    /// ```
+    pub fn with_burst(mut self, burst: u64) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Starts at `initial_fraction` of the target rate, clamped between `0.0` and `1.0`, and
+    /// increases the rate linearly to the full target over `warm_up`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::time::Duration;
+    /// # use trackable_s3_stream::Throttle;
+    /// let throttle = Throttle::new(1000).with_ramp(0.25, Duration::from_secs(10));
+    /// assert_eq!(throttle.rate_at(Duration::ZERO), 250.0);
+    /// assert_eq!(throttle.rate_at(Duration::from_secs(5)), 625.0);
+    /// assert_eq!(throttle.rate_at(Duration::from_secs(10)), 1000.0);
+    /// assert_eq!(throttle.rate_at(Duration::from_secs(60)), 1000.0);
+    /// ```
+    pub fn with_ramp(mut self, initial_fraction: f64, warm_up: Duration) -> Self {
+        self.initial_fraction = initial_fraction.clamp(0.0, 1.0);
+        self.warm_up = warm_up;
+        self
+    }
+
+    /// Uses `clock` instead of the system clock to measure time.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+    /// # use trackable_s3_stream::{Clock, Throttle};
+    /// struct ManualClock(Mutex<Instant>);
+    ///
+    /// impl Clock for ManualClock {
+    ///     fn now(&self) -> Instant {
+    ///         *self.0.lock().unwrap()
+    ///     }
+    /// }
+    ///
+    /// let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+    /// let mut throttle = Throttle::new(1000)
+    ///     .with_ramp(0.5, Duration::from_secs(4))
+    ///     .with_clock(clock.clone());
+    /// throttle.start();
+    /// assert!(throttle.permitted_rate() < 1000.0);
+    ///
+    /// *clock.0.lock().unwrap() += Duration::from_secs(2);
+    /// assert_eq!(throttle.permitted_rate(), 750.0);
+    ///
+    /// *clock.0.lock().unwrap() += Duration::from_secs(2);
+    /// assert_eq!(throttle.permitted_rate(), 1000.0);
+    /// ```
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Starts the warm-up period now, if it has not started yet. The stream calls this when it
+    /// emits its first frame.
+    pub fn start(&mut self) {
+        if self.start.is_none() {
+            self.start = Some(self.clock.now());
+        }
+    }
+
+    /// Returns the rate permitted `elapsed` after the first frame, in bytes per second
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.warm_up {
+            return self.bytes_per_second;
+        }
+        let progress = elapsed.as_secs_f64() / self.warm_up.as_secs_f64();
+        self.bytes_per_second * (self.initial_fraction + (1.0 - self.initial_fraction) * progress)
+    }
+
+    /// Returns the rate permitted at the current time of the clock, in bytes per second
+    pub fn permitted_rate(&self) -> f64 {
+        self.rate_at(self.elapsed())
+    }
+
+    /// Forgets when the throttle was started, so that the warm-up runs again
+    pub(crate) fn restart(&mut self) {
+        self.start = None;
+        self.forfeited = 0.0;
+    }
+
+    /// Returns how long to wait before more bytes can be emitted, when `emitted` bytes have
+    /// already been emitted, or `None` if they can be emitted right away.
+    pub(crate) fn delay(&mut self, emitted: u64) -> Option<Duration> {
+        self.start();
+        let elapsed = self.elapsed();
+        let mut excess = emitted as f64 - (self.allowance(elapsed) - self.forfeited);
+        if let Some(burst) = self.burst {
+            // a full bucket does not collect more credit
+            if -excess > burst as f64 {
+                self.forfeited += -excess - burst as f64;
+                excess = -(burst as f64);
+            }
+        }
+        if excess <= 0.0 {
+            return None;
+        }
+        // the rate only increases over time, waiting at the current rate never overshoots
+        let delay = Duration::from_secs_f64(excess / self.rate_at(elapsed).max(1.0));
+        if elapsed < self.warm_up {
+            // the rate is still increasing, check again soon rather than wait at a low rate
+            return Some(delay.min(RAMP_POLL_INTERVAL));
+        }
+        Some(delay)
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.start.map_or(Duration::ZERO, |start| self.clock.now().saturating_duration_since(start))
+    }
+
+    /// The number of bytes the throttle lets through during the first `elapsed` of the transfer
+    fn allowance(&self, elapsed: Duration) -> f64 {
+        let ramp = elapsed.min(self.warm_up).as_secs_f64();
+        let ramped = if self.warm_up.is_zero() {
+            0.0
+        } else {
+            let initial = self.bytes_per_second * self.initial_fraction;
+            initial * ramp + (self.bytes_per_second - initial) * ramp * ramp / (2.0 * self.warm_up.as_secs_f64())
+        };
+        ramped + self.bytes_per_second * elapsed.saturating_sub(self.warm_up).as_secs_f64()
+    }
+}
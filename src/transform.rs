@@ -0,0 +1,94 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{encryption::Encryptor, gzip::GzipEncoder, Encryption};
+
+/// The compression formats a `TrackableBodyStream` can apply to its source with
+/// `with_compression`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, the format of the `gzip` content encoding
+    Gzip,
+}
+
+/// An encoding applied to the bytes of the source before they are emitted by the stream.
+pub(crate) enum Transform {
+    Base64 {
+        /// Bytes that did not fill a complete 3-byte group, carried over to the next chunk
+        carry: Vec<u8>,
+    },
+    Gzip(Box<GzipEncoder>),
+    Encrypt(Encryptor),
+}
+
+impl Transform {
+    pub(crate) fn base64() -> Self {
+        Transform::Base64 { carry: Vec::with_capacity(2) }
+    }
+
+    pub(crate) fn compression(compression: Compression) -> Self {
+        match compression {
+            Compression::Gzip => Transform::Gzip(Box::new(GzipEncoder::new())),
+        }
+    }
+
+    pub(crate) fn encryption(encryption: &Encryption) -> Self {
+        Transform::Encrypt(encryption.encryptor())
+    }
+
+    /// Returns a transform of the same kind with no state, to encode the source from the start
+    pub(crate) fn restart(self) -> Self {
+        match self {
+            Transform::Base64 { .. } => Transform::base64(),
+            Transform::Gzip(_) => Transform::compression(Compression::Gzip),
+            Transform::Encrypt(encryptor) => Transform::Encrypt(encryptor.restart()),
+        }
+    }
+
+    /// Encodes a chunk of the source. The output may be empty if the transform needs more input
+    /// to produce anything.
+    pub(crate) fn encode(&mut self, chunk: &[u8]) -> std::io::Result<Vec<u8>> {
+        Ok(match self {
+            Transform::Base64 { carry } => {
+                carry.extend_from_slice(chunk);
+                let complete = carry.len() - carry.len() % 3;
+                let encoded = STANDARD.encode(&carry[..complete]).into_bytes();
+                carry.drain(..complete);
+                encoded
+            }
+            Transform::Gzip(encoder) => encoder.encode(chunk),
+            Transform::Encrypt(encryptor) => encryptor.encode(chunk)?,
+        })
+    }
+
+    /// Flushes the output still held by the transform once the end of the source is reached
+    pub(crate) fn finish(&mut self) -> std::io::Result<Vec<u8>> {
+        Ok(match self {
+            Transform::Base64 { carry } => {
+                let encoded = STANDARD.encode(&carry[..]).into_bytes();
+                carry.clear();
+                encoded
+            }
+            Transform::Gzip(encoder) => encoder.finish(),
+            Transform::Encrypt(encryptor) => encryptor.finish()?,
+        })
+    }
+
+    /// Returns the value of the `Content-Encoding` header of an object holding the output, if
+    /// clients need it to decode the object
+    pub(crate) fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Transform::Gzip(_) => Some("gzip"),
+            Transform::Base64 { .. } | Transform::Encrypt(_) => None,
+        }
+    }
+
+    /// Returns the length of the output for a source of `len` bytes, when it can be known
+    /// before encoding
+    pub(crate) fn encoded_length(&self, len: u64) -> Option<u64> {
+        match self {
+            Transform::Base64 { .. } => Some(len.div_ceil(3) * 4),
+            Transform::Gzip(_) => None,
+            Transform::Encrypt(encryptor) => Some(encryptor.encrypted_length(len)),
+        }
+    }
+}
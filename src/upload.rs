@@ -0,0 +1,214 @@
+use aws_sdk_s3::{model::{ChecksumMode, Tag, Tagging}, output::PutObjectOutput, types::SdkError, Client};
+use tokio::io::{AsyncReadExt, AsyncSeek};
+
+use crate::{BodySource, ChecksumAlgorithm, TrackableBodyStream, TransferError, METADATA_SHA256_KEY};
+
+/// The outcome of `put_object_if_changed`
+#[derive(Debug)]
+pub enum ConditionalUpload {
+    /// S3 already has an object with the same SHA-256 checksum, no data was sent
+    Skipped,
+    /// The object was uploaded
+    Uploaded(Box<PutObjectOutput>),
+}
+
+/// Uploads `body` to `bucket`/`key` unless S3 already has an identical object, which makes
+/// repeated uploads of the same content idempotent and free. This is a two-pass flow: the
+/// SHA-256 checksum of the source is computed first, then compared with the checksum S3 reports
+/// for the existing object in a `head_object` request.
+///
+/// S3 only reports a whole-object SHA-256 for objects uploaded in a single request with that
+/// checksum. Multipart uploads report a checksum of the part checksums instead, and ETags are
+/// not content hashes for multipart or SSE-KMS objects, so neither can be compared with the
+/// local checksum. For those objects the helper falls back to the `METADATA_SHA256_KEY` user
+/// metadata, which this helper always sets on upload; objects with neither are uploaded again.
+///
+/// When the upload is skipped the stream reports the whole source as sent, with a final
+/// callback whose chunk size is `0`, and progress sinks are finished.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::{put_object_if_changed, ConditionalUpload, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+/// let body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+///
+/// match put_object_if_changed(&client, "bucket", "sample.jpeg", body).await? {
+///     ConditionalUpload::Skipped => println!("skipped (already present)"),
+///     ConditionalUpload::Uploaded(_) => println!("upload complete"),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn put_object_if_changed<I>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    mut body: TrackableBodyStream<I>,
+) -> Result<ConditionalUpload, TransferError>
+where
+    I: AsyncReadExt + AsyncSeek + Unpin + Send + Sync + 'static,
+{
+    let checksum = body.precompute_checksum(ChecksumAlgorithm::Sha256).await?;
+
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .checksum_mode(ChecksumMode::Enabled)
+        .send()
+        .await;
+    match head {
+        Ok(head) => {
+            let remote = head
+                .checksum_sha256()
+                .or_else(|| head.metadata().and_then(|metadata| metadata.get(METADATA_SHA256_KEY)).map(String::as_str));
+            if remote == Some(checksum.as_str()) {
+                body.mark_skipped();
+                return Ok(ConditionalUpload::Skipped);
+            }
+        }
+        Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => {}
+        Err(err) => return Err(TransferError::s3("HeadObject", err)),
+    }
+
+    let mut request = body
+        .apply_metadata(client.put_object())
+        .bucket(bucket)
+        .key(key)
+        .set_content_length(body.content_length());
+    // S3 validates the checksum against the body as sent, which differs from the source when
+    // it is encoded
+    if body.transform.is_none() {
+        request = request.checksum_sha256(checksum);
+    }
+    let output = request
+        .body(body.to_s3_stream())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("PutObject", err))?;
+    Ok(ConditionalUpload::Uploaded(Box::new(output)))
+}
+
+/// Uploads `body` to `bucket`/`key` while computing the SHA-256 checksum of the source, then
+/// stores the base64 encoded checksum in the `tag_key` tag of the new object. Unlike metadata,
+/// which has to be sent before the body, tags can be set once the upload is over, so the
+/// checksum is computed in a single pass over the source.
+///
+/// This issues two requests, `put_object` then `put_object_tagging` on the version that was
+/// just uploaded. They are not atomic: if tagging fails the error is a `TransferError::S3` for
+/// the `PutObjectTagging` operation, but the object has been uploaded and is left in place
+/// without the tag. Tagging replaces any tags the object already had.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::{put_object_with_checksum_tag, TrackableBodyStream};
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+/// let body = TrackableBodyStream::from_path("./examples/sample.jpeg").await?;
+///
+/// put_object_with_checksum_tag(&client, "bucket", "sample.jpeg", body, "sha256").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn put_object_with_checksum_tag<I>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    mut body: TrackableBodyStream<I>,
+    tag_key: &str,
+) -> Result<PutObjectOutput, TransferError>
+where
+    I: BodySource + Send + Sync + 'static,
+{
+    let checksum = body.share_checksum(ChecksumAlgorithm::Sha256);
+    let output = client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_content_length(body.content_length())
+        .body(body.to_s3_stream())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("PutObject", err))?;
+
+    // the SDK only completes the upload once the stream has been read to the end
+    let checksum = checksum
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take()
+        .ok_or_else(|| std::io::Error::other("the checksum of the body was not computed"))?;
+    let tagging = Tagging::builder()
+        .tag_set(Tag::builder().key(tag_key).value(checksum).build())
+        .build();
+    client
+        .put_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(output.version_id().map(str::to_string))
+        .tagging(tagging)
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("PutObjectTagging", err))?;
+    Ok(output)
+}
+
+/// Copies `source_bucket`/`source_key`, read with the `source` client, to `bucket`/`key`,
+/// written with the `destination` client, by streaming the body of the `get_object` response
+/// into a `put_object` request. The two clients can belong to different accounts or regions,
+/// which `copy_object` cannot bridge. The object goes through this machine but is never
+/// buffered whole or staged on disk, the callback receives the same arguments as the
+/// `TrackableBodyStream` callback and covers the transfer from the first byte downloaded to the
+/// last byte uploaded. Objects larger than 5 GB cannot be copied with a single `put_object`.
+///
+/// # Examples
+/// ```no_run
+/// # use aws_sdk_s3::Region;
+/// # use trackable_s3_stream::pipe_object;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let source_config = aws_config::from_env().region(Region::new("us-east-1")).load().await;
+/// let destination_config = aws_config::from_env().region(Region::new("eu-west-1")).load().await;
+/// let source = aws_sdk_s3::Client::new(&source_config);
+/// let destination = aws_sdk_s3::Client::new(&destination_config);
+///
+/// pipe_object(&source, "source-bucket", "sample.jpeg", &destination, "bucket", "sample.jpeg", |total, sent, _| {
+///     println!("{}/{} bytes", sent, total)
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn pipe_object(
+    source: &Client,
+    source_bucket: &str,
+    source_key: &str,
+    destination: &Client,
+    bucket: &str,
+    key: &str,
+    callback: impl FnMut(u64, u64, u64) + Send + 'static,
+) -> Result<PutObjectOutput, TransferError> {
+    let output = source
+        .get_object()
+        .bucket(source_bucket)
+        .key(source_key)
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("GetObject", err))?;
+    let content_type = output.content_type().map(str::to_string);
+    let body = TrackableBodyStream::from_get_object(output).with_callback(callback);
+    destination
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_content_type(content_type)
+        .set_content_length(body.content_length())
+        .body(body.to_s3_stream())
+        .send()
+        .await
+        .map_err(|err| TransferError::s3("PutObject", err))
+}
@@ -0,0 +1,124 @@
+use std::{pin::Pin, task::{Context, Poll}};
+
+use tokio::io::AsyncWrite;
+
+use crate::CallbackFn;
+
+/// A `tokio::io::AsyncWrite` wrapper that tracks the bytes written to a file or any other
+/// writer, for example a download copied to disk from the reader of a `get_object` body. Each
+/// write triggers the same callback as `TrackableBodyStream`, with the number of bytes the
+/// writer accepted.
+///
+/// # Examples
+/// ```no_run
+/// # use trackable_s3_stream::TrackableWriter;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let sdk_config = aws_config::from_env().load().await;
+/// let client = aws_sdk_s3::Client::new(&sdk_config);
+/// let output = client.get_object().bucket("bucket").key("sample.jpeg").send().await?;
+///
+/// let file = tokio::fs::File::create("./sample.jpeg").await?;
+/// let mut writer = TrackableWriter::new(file, output.content_length() as u64)
+///     .with_callback(|total, written, _| println!("{}/{} bytes", written, total));
+/// tokio::io::copy(&mut output.body.into_async_read(), &mut writer).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// ```
+/// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+/// # use tokio::io::AsyncWriteExt;
+/// # use trackable_s3_stream::TrackableWriter;
+/// # futures::executor::block_on(async {
+/// let written = Arc::new(AtomicU64::new(0));
+/// let progress = written.clone();
+/// let mut writer = TrackableWriter::new(Vec::new(), 11)
+///     .with_callback(move |_, written, _| progress.store(written, Ordering::SeqCst));
+///
+/// tokio::io::copy(&mut &b"hello world"[..], &mut writer).await.unwrap();
+/// writer.shutdown().await.unwrap();
+/// assert_eq!(written.load(Ordering::SeqCst), 11);
+/// assert_eq!(writer.into_inner(), b"hello world");
+/// # });
+/// ```
+pub struct TrackableWriter<W> {
+    inner: W,
+    total: Option<u64>,
+    written: u64,
+    callback: Option<Box<CallbackFn>>,
+}
+
+impl<W: AsyncWrite + Unpin> TrackableWriter<W> {
+    /// Wraps a writer that will receive `total` bytes, usually the `content_length` of the
+    /// `get_object` response
+    pub fn new(inner: W, total: u64) -> Self {
+        Self { inner, total: Some(total), written: 0, callback: None }
+    }
+
+    /// Wraps a writer that will receive an unknown number of bytes, the callback receives `0`
+    /// as the total
+    pub fn with_unknown_length(inner: W) -> Self {
+        Self { inner, total: None, written: 0, callback: None }
+    }
+
+    /// Sets the callback method and returns the populated writer. The callback receives the
+    /// same arguments as the `TrackableBodyStream` callback.
+    pub fn with_callback(mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the callback method
+    pub fn set_callback(&mut self, callback: impl Fn(u64, u64, u64) + Sync + Send + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Returns the number of bytes written so far
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Returns the number of bytes the writer will receive, if known
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    pub(crate) fn set_total(&mut self, total: u64) {
+        self.total = Some(total);
+    }
+
+    /// Returns a reference to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the wrapped writer. Bytes buffered by the writer may not have been flushed yet.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TrackableWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            if written > 0 {
+                this.written += written as u64;
+                if let Some(callback) = &this.callback {
+                    callback(this.total.unwrap_or(0), this.written, written as u64);
+                }
+            }
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::recorded_callback;
+use futures::{StreamExt, TryStreamExt};
+use trackable_s3_stream::TrackableBodyStream;
+
+#[tokio::test]
+async fn caches_a_partly_read_coalescing_stream() {
+    let path = std::env::temp_dir().join("trackable_s3_stream_partly_cached");
+    let data: Vec<u8> = (0..10_000u32).map(|index| (index % 251) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let (callback, calls) = recorded_callback();
+    let mut body = TrackableBodyStream::from_path(&path).await.unwrap().with_callback(callback).with_callback_every_bytes(1000);
+    body.set_buffer_size(4000);
+
+    let mut read = Vec::new();
+    for _ in 0..2 {
+        read.extend_from_slice(&body.next().await.unwrap().unwrap());
+    }
+    assert_eq!(read.len(), 8000);
+    calls.lock().unwrap().clear();
+
+    // the cached stream counts from the start of what was left to read
+    let body = body.into_memory_cached().await.unwrap();
+    let rest: Vec<_> = body.try_collect().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    read.extend_from_slice(&rest.concat());
+    assert!(read == data);
+    let calls = calls.lock().unwrap();
+    assert!(calls.iter().all(|&(total, sent, _)| total == 2000 && sent <= 2000), "{:?}", calls);
+    assert_eq!(calls.iter().map(|&(_, _, chunk)| chunk).sum::<u64>(), 2000);
+    assert_eq!(calls.last().map(|&(_, sent, _)| sent), Some(2000));
+}
@@ -0,0 +1,258 @@
+//! An in-memory S3 connection for the tests. The SDK client sends its requests to `MockS3`
+//! instead of the network: every request is recorded, with its body, and answered by a
+//! handler, which falls back to `Request::respond` for a plausible successful response.
+#![allow(dead_code)]
+
+use std::{
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use aws_sdk_s3::{Client, Config, Credentials, Region};
+use aws_smithy_http::{body::SdkBody, result::ConnectorError};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use md5::{Digest, Md5};
+
+pub type Response = http::Response<SdkBody>;
+
+/// A request received by `MockS3`
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: http::Method,
+    pub uri: http::Uri,
+    pub headers: http::HeaderMap,
+    pub body: Bytes,
+}
+
+impl Request {
+    /// Returns the name of the S3 operation of the request
+    pub fn operation(&self) -> &'static str {
+        let has = |name: &str| self.query(name).is_some();
+        match self.method.as_str() {
+            "HEAD" => "HeadObject",
+            "GET" if self.query("list-type").as_deref() == Some("2") => "ListObjectsV2",
+            "GET" => "GetObject",
+            "POST" if has("uploads") => "CreateMultipartUpload",
+            "POST" if has("uploadId") => "CompleteMultipartUpload",
+            "DELETE" if has("uploadId") => "AbortMultipartUpload",
+            "DELETE" => "DeleteObject",
+            "PUT" if has("tagging") => "PutObjectTagging",
+            "PUT" if has("partNumber") => "UploadPart",
+            "PUT" if self.header("x-amz-copy-source").is_some() => "CopyObject",
+            "PUT" => "PutObject",
+            _ => "Unknown",
+        }
+    }
+
+    /// Returns the bucket of the request, from the virtual host or the path the SDK addresses it to
+    pub fn bucket(&self) -> String {
+        self.bucket_and_key().0
+    }
+
+    /// Returns the key of the request, empty for bucket operations
+    pub fn key(&self) -> String {
+        self.bucket_and_key().1
+    }
+
+    fn bucket_and_key(&self) -> (String, String) {
+        let host = self.uri.host().unwrap_or_default();
+        let path = self.uri.path().trim_start_matches('/');
+        match host.split_once(".s3") {
+            Some((bucket, _)) if !bucket.is_empty() => (bucket.to_string(), path.to_string()),
+            _ => {
+                let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+                (bucket.to_string(), key.to_string())
+            }
+        }
+    }
+
+    /// Returns the value of a parameter of the query string, empty for a parameter without one
+    pub fn query(&self, name: &str) -> Option<String> {
+        self.uri.query()?.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key == name).then(|| value.to_string())
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// Returns the body of the request as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Answers the request as S3 would if it succeeded, with the MD5 digest of the body as the
+    /// ETag of an uploaded object or part, and a missing object for the reads
+    pub fn respond(&self) -> Response {
+        match self.operation() {
+            "PutObject" | "UploadPart" => response(200).header("ETag", e_tag(&self.body)).body(SdkBody::empty()).unwrap(),
+            "CreateMultipartUpload" => xml(
+                200,
+                &format!(
+                    "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>",
+                    self.bucket(),
+                    self.key()
+                ),
+            ),
+            "CompleteMultipartUpload" => xml(
+                200,
+                &format!(
+                    "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><ETag>\"completed\"</ETag></CompleteMultipartUploadResult>",
+                    self.bucket(),
+                    self.key()
+                ),
+            ),
+            "AbortMultipartUpload" | "DeleteObject" => empty(204),
+            "HeadObject" => empty(404),
+            "GetObject" => error(404, "NoSuchKey"),
+            "ListObjectsV2" => xml(200, "<ListBucketResult><IsTruncated>false</IsTruncated><KeyCount>0</KeyCount></ListBucketResult>"),
+            _ => empty(200),
+        }
+    }
+}
+
+/// Returns the ETag S3 gives to an object uploaded with a single request
+pub fn e_tag(body: &[u8]) -> String {
+    let digest = Md5::digest(body);
+    format!("\"{}\"", digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+}
+
+pub fn response(status: u16) -> http::response::Builder {
+    http::Response::builder().status(status)
+}
+
+pub fn empty(status: u16) -> Response {
+    response(status).body(SdkBody::empty()).unwrap()
+}
+
+/// Returns a response without a body, with `headers`
+pub fn with_headers(status: u16, headers: &[(&str, &str)]) -> Response {
+    headers
+        .iter()
+        .fold(response(status), |response, (name, value)| response.header(*name, *value))
+        .body(SdkBody::empty())
+        .unwrap()
+}
+
+pub fn xml(status: u16, body: &str) -> Response {
+    response(status).header("Content-Type", "application/xml").body(SdkBody::from(body.to_string())).unwrap()
+}
+
+/// Returns an S3 error response with the error code `code`
+pub fn error(status: u16, code: &str) -> Response {
+    xml(status, &format!("<Error><Code>{}</Code><Message>{} from the mock</Message></Error>", code, code))
+}
+
+/// Returns a response body that yields `bytes` then fails, like a connection dropped in the
+/// middle of a download
+pub fn failing_body(bytes: Bytes) -> SdkBody {
+    let chunks: Vec<Result<Bytes, std::io::Error>> =
+        vec![Ok(bytes), Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset by the mock"))];
+    SdkBody::from(hyper::Body::wrap_stream(futures::stream::iter(chunks)))
+}
+
+/// The arguments of every call of a progress callback
+pub type Calls = Arc<Mutex<Vec<(u64, u64, u64)>>>;
+
+/// Returns a progress callback that records its arguments, and the calls it records
+pub fn recorded_callback() -> (impl Fn(u64, u64, u64) + Send + Sync + 'static, Calls) {
+    let calls = Calls::default();
+    let recorder = calls.clone();
+    (move |total, sent, chunk| recorder.lock().unwrap().push((total, sent, chunk)), calls)
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+#[derive(Default)]
+struct Recorder {
+    requests: Mutex<Vec<Request>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+/// A connection for the SDK client that answers requests with a handler and records them.
+/// Requests take `with_latency` to be answered, so that concurrent requests overlap.
+#[derive(Clone)]
+pub struct MockS3 {
+    handler: Arc<Handler>,
+    latency: Duration,
+    recorder: Arc<Recorder>,
+}
+
+impl MockS3 {
+    pub fn new(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        Self { handler: Arc::new(handler), latency: Duration::ZERO, recorder: Default::default() }
+    }
+
+    /// Answers every request as it would succeed
+    pub fn succeeding() -> Self {
+        Self::new(Request::respond)
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Returns a client that sends its requests to this connection
+    pub fn client(&self) -> Client {
+        let config = Config::builder()
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("access-key", "secret-key", None, None, "mock"))
+            .build();
+        Client::from_conf_conn(config, self.clone())
+    }
+
+    /// Returns the requests received so far, in the order they were answered
+    pub fn requests(&self) -> Vec<Request> {
+        self.recorder.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the requests for `operation` received so far
+    pub fn requests_for(&self, operation: &str) -> Vec<Request> {
+        self.requests().into_iter().filter(|request| request.operation() == operation).collect()
+    }
+
+    /// Returns the operations of the requests received so far
+    pub fn operations(&self) -> Vec<&'static str> {
+        self.requests().iter().map(Request::operation).collect()
+    }
+
+    /// Returns the largest number of requests that were in flight at the same time
+    pub fn max_in_flight(&self) -> usize {
+        self.recorder.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl tower_service::Service<http::Request<SdkBody>> for MockS3 {
+    type Response = Response;
+    type Error = ConnectorError;
+    type Future = BoxFuture<'static, Result<Response, ConnectorError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<SdkBody>) -> Self::Future {
+        let mock = self.clone();
+        Box::pin(async move {
+            let recorder = &mock.recorder;
+            let in_flight = recorder.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            recorder.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            let (parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await;
+            tokio::time::sleep(mock.latency).await;
+            recorder.in_flight.fetch_sub(1, Ordering::SeqCst);
+            let body = body.map_err(ConnectorError::io)?;
+
+            let request = Request { method: parts.method, uri: parts.uri, headers: parts.headers, body };
+            let response = (mock.handler)(&request);
+            recorder.requests.lock().unwrap().push(request);
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::{with_headers, MockS3, Request};
+use trackable_s3_stream::copy_object_tracked;
+
+#[tokio::test]
+async fn keeps_the_attributes_of_the_source() {
+    let attributes = [
+        ("Content-Type", "text/html"),
+        ("Content-Encoding", "gzip"),
+        ("Content-Language", "en"),
+        ("Content-Disposition", "inline"),
+        ("Cache-Control", "max-age=60"),
+        ("x-amz-meta-origin", "build-42"),
+    ];
+    let mock = MockS3::new(move |request: &Request| match request.operation() {
+        "HeadObject" => with_headers(200, &[attributes.as_slice(), &[("Content-Length", "1000"), ("ETag", "\"source\"")]].concat()),
+        _ => request.respond(),
+    });
+
+    copy_object_tracked(&mock.client(), "source", "page.html", "bucket", "page.html", |_, _, _| {}).await.unwrap();
+    assert_eq!(mock.operations(), ["HeadObject", "CreateMultipartUpload", "UploadPart", "CompleteMultipartUpload"]);
+    let created = &mock.requests()[1];
+    for (name, value) in attributes {
+        assert_eq!(created.header(name), Some(value), "{}", name);
+    }
+    let copied = &mock.requests()[2];
+    assert_eq!(copied.header("x-amz-copy-source"), Some("source/page.html"));
+    assert_eq!(copied.header("x-amz-copy-source-if-match"), Some("\"source\""));
+}
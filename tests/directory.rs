@@ -0,0 +1,140 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use common::{e_tag, error, recorded_callback, xml, MockS3, Request};
+use trackable_s3_stream::{DirectoryUploader, TransferError};
+
+/// The files of the test directories, with their sizes
+const FILES: [(&str, usize); 3] = [("a.txt", 1000), ("b/c.txt", 3000), ("d.txt", 2000)];
+
+fn create_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    for (file, len) in FILES {
+        let path = directory.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, vec![file.as_bytes()[0]; len]).unwrap();
+    }
+    directory
+}
+
+type FileCalls = Arc<Mutex<HashMap<String, Vec<(u64, u64)>>>>;
+
+/// Returns a file callback that records the total and the bytes sent of every call by file name
+fn recorded_file_callback(directory: &Path) -> (impl Fn(&Path, u64, u64, u64) + Send + Sync + 'static, FileCalls) {
+    let calls = FileCalls::default();
+    let (recorder, directory) = (calls.clone(), directory.to_path_buf());
+    let callback = move |path: &Path, total, sent, _| {
+        let name = path.strip_prefix(&directory).unwrap().to_string_lossy().replace('\\', "/");
+        recorder.lock().unwrap().entry(name).or_default().push((total, sent));
+    };
+    (callback, calls)
+}
+
+#[tokio::test]
+async fn reports_the_progress_of_every_file_and_of_the_job() {
+    let directory = create_directory("trackable_s3_stream_directory_progress");
+    let mock = MockS3::succeeding();
+    let (callback, calls) = recorded_callback();
+    let (file_callback, file_calls) = recorded_file_callback(&directory);
+
+    let outcomes = DirectoryUploader::new(mock.client(), "bucket", &directory)
+        .with_key_prefix("www/")
+        .with_concurrency(2)
+        .with_callback(callback)
+        .with_file_callback(file_callback)
+        .upload()
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let keys: Vec<_> = outcomes.iter().map(|(key, outcome)| (key.as_str(), outcome.is_ok())).collect();
+    assert_eq!(keys, [("www/a.txt", true), ("www/b/c.txt", true), ("www/d.txt", true)]);
+    let mut uploaded: Vec<_> = mock.requests_for("PutObject").iter().map(|request| (request.key(), request.body.len())).collect();
+    uploaded.sort();
+    assert_eq!(uploaded, FILES.map(|(file, len)| (format!("www/{}", file), len)));
+
+    // every file reaches its size, the job reaches the size of all the files
+    let file_calls = file_calls.lock().unwrap();
+    for (file, len) in FILES {
+        let calls = &file_calls[file];
+        assert!(calls.iter().all(|&(total, _)| total == len as u64));
+        assert!(calls.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert_eq!(calls.last().unwrap().1, len as u64);
+    }
+    let calls = calls.lock().unwrap();
+    assert!(calls.iter().all(|&(total, _, _)| total == 6000));
+    assert!(calls.windows(2).all(|pair| pair[0].1 + pair[1].2 == pair[1].1));
+    assert_eq!(calls.last().map(|&(_, sent, _)| sent), Some(6000));
+}
+
+#[tokio::test]
+async fn uploads_the_other_files_when_one_fails() {
+    let directory = create_directory("trackable_s3_stream_directory_failure");
+    let mock = MockS3::new(|request: &Request| match request.key().as_str() {
+        "b/c.txt" => error(403, "AccessDenied"),
+        _ => request.respond(),
+    });
+    let (callback, calls) = recorded_callback();
+
+    let outcomes = DirectoryUploader::new(mock.client(), "bucket", &directory)
+        .with_callback(callback)
+        .upload()
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(outcomes.len(), 3);
+    assert!(outcomes.iter().all(|(key, outcome)| outcome.is_ok() == (key != "b/c.txt")));
+    assert!(matches!(&outcomes[1].1, Err(TransferError::S3 { operation: "PutObject", .. })));
+    assert_eq!(mock.requests_for("PutObject").len(), 3);
+
+    // the failed file is taken out of the job, which reaches the size of the other files
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0].0, 6000);
+    assert!(calls.iter().any(|&(total, _, chunk)| total == 3000 && chunk == 0));
+    assert_eq!(calls.last().map(|&(total, sent, _)| (total, sent)), Some((3000, 3000)));
+}
+
+#[tokio::test]
+async fn syncs_only_the_changed_files() {
+    let directory = create_directory("trackable_s3_stream_directory_sync");
+    let listing = [
+        // written before the file, with the same content
+        ("a.txt", 1000, e_tag(&[b'a'; 1000])),
+        // a different size
+        ("b/c.txt", 2500, e_tag(&[b'b'; 2500])),
+        // written after the file
+        ("d.txt", 2000, e_tag(b"other content")),
+    ];
+    let contents: String = listing
+        .iter()
+        .map(|(key, len, e_tag)| {
+            let last_modified = if *key == "d.txt" { "2999-01-01T00:00:00.000Z" } else { "2000-01-01T00:00:00.000Z" };
+            format!("<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size></Contents>", key, last_modified, e_tag, len)
+        })
+        .collect();
+    let mock = MockS3::new(move |request: &Request| match request.operation() {
+        "ListObjectsV2" => xml(200, &format!("<ListBucketResult><IsTruncated>false</IsTruncated><KeyCount>3</KeyCount>{}</ListBucketResult>", contents)),
+        _ => request.respond(),
+    });
+    let (callback, calls) = recorded_callback();
+
+    let outcomes = DirectoryUploader::new(mock.client(), "bucket", &directory).with_callback(callback).sync().await.unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let keys: Vec<_> = outcomes.iter().map(|(key, outcome)| (key.as_str(), outcome.is_ok())).collect();
+    assert_eq!(keys, [("b/c.txt", true)]);
+    let uploaded: Vec<_> = mock.requests_for("PutObject").iter().map(|request| (request.key(), request.body.len())).collect();
+    assert_eq!(uploaded, [("b/c.txt".to_string(), 3000)]);
+
+    // the progress only counts the bytes of the changed file
+    let calls = calls.lock().unwrap();
+    assert!(calls.iter().all(|&(total, _, _)| total == 3000));
+    assert_eq!(calls.last().map(|&(_, sent, _)| sent), Some(3000));
+}
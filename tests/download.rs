@@ -0,0 +1,108 @@
+mod common;
+
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use common::{error, failing_body, recorded_callback, response, MockS3, Request};
+use trackable_s3_stream::{download_object, TrackableByteStream, TrackableStreamError};
+
+const LEN: usize = 100_000;
+const FAILS_AFTER: usize = 40_000;
+
+#[tokio::test]
+async fn resumes_an_interrupted_download_with_a_ranged_request() {
+    let data = Bytes::from((0..LEN).map(|index| (index % 253) as u8).collect::<Vec<_>>());
+    let object = data.clone();
+    let mock = MockS3::new(move |request: &Request| match (request.operation(), request.header("range")) {
+        // the first response fails after part of the body
+        ("GetObject", None) => response(200)
+            .header("Content-Length", LEN)
+            .header("ETag", "\"object-e-tag\"")
+            .body(failing_body(object.slice(..FAILS_AFTER)))
+            .unwrap(),
+        ("GetObject", Some(range)) => {
+            let start: usize = range.strip_prefix("bytes=").and_then(|range| range.strip_suffix('-')).unwrap().parse().unwrap();
+            response(206)
+                .header("Content-Length", LEN - start)
+                .header("Content-Range", format!("bytes {}-{}/{}", start, LEN - 1, LEN))
+                .header("ETag", "\"object-e-tag\"")
+                .body(object.slice(start..).into())
+                .unwrap()
+        }
+        _ => request.respond(),
+    });
+    let path = std::env::temp_dir().join("trackable_s3_stream_resumed_download");
+    let (callback, calls) = recorded_callback();
+
+    let written = download_object(&mock.client(), "bucket", "data.bin", &path, callback).await.unwrap();
+    assert_eq!(written, LEN as u64);
+    assert!(tokio::fs::read(&path).await.unwrap() == data);
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    // the second request asks for the rest of the same version of the object
+    let requests = mock.requests_for("GetObject");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].header("range"), None);
+    assert_eq!(requests[1].header("range"), Some(format!("bytes={}-", FAILS_AFTER).as_str()));
+    assert_eq!(requests[1].header("if-match"), Some("\"object-e-tag\""));
+
+    // the bytes already written are reported once, the progress never goes backwards
+    let calls = calls.lock().unwrap();
+    assert!(calls.windows(2).all(|pair| pair[0].1 < pair[1].1));
+    assert_eq!(calls.iter().map(|(_, _, chunk)| chunk).sum::<u64>(), LEN as u64);
+    assert_eq!(calls.last().map(|(total, sent, _)| (*total, *sent)), Some((LEN as u64, LEN as u64)));
+}
+
+#[tokio::test]
+async fn tracks_an_empty_object_with_a_known_length() {
+    let mock = MockS3::new(|_: &Request| common::with_headers(200, &[("Content-Length", "0")]));
+    let output = mock.client().get_object().bucket("bucket").key("empty").send().await.unwrap();
+
+    let download = TrackableByteStream::from_get_object(output);
+    assert_eq!(download.content_length(), Some(0));
+    assert!(download.try_collect::<Vec<_>>().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn ends_after_the_first_error_of_the_body() {
+    let chunks = vec![
+        Ok(Bytes::from_static(&[1; 100])),
+        Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")),
+        Ok(Bytes::from_static(&[2; 100])),
+    ];
+    let mut download = TrackableByteStream::new(futures::stream::iter(chunks), 1000);
+
+    assert_eq!(download.next().await.unwrap().unwrap().len(), 100);
+    let err = download.next().await.unwrap().unwrap_err();
+    assert!(matches!(err, TrackableStreamError::Io { bytes_read: 100, .. }), "{}", err);
+    // neither the rest of the body nor a length mismatch follows the error
+    assert!(download.next().await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn backs_off_between_the_retries_of_a_throttled_download() {
+    let sent_at = Arc::new(Mutex::new(Vec::new()));
+    let recorder = sent_at.clone();
+    let mock = MockS3::new(move |_: &Request| {
+        let mut sent_at = recorder.lock().unwrap();
+        sent_at.push(tokio::time::Instant::now());
+        match sent_at.len() {
+            1..=3 => error(503, "SlowDown"),
+            _ => response(200).header("Content-Length", 3).body(Bytes::from_static(b"abc").into()).unwrap(),
+        }
+    });
+    let path = std::env::temp_dir().join("trackable_s3_stream_throttled_download");
+
+    let written = download_object(&mock.client(), "bucket", "data.bin", &path, |_, _, _| {}).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(written, 3);
+
+    // every retry waits twice as long as the previous one
+    let sent_at = sent_at.lock().unwrap();
+    let waits: Vec<_> = sent_at.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    assert_eq!(waits.len(), 3);
+    for (wait, expected) in waits.iter().zip([200, 400, 800]) {
+        assert!(*wait >= Duration::from_millis(expected), "{:?}", waits);
+    }
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use common::{error, recorded_callback, MockS3, Request};
+use trackable_s3_stream::{FanOutUploader, TrackableBodyStream, TransferError};
+
+const LEN: u64 = 10_000;
+
+/// Uploads to `primary` and `failing`, whose `failed_operation` requests fail, and checks that
+/// the primary destination completes while the failing one is taken out of the progress
+async fn upload_with_a_failing_destination(multipart_threshold: u64, failed_operation: &'static str) {
+    let data: Vec<u8> = (0..LEN).map(|index| (index % 241) as u8).collect();
+    let mock = MockS3::new(move |request: &Request| {
+        let failing = request.bucket() == "failing" && request.operation() == failed_operation;
+        // a multipart upload fails on its second part
+        if failing && request.query("partNumber").is_none_or(|part| part == "2") {
+            return error(500, "InternalError");
+        }
+        request.respond()
+    });
+    let (callback, calls) = recorded_callback();
+    let destination_calls = Arc::new(Mutex::new(Vec::new()));
+    let recorder = destination_calls.clone();
+
+    let frames: Vec<_> = data.chunks(1000).map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk))).collect();
+    let body = TrackableBodyStream::from_stream(futures::stream::iter(frames), Some(LEN));
+    let outcomes = FanOutUploader::new(body)
+        .with_destination(mock.client(), "primary", "data.bin")
+        .with_destination(mock.client(), "failing", "data.bin")
+        .with_multipart_threshold(multipart_threshold)
+        .with_part_size(4000)
+        .with_callback(callback)
+        .with_destination_callback(move |index, total, sent, _| recorder.lock().unwrap().push((index, total, sent)))
+        .upload()
+        .await;
+
+    // the failure of one destination does not stop the other
+    assert!(outcomes[0].is_ok(), "{:?}", outcomes[0]);
+    assert!(matches!(&outcomes[1], Err(TransferError::S3 { operation, .. }) if *operation == failed_operation), "{:?}", outcomes[1]);
+    let uploaded: Vec<_> = mock
+        .requests()
+        .into_iter()
+        .filter(|request| request.bucket() == "primary" && matches!(request.operation(), "PutObject" | "UploadPart"))
+        .flat_map(|request| request.body.to_vec())
+        .collect();
+    assert!(uploaded == data);
+    let aborted = mock.requests_for("AbortMultipartUpload").iter().map(Request::bucket).collect::<Vec<_>>();
+    assert_eq!(aborted, if failed_operation == "UploadPart" { vec!["failing"] } else { vec![] });
+
+    // the combined progress drops the failing destination and reaches the size of the source
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0].0, 2 * LEN);
+    let removed = calls.iter().position(|&(total, _, chunk)| total == LEN && chunk == 0).expect("the failed destination is taken out");
+    assert!(calls[..removed].iter().all(|&(total, _, _)| total == 2 * LEN));
+    assert!(calls[removed..].iter().all(|&(total, sent, _)| total == LEN && sent <= LEN));
+    assert_eq!(calls.last().map(|&(total, sent, _)| (total, sent)), Some((LEN, LEN)));
+    let primary = destination_calls.lock().unwrap().iter().rfind(|(index, _, _)| *index == 0).copied();
+    assert_eq!(primary, Some((0, LEN, LEN)));
+}
+
+#[tokio::test]
+async fn completes_the_other_destinations_when_a_multipart_upload_fails() {
+    upload_with_a_failing_destination(0, "UploadPart").await;
+}
+
+#[tokio::test]
+async fn completes_the_other_destinations_when_a_put_object_fails() {
+    upload_with_a_failing_destination(u64::MAX, "PutObject").await;
+}
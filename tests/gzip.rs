@@ -0,0 +1,166 @@
+use futures::{executor::block_on, TryStreamExt};
+use trackable_s3_stream::{Compression, TrackableBodyStream};
+
+/// Reads the bits of a deflate stream, starting from the least significant bit of every byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u32 {
+        let bit = (self.data[self.pos] >> self.bit) as u32 & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            (self.pos, self.bit) = (self.pos + 1, 0);
+        }
+        bit
+    }
+
+    /// Reads a value stored least significant bit first
+    fn bits(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |value, index| value | self.bit() << index)
+    }
+
+    /// Reads a Huffman code, stored most significant bit first
+    fn code(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |code, _| code << 1 | self.bit())
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            (self.pos, self.bit) = (self.pos + 1, 0);
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> &[u8] {
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        bytes
+    }
+}
+
+const LENGTH_BASE: [u32; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u32; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Reads a literal, a length or the end of block with the fixed Huffman codes of deflate
+fn fixed_symbol(reader: &mut BitReader) -> u32 {
+    let code = reader.code(7);
+    if code <= 0b0010111 {
+        return 256 + code;
+    }
+    let code = code << 1 | reader.bit();
+    match code {
+        0x30..=0xbf => code - 0x30,
+        0xc0..=0xc7 => 280 + code - 0xc0,
+        _ => 144 + (code << 1 | reader.bit()) - 0x190,
+    }
+}
+
+/// Decompresses a gzip member made of stored and fixed Huffman blocks, the ones the encoder
+/// writes, and checks its trailer
+fn gunzip(gzip: &[u8]) -> Vec<u8> {
+    assert_eq!(&gzip[..3], &[0x1f, 0x8b, 8], "not a deflate gzip member");
+    let mut reader = BitReader { data: &gzip[10..], pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bit() == 1;
+        match reader.bits(2) {
+            0 => {
+                reader.align();
+                let header = reader.bytes(4);
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                assert_eq!(!len, u16::from_le_bytes([header[2], header[3]]), "corrupt stored block length");
+                out.extend_from_slice(reader.bytes(len as usize));
+            }
+            1 => loop {
+                let symbol = fixed_symbol(&mut reader);
+                match symbol {
+                    0..=255 => out.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let index = (symbol - 257) as usize;
+                        let length = LENGTH_BASE[index] + reader.bits(LENGTH_EXTRA[index]);
+                        let index = reader.code(5) as usize;
+                        let distance = (DISTANCE_BASE[index] + reader.bits(DISTANCE_EXTRA[index])) as usize;
+                        for _ in 0..length {
+                            out.push(out[out.len() - distance]);
+                        }
+                    }
+                }
+            },
+            kind => panic!("unexpected block type {}", kind),
+        }
+        if last {
+            break;
+        }
+    }
+    reader.align();
+    let trailer = reader.bytes(8);
+    assert_eq!(u32::from_le_bytes(trailer[..4].try_into().unwrap()), crc32fast::hash(&out), "crc mismatch");
+    assert_eq!(u32::from_le_bytes(trailer[4..].try_into().unwrap()), out.len() as u32, "size mismatch");
+    assert_eq!(reader.pos, reader.data.len(), "trailing bytes after the gzip member");
+    out
+}
+
+fn compress(data: &[u8], buffer_size: usize) -> Vec<u8> {
+    let mut body = TrackableBodyStream::from(data).with_compression(Compression::Gzip);
+    body.set_buffer_size(buffer_size);
+    block_on(body.try_collect::<Vec<_>>()).unwrap().concat()
+}
+
+/// Bytes that do not compress
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn known_vectors() {
+    let header = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    assert_eq!(compress(b"", 2048), [&header[..], &[0x03, 0x00], &[0; 8]].concat());
+    assert_eq!(compress(b"a", 2048), [&header[..], &[0x4b, 0x04, 0x00], &[0x43, 0xbe, 0xb7, 0xe8, 1, 0, 0, 0]].concat());
+}
+
+#[test]
+fn round_trips() {
+    let text = "the quick brown fox jumps over the lazy dog, ".repeat(3000).into_bytes();
+    let mixed = [text.clone(), noise(100_000), text.clone()].concat();
+    for data in [Vec::new(), b"a".to_vec(), text, noise(200_000), mixed] {
+        for buffer_size in [1, 1000, 64 * 1024] {
+            assert!(gunzip(&compress(&data, buffer_size)) == data, "{} bytes read {} at a time", data.len(), buffer_size);
+        }
+    }
+}
+
+#[test]
+fn incompressible_data_is_stored() {
+    let data = noise(1_000_000);
+    let compressed = compress(&data, 8192);
+    // the gzip header and trailer, and 5 bytes for every stored block
+    assert!(compressed.len() < data.len() + data.len() / 1000 + 64, "{} bytes", compressed.len());
+    assert!(gunzip(&compressed) == data);
+}
+
+#[test]
+fn small_reads_are_buffered_into_blocks() {
+    let data = "hello world ".repeat(10_000).into_bytes();
+    let compressed = compress(&data, 1);
+    assert!(compressed.len() < data.len() / 20, "{} bytes", compressed.len());
+    assert!(gunzip(&compressed) == data);
+}
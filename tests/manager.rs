@@ -0,0 +1,48 @@
+mod common;
+
+use std::{io::ErrorKind, time::Duration};
+
+use common::MockS3;
+use trackable_s3_stream::{TransferError, TransferManager};
+
+#[tokio::test]
+async fn starts_waiting_jobs_by_priority_and_skips_cancelled_ones() {
+    let directory = std::env::temp_dir().join("trackable_s3_stream_manager");
+    std::fs::create_dir_all(&directory).unwrap();
+    let names = ["running", "background", "urgent", "cancelled", "promoted"];
+    for (index, name) in names.iter().enumerate() {
+        std::fs::write(directory.join(name), vec![index as u8; 1000 * (index + 1)]).unwrap();
+    }
+    // the first job is still running while the others are submitted
+    let mock = MockS3::succeeding().with_latency(Duration::from_millis(100));
+    let manager = TransferManager::new(mock.client(), 1);
+
+    let running = manager.upload("bucket", "running", directory.join("running")).await.unwrap();
+    let background = manager.upload("bucket", "background", directory.join("background")).await.unwrap();
+    let urgent = manager.upload_with_priority("bucket", "urgent", directory.join("urgent"), 5).await.unwrap();
+    let cancelled = manager.upload("bucket", "cancelled", directory.join("cancelled")).await.unwrap();
+    let promoted = manager.upload_with_priority("bucket", "promoted", directory.join("promoted"), -1).await.unwrap();
+    assert_eq!(manager.progress().total, 15_000);
+
+    assert!(promoted.set_priority(10));
+    assert!(cancelled.cancel());
+    // a job that has started can no longer be cancelled nor reprioritized
+    assert!(!running.cancel());
+    assert!(!running.set_priority(100));
+
+    let err = cancelled.wait().await.unwrap_err();
+    assert!(matches!(&err, TransferError::Io(err) if err.kind() == ErrorKind::Interrupted), "{}", err);
+    for job in [running, background, urgent, promoted] {
+        job.wait().await.unwrap();
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    // one transfer at a time, the waiting jobs by priority then in the order they were submitted
+    assert_eq!(mock.max_in_flight(), 1);
+    let keys: Vec<_> = mock.requests_for("PutObject").iter().map(|request| request.key()).collect();
+    assert_eq!(keys, ["running", "promoted", "urgent", "background"]);
+
+    // the cancelled job is taken out of the feed, which reaches the size of the other jobs
+    let progress = manager.progress();
+    assert_eq!((progress.total, progress.sent), (11_000, 11_000));
+}
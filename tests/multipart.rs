@@ -0,0 +1,72 @@
+mod common;
+
+use std::{
+    collections::BTreeSet,
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
+    time::Duration,
+};
+
+use common::{error, MockS3, Request};
+use trackable_s3_stream::{ParallelUploadTracker, MIN_PART_SIZE};
+
+#[tokio::test]
+async fn uploads_parts_concurrently_and_retries_a_failed_part() {
+    let path = std::env::temp_dir().join("trackable_s3_stream_multipart_parts");
+    let data: Vec<u8> = (0..4 * MIN_PART_SIZE).map(|index| (index % 251) as u8).collect();
+    tokio::fs::write(&path, &data).await.unwrap();
+
+    // the first attempt of the third part fails
+    let failed = Arc::new(AtomicBool::new(false));
+    let failing = failed.clone();
+    let mock = MockS3::new(move |request: &Request| {
+        if request.operation() == "UploadPart"
+            && request.query("partNumber").as_deref() == Some("3")
+            && !failing.swap(true, Ordering::SeqCst)
+        {
+            return error(500, "InternalError");
+        }
+        request.respond()
+    })
+    .with_latency(Duration::from_millis(50));
+
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let completed = Arc::new(Mutex::new(Vec::new()));
+    let (recorder, parts) = (progress.clone(), completed.clone());
+    ParallelUploadTracker::new(mock.client(), "bucket", "data.bin", &path)
+        .with_concurrency(2)
+        .with_callback(move |total, sent, _| recorder.lock().unwrap().push((total, sent)))
+        .with_part_callback(move |part, size| parts.lock().unwrap().push((part, size)))
+        .upload()
+        .await
+        .unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    // every part completed, the third one after a retry
+    let mut completed = completed.lock().unwrap().clone();
+    completed.sort();
+    assert_eq!(completed, (1..=4).map(|part| (part, MIN_PART_SIZE)).collect::<Vec<_>>());
+    let attempts: Vec<_> = mock.requests_for("UploadPart").iter().filter_map(|request| request.query("partNumber")).collect();
+    assert_eq!(attempts.len(), 5);
+    assert_eq!(attempts.iter().filter(|part| *part == "3").count(), 2);
+    assert_eq!(attempts.iter().collect::<BTreeSet<_>>().len(), 4);
+
+    // the parts were uploaded two at a time, and their bytes reached S3 in order
+    assert_eq!(mock.max_in_flight(), 2);
+    let mut uploaded: Vec<_> = mock
+        .requests_for("UploadPart")
+        .into_iter()
+        .filter(|request| request.body.len() as u64 == MIN_PART_SIZE)
+        .map(|request| (request.query("partNumber").unwrap().parse::<u64>().unwrap(), request.body))
+        .collect();
+    uploaded.sort_by_key(|(part, _)| *part);
+    uploaded.dedup_by_key(|(part, _)| *part);
+    assert!(uploaded.iter().flat_map(|(_, body)| body.iter()).eq(data.iter()));
+    let complete = &mock.requests_for("CompleteMultipartUpload")[0];
+    assert_eq!(complete.text().matches("<PartNumber>").count(), 4);
+
+    // the aggregate progress only goes up and reaches the size of the file
+    let progress = progress.lock().unwrap();
+    assert!(progress.windows(2).all(|pair| pair[0].1 < pair[1].1));
+    assert_eq!(progress.last(), Some(&(4 * MIN_PART_SIZE, 4 * MIN_PART_SIZE)));
+    assert!(mock.requests_for("AbortMultipartUpload").is_empty());
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{error, recorded_callback, with_headers, Calls, MockS3, Request};
+use sha2::{Digest, Sha256};
+use trackable_s3_stream::{
+    put_object_if_changed, put_object_with_checksum_tag, ConditionalUpload, TrackableBodyStream, TransferError, METADATA_SHA256_KEY,
+};
+
+const DATA: &[u8] = b"the same bytes as the object already in the bucket";
+
+fn sha256(data: &[u8]) -> String {
+    STANDARD.encode(Sha256::digest(data))
+}
+
+/// Returns a body over `DATA` that records the arguments of every callback
+fn recorded_body() -> (TrackableBodyStream<std::io::Cursor<&'static [u8]>>, Calls) {
+    let (callback, calls) = recorded_callback();
+    (TrackableBodyStream::new(std::io::Cursor::new(DATA), DATA.len() as u64).with_callback(callback), calls)
+}
+
+#[tokio::test]
+async fn skips_an_object_with_the_same_checksum() {
+    for header in ["x-amz-checksum-sha256".to_string(), format!("x-amz-meta-{}", METADATA_SHA256_KEY)] {
+        let mock = MockS3::new(move |request: &Request| match request.operation() {
+            "HeadObject" => with_headers(200, &[(header.as_str(), &sha256(DATA))]),
+            _ => request.respond(),
+        });
+        let (body, calls) = recorded_body();
+
+        let outcome = put_object_if_changed(&mock.client(), "bucket", "data.txt", body).await.unwrap();
+        assert!(matches!(outcome, ConditionalUpload::Skipped));
+        assert_eq!(mock.operations(), ["HeadObject"]);
+        let head = &mock.requests()[0];
+        assert_eq!(head.key(), "data.txt");
+        assert_eq!(head.header("x-amz-checksum-mode"), Some("ENABLED"));
+
+        // the whole source is reported as sent at once, with a final chunk of 0 bytes
+        let len = DATA.len() as u64;
+        assert_eq!(*calls.lock().unwrap(), [(len, len, 0)]);
+    }
+}
+
+#[tokio::test]
+async fn uploads_a_changed_object_with_its_checksum() {
+    let mock = MockS3::new(|request: &Request| match request.operation() {
+        "HeadObject" => with_headers(200, &[("x-amz-checksum-sha256", &sha256(b"old"))]),
+        _ => request.respond(),
+    });
+    let (body, calls) = recorded_body();
+
+    let outcome = put_object_if_changed(&mock.client(), "bucket", "data.txt", body).await.unwrap();
+    assert!(matches!(outcome, ConditionalUpload::Uploaded(_)));
+    assert_eq!(mock.operations(), ["HeadObject", "PutObject"]);
+    let put = &mock.requests()[1];
+    assert_eq!(put.body, DATA);
+    assert_eq!(put.header("x-amz-checksum-sha256"), Some(sha256(DATA).as_str()));
+    assert_eq!(put.header(&format!("x-amz-meta-{}", METADATA_SHA256_KEY)), Some(sha256(DATA).as_str()));
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.iter().map(|(_, _, chunk)| chunk).sum::<u64>(), DATA.len() as u64);
+    assert_eq!(calls.last().map(|(total, sent, _)| (*total, *sent)), Some((DATA.len() as u64, DATA.len() as u64)));
+}
+
+#[tokio::test]
+async fn uploads_a_missing_object() {
+    let mock = MockS3::succeeding();
+    let (body, _) = recorded_body();
+
+    let outcome = put_object_if_changed(&mock.client(), "bucket", "data.txt", body).await.unwrap();
+    let ConditionalUpload::Uploaded(output) = outcome else {
+        panic!("the object is missing, it should have been uploaded");
+    };
+    assert_eq!(output.e_tag(), Some(common::e_tag(DATA).as_str()));
+    assert_eq!(mock.operations(), ["HeadObject", "PutObject"]);
+    assert_eq!(mock.requests()[1].body, DATA);
+}
+
+#[tokio::test]
+async fn tags_the_uploaded_version_with_its_checksum() {
+    let mock = MockS3::new(|request: &Request| match request.operation() {
+        "PutObject" => with_headers(200, &[("ETag", &common::e_tag(&request.body)), ("x-amz-version-id", "version-1")]),
+        _ => request.respond(),
+    });
+    let (body, calls) = recorded_body();
+
+    let output = put_object_with_checksum_tag(&mock.client(), "bucket", "data.txt", body, "content-sha256").await.unwrap();
+    assert_eq!(output.version_id(), Some("version-1"));
+    assert_eq!(mock.operations(), ["PutObject", "PutObjectTagging"]);
+    let (put, tagging) = (&mock.requests()[0], &mock.requests()[1]);
+    assert_eq!(put.body, DATA);
+    assert_eq!(tagging.key(), "data.txt");
+    assert_eq!(tagging.query("versionId").as_deref(), Some("version-1"));
+    let tag = format!("<Tag><Key>content-sha256</Key><Value>{}</Value></Tag>", sha256(DATA));
+    assert!(tagging.text().contains(&tag), "{}", tagging.text());
+    assert_eq!(calls.lock().unwrap().last().map(|(_, sent, _)| *sent), Some(DATA.len() as u64));
+}
+
+#[tokio::test]
+async fn reports_a_failed_tagging_after_the_upload() {
+    let mock = MockS3::new(|request: &Request| match request.operation() {
+        "PutObjectTagging" => error(403, "AccessDenied"),
+        _ => request.respond(),
+    });
+    let (body, _) = recorded_body();
+
+    let err = put_object_with_checksum_tag(&mock.client(), "bucket", "data.txt", body, "content-sha256").await.unwrap_err();
+    assert!(matches!(err, TransferError::S3 { operation: "PutObjectTagging", .. }), "{}", err);
+    // the object has been uploaded and is left in place
+    assert_eq!(mock.operations(), ["PutObject", "PutObjectTagging"]);
+    assert_eq!(mock.requests()[0].body, DATA);
+}